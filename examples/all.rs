@@ -1,4 +1,4 @@
-use pgfx::{Engine, Texture, Key, Rect, Color, Point, Sound};
+use pgfx::{Engine, DrawParams, Texture, Key, Rect, Color, Point, Sound};
 
 use rand::Rng;
 
@@ -21,9 +21,9 @@ fn main() {
 
     let mut rotations = vec![0.0; rect_count];
 
-    let music = g.load_sound_file("res/music/sample.ogg");
+    let music = g.load_sound_file("res/music/sample.ogg").unwrap();
     let tex_bird = g.load_texture_file("res/textures/bird.png").unwrap();
-    let sound = g.load_sound_file("res/sounds/tweet.ogg");
+    let sound = g.load_sound_file("res/sounds/tweet.ogg").unwrap();
 
     // State
     let mut scroll_offset = 0.0;
@@ -70,12 +70,14 @@ fn main() {
             g.draw_rotated_rect(rects[i], colors[i], Point::new(rects[i].width / 2.0, rects[i].height / 2.0), rotations[i]);
         }
 
-        g.draw_rotated_texture(
+        g.draw_texture_ex(
             &tex_bird,
             Rect::new(0.0, 0.0, tex_bird.width, tex_bird.height),
             Rect::new(g.mouse.x, g.mouse.y, tex_bird.width * 4.0, tex_bird.height * 4.0),
-            Point::new(tex_bird.width * 2.0, tex_bird.height * 2.0),
-            rotation,
+            DrawParams::new()
+                .origin(Point::new(tex_bird.width * 2.0, tex_bird.height * 2.0))
+                .rotation(rotation)
+                .flip_x(g.mouse_right_down),
         );
         g.draw_text("Hello World!", 30.0, 30.0 + scroll_offset * 10.0, 20.0, Color::new(0, 0, 100));
     }
@@ -0,0 +1,204 @@
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use hound::{SampleFormat, WavSpec, WavWriter};
+use rodio::{OutputStream, OutputStreamHandle};
+use rodio::source::Source;
+
+use super::sound::SAMPLE_RATE;
+
+// Audio backends ============================================================
+
+/// Where the mixed audio goes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AudioBackend {
+    /// The default output device.
+    Device,
+    /// Consumes the mix in real time without playing it, for headless runs.
+    Null,
+    /// Like `Null`, but records the mix to a 16-bit stereo WAV file.
+    Wav(PathBuf),
+}
+
+impl AudioBackend {
+    // Reads PGFX_AUDIO, which can be "device", "null" or "wav:<path>"
+    pub(crate) fn from_env() -> Result<Option<Self>, String> {
+        let Ok(value) = std::env::var("PGFX_AUDIO") else { return Ok(None) };
+        match value.as_str() {
+            "device" => Ok(Some(AudioBackend::Device)),
+            "null" => Ok(Some(AudioBackend::Null)),
+            _ => match value.strip_prefix("wav:") {
+                Some(path) => Ok(Some(AudioBackend::Wav(PathBuf::from(path)))),
+                None => Err(format!("Unknown PGFX_AUDIO backend \"{}\"", value)),
+            },
+        }
+    }
+}
+
+pub(crate) struct AudioOutput {
+    _device: Option<(OutputStream, OutputStreamHandle)>,
+    frames: Arc<AtomicU64>,
+    opened: Instant,
+    // Nanoseconds after `opened` when the first frame was pulled, plus one
+    first_pull: Arc<AtomicU64>,
+    // Set by the output thread if writing the WAV file failed
+    error: Arc<Mutex<Option<String>>>,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl AudioOutput {
+    pub(crate) fn open(backend: &AudioBackend, source: impl Source<Item = f32> + Send + 'static) -> Result<Self, String> {
+        let frames = Arc::new(AtomicU64::new(0));
+        let opened = Instant::now();
+        let first_pull = Arc::new(AtomicU64::new(0));
+        let source = Clock {
+            source,
+            frames: Arc::clone(&frames),
+            opened,
+            first_pull: Arc::clone(&first_pull),
+            left: true,
+        };
+        let stop = Arc::new(AtomicBool::new(false));
+        let error = Arc::new(Mutex::new(None));
+        let mut output = Self {
+            _device: None,
+            frames,
+            opened,
+            first_pull,
+            error: Arc::clone(&error),
+            stop: Arc::clone(&stop),
+            thread: None,
+        };
+
+        match backend {
+            AudioBackend::Device => {
+                let (stream, handle) = OutputStream::try_default().map_err(|e| e.to_string())?;
+                handle.play_raw(source).map_err(|e| e.to_string())?;
+                output._device = Some((stream, handle));
+            }
+            AudioBackend::Null => output.thread = Some(spawn(source, None, stop, error)?),
+            AudioBackend::Wav(path) => {
+                let spec = WavSpec {
+                    channels: 2,
+                    sample_rate: SAMPLE_RATE,
+                    bits_per_sample: 16,
+                    sample_format: SampleFormat::Int,
+                };
+                let writer = WavWriter::create(path, spec).map_err(|e| format!("{}: {}", path.display(), e))?;
+                output.thread = Some(spawn(source, Some(writer), stop, error)?);
+            }
+        }
+        Ok(output)
+    }
+
+    // Frames consumed by the backend so far
+    pub(crate) fn frames(&self) -> u64 {
+        self.frames.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn error(&self) -> Option<String> {
+        self.error.lock().unwrap().clone()
+    }
+
+    // Backends pull audio ahead of playing it; estimate by how much the
+    // mixed audio is ahead of the time since the first pull
+    pub(crate) fn latency(&self) -> Duration {
+        let first_pull = self.first_pull.load(Ordering::Relaxed);
+        if first_pull == 0 {
+            return Duration::ZERO;
+        }
+        let playing = self.opened.elapsed().saturating_sub(Duration::from_nanos(first_pull - 1));
+        let mixed = Duration::from_secs_f64(self.frames() as f64 / SAMPLE_RATE as f64);
+        mixed.saturating_sub(playing)
+    }
+}
+
+impl Drop for AudioOutput {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+// Pulls the mix at the rate a device would, writing it to `writer` if any.
+// Write errors are stored in `error` and stop the recording.
+fn spawn(
+    mut source: impl Source<Item = f32> + Send + 'static,
+    mut writer: Option<WavWriter<BufWriter<File>>>,
+    stop: Arc<AtomicBool>,
+    error: Arc<Mutex<Option<String>>>,
+) -> Result<JoinHandle<()>, String> {
+    let fail = move |e: hound::Error| *error.lock().unwrap() = Some(format!("Failed to write audio: {}", e));
+    let run = move || {
+        let start = Instant::now();
+        let mut rendered = 0u64;
+        while !stop.load(Ordering::Relaxed) {
+            let due = (start.elapsed().as_secs_f64() * SAMPLE_RATE as f64) as u64;
+            for _ in rendered..due {
+                for _ in 0..2 {
+                    let sample = source.next().unwrap_or(0.0);
+                    if let Some(wav) = &mut writer {
+                        if let Err(e) = wav.write_sample((sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16) {
+                            fail(e);
+                            writer = None;
+                        }
+                    }
+                }
+            }
+            rendered = rendered.max(due);
+            thread::sleep(Duration::from_millis(5));
+        }
+        if let Some(wav) = writer {
+            if let Err(e) = wav.finalize() {
+                fail(e);
+            }
+        }
+    };
+    thread::Builder::new().name("pgfx audio".to_string()).spawn(run).map_err(|e| e.to_string())
+}
+
+// Counts the frames pulled from the mix, as the audio clock
+struct Clock<S> {
+    source: S,
+    frames: Arc<AtomicU64>,
+    opened: Instant,
+    first_pull: Arc<AtomicU64>,
+    left: bool,
+}
+
+impl<S: Source<Item = f32>> Iterator for Clock<S> {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        if self.left && self.frames.fetch_add(1, Ordering::Relaxed) == 0 {
+            self.first_pull.store(self.opened.elapsed().as_nanos() as u64 + 1, Ordering::Relaxed);
+        }
+        self.left = !self.left;
+        self.source.next()
+    }
+}
+
+impl<S: Source<Item = f32>> Source for Clock<S> {
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        2
+    }
+
+    fn sample_rate(&self) -> u32 {
+        SAMPLE_RATE
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
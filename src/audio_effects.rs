@@ -0,0 +1,441 @@
+use std::f32::consts::PI;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use rodio::source::Source;
+
+use super::sound::SAMPLE_RATE;
+
+// Audio effects ============================================================
+
+/// An effect in an `EffectChain`. There is no pitch effect: changing the
+/// pitch changes how fast a sound is played, so it is set per sound with
+/// `PlayOptions::pitch` or `SoundHandle::set_pitch` rather than on a bus.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum AudioEffect {
+    /// Removes frequencies above `cutoff` Hz, e.g. for muffled or underwater sound.
+    LowPass { cutoff: f32 },
+    /// Removes frequencies below `cutoff` Hz, e.g. for radio or telephone voices.
+    HighPass { cutoff: f32 },
+    /// Repeats the sound after `delay` seconds, each repeat `feedback` times
+    /// as loud as the previous one.
+    Echo { delay: f32, feedback: f32, mix: f32 },
+    /// `room_size` and `damping` are between 0 and 1.
+    Reverb { room_size: f32, damping: f32, mix: f32 },
+    /// Soft clipping that keeps full scale peaks at full scale. `drive` is at
+    /// least 1, which already saturates slightly, and higher values distort
+    /// more; a `mix` of 0 leaves the sound clean.
+    Distortion { drive: f32, mix: f32 },
+    /// Reduces the sound to `bits` of resolution, holding each sample for
+    /// `downsample` frames.
+    Bitcrush { bits: u32, downsample: u32 },
+}
+
+struct EffectShared {
+    effects: Mutex<Vec<AudioEffect>>,
+    version: AtomicU64,
+}
+
+/// A list of effects applied in order to a voice or a bus. Changes are
+/// picked up by the audio thread while playing; clones share the same list.
+#[derive(Clone)]
+pub struct EffectChain {
+    shared: Arc<EffectShared>,
+}
+
+impl Default for EffectChain {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EffectChain {
+    pub fn new() -> Self {
+        Self {
+            shared: Arc::new(EffectShared {
+                effects: Mutex::new(Vec::new()),
+                version: AtomicU64::new(0),
+            }),
+        }
+    }
+
+    pub fn with(self, effect: AudioEffect) -> Self {
+        self.push(effect);
+        self
+    }
+
+    /// Adds an effect at the end of the chain and returns its index.
+    pub fn push(&self, effect: AudioEffect) -> usize {
+        self.edit(|effects| {
+            effects.push(effect);
+            effects.len() - 1
+        })
+    }
+
+    /// Replaces the effect at `index`. Changing the parameters of an effect
+    /// keeps its state, so a filter sweep or a ringing echo doesn't click.
+    pub fn set(&self, index: usize, effect: AudioEffect) {
+        self.edit(|effects| {
+            if let Some(slot) = effects.get_mut(index) {
+                *slot = effect;
+            }
+        })
+    }
+
+    pub fn remove(&self, index: usize) {
+        self.edit(|effects| {
+            if index < effects.len() {
+                effects.remove(index);
+            }
+        })
+    }
+
+    pub fn clear(&self) {
+        self.edit(|effects| effects.clear())
+    }
+
+    pub fn effects(&self) -> Vec<AudioEffect> {
+        self.shared.effects.lock().unwrap().clone()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.shared.effects.lock().unwrap().is_empty()
+    }
+
+    fn edit<T>(&self, f: impl FnOnce(&mut Vec<AudioEffect>) -> T) -> T {
+        let result = f(&mut self.shared.effects.lock().unwrap());
+        self.shared.version.fetch_add(1, Ordering::Release);
+        result
+    }
+}
+
+// Effect source ============================================================
+
+// Applies a chain to a stereo source at the mixer rate.
+pub(crate) struct EffectSource<S> {
+    input: S,
+    chain: EffectChain,
+    version: u64,
+    states: Vec<EffectState>,
+    right: Option<f32>,
+}
+
+impl<S: Source<Item = f32>> EffectSource<S> {
+    pub(crate) fn new(input: S, chain: EffectChain) -> Self {
+        let mut source = Self {
+            input,
+            chain,
+            version: u64::MAX,
+            states: Vec::new(),
+            right: None,
+        };
+        source.sync();
+        source
+    }
+
+    fn sync(&mut self) {
+        let version = self.chain.shared.version.load(Ordering::Acquire);
+        if version == self.version {
+            return;
+        }
+        // Never block the audio thread; try again on the next frame
+        let Ok(effects) = self.chain.shared.effects.try_lock() else { return };
+        self.states.truncate(effects.len());
+        for (i, effect) in effects.iter().enumerate() {
+            match self.states.get_mut(i) {
+                Some(state) if state.accepts(effect) => state.configure(effect),
+                Some(state) => *state = EffectState::new(effect),
+                None => self.states.push(EffectState::new(effect)),
+            }
+        }
+        self.version = version;
+    }
+}
+
+impl<S: Source<Item = f32>> Iterator for EffectSource<S> {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        if let Some(right) = self.right.take() {
+            return Some(right);
+        }
+        let left = self.input.next()?;
+        let right = self.input.next().unwrap_or(left);
+        self.sync();
+
+        let mut frame = [left, right];
+        for state in &mut self.states {
+            frame = state.process(frame);
+        }
+        self.right = Some(frame[1]);
+        Some(frame[0])
+    }
+}
+
+impl<S: Source<Item = f32>> Source for EffectSource<S> {
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        2
+    }
+
+    fn sample_rate(&self) -> u32 {
+        SAMPLE_RATE
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+// Effect state ============================================================
+
+enum EffectState {
+    Filter(Biquad),
+    Echo(Echo),
+    Reverb(Box<Reverb>),
+    Distortion { drive: f32, mix: f32 },
+    Bitcrush(Bitcrush),
+}
+
+impl EffectState {
+    fn new(effect: &AudioEffect) -> Self {
+        let mut state = match effect {
+            AudioEffect::LowPass { .. } | AudioEffect::HighPass { .. } => EffectState::Filter(Biquad::default()),
+            AudioEffect::Echo { .. } => EffectState::Echo(Echo::default()),
+            AudioEffect::Reverb { .. } => EffectState::Reverb(Box::new(Reverb::new())),
+            AudioEffect::Distortion { .. } => EffectState::Distortion { drive: 1.0, mix: 1.0 },
+            AudioEffect::Bitcrush { .. } => EffectState::Bitcrush(Bitcrush::default()),
+        };
+        state.configure(effect);
+        state
+    }
+
+    fn accepts(&self, effect: &AudioEffect) -> bool {
+        matches!(
+            (self, effect),
+            (EffectState::Filter(_), AudioEffect::LowPass { .. } | AudioEffect::HighPass { .. })
+                | (EffectState::Echo(_), AudioEffect::Echo { .. })
+                | (EffectState::Reverb(_), AudioEffect::Reverb { .. })
+                | (EffectState::Distortion { .. }, AudioEffect::Distortion { .. })
+                | (EffectState::Bitcrush(_), AudioEffect::Bitcrush { .. })
+        )
+    }
+
+    fn configure(&mut self, effect: &AudioEffect) {
+        match (self, *effect) {
+            (EffectState::Filter(filter), AudioEffect::LowPass { cutoff }) => filter.configure(cutoff, false),
+            (EffectState::Filter(filter), AudioEffect::HighPass { cutoff }) => filter.configure(cutoff, true),
+            (EffectState::Echo(echo), AudioEffect::Echo { delay, feedback, mix }) => {
+                echo.resize((delay.clamp(0.001, 4.0) * SAMPLE_RATE as f32) as usize);
+                echo.feedback = feedback.clamp(0.0, 0.95);
+                echo.mix = mix.clamp(0.0, 1.0);
+            }
+            (EffectState::Reverb(reverb), AudioEffect::Reverb { room_size, damping, mix }) => {
+                reverb.feedback = 0.7 + 0.28 * room_size.clamp(0.0, 1.0);
+                reverb.damping = damping.clamp(0.0, 1.0) * 0.4;
+                reverb.mix = mix.clamp(0.0, 1.0);
+            }
+            (EffectState::Distortion { drive, mix }, AudioEffect::Distortion { drive: new_drive, mix: new_mix }) => {
+                *drive = new_drive.max(1.0);
+                *mix = new_mix.clamp(0.0, 1.0);
+            }
+            (EffectState::Bitcrush(crush), AudioEffect::Bitcrush { bits, downsample }) => {
+                crush.levels = (1u32 << (bits.clamp(1, 24) - 1)) as f32;
+                crush.downsample = downsample.max(1);
+            }
+            _ => {}
+        }
+    }
+
+    fn process(&mut self, frame: [f32; 2]) -> [f32; 2] {
+        match self {
+            EffectState::Filter(filter) => filter.process(frame),
+            EffectState::Echo(echo) => echo.process(frame),
+            EffectState::Reverb(reverb) => reverb.process(frame),
+            EffectState::Distortion { drive, mix } => {
+                let normalize = drive.tanh();
+                frame.map(|x| x + ((*drive * x).tanh() / normalize - x) * *mix)
+            }
+            EffectState::Bitcrush(crush) => crush.process(frame),
+        }
+    }
+}
+
+// Second order filter, from the Audio EQ Cookbook
+#[derive(Default)]
+struct Biquad {
+    b: [f32; 3],
+    a: [f32; 2],
+    z: [[f32; 2]; 2],
+}
+
+impl Biquad {
+    fn configure(&mut self, cutoff: f32, high_pass: bool) {
+        let cutoff = cutoff.clamp(10.0, SAMPLE_RATE as f32 * 0.45);
+        let w0 = 2.0 * PI * cutoff / SAMPLE_RATE as f32;
+        let (sin, cos) = w0.sin_cos();
+        let alpha = sin / (2.0 * std::f32::consts::FRAC_1_SQRT_2);
+        let a0 = 1.0 + alpha;
+        self.b = if high_pass {
+            [(1.0 + cos) / 2.0, -(1.0 + cos), (1.0 + cos) / 2.0]
+        } else {
+            [(1.0 - cos) / 2.0, 1.0 - cos, (1.0 - cos) / 2.0]
+        }
+        .map(|b| b / a0);
+        self.a = [-2.0 * cos / a0, (1.0 - alpha) / a0];
+    }
+
+    fn process(&mut self, frame: [f32; 2]) -> [f32; 2] {
+        let mut out = [0.0; 2];
+        for (c, z) in self.z.iter_mut().enumerate() {
+            let x = frame[c];
+            let y = self.b[0] * x + z[0];
+            z[0] = self.b[1] * x - self.a[0] * y + z[1];
+            z[1] = self.b[2] * x - self.a[1] * y;
+            out[c] = y;
+        }
+        out
+    }
+}
+
+#[derive(Default)]
+struct Echo {
+    buffer: Vec<[f32; 2]>,
+    position: usize,
+    feedback: f32,
+    mix: f32,
+}
+
+impl Echo {
+    fn resize(&mut self, frames: usize) {
+        self.buffer.resize(frames.max(1), [0.0; 2]);
+        if self.position >= self.buffer.len() {
+            self.position = 0;
+        }
+    }
+
+    fn process(&mut self, frame: [f32; 2]) -> [f32; 2] {
+        let delayed = self.buffer[self.position];
+        let mut out = [0.0; 2];
+        for c in 0..2 {
+            self.buffer[self.position][c] = frame[c] + delayed[c] * self.feedback;
+            out[c] = frame[c] + delayed[c] * self.mix;
+        }
+        self.position = (self.position + 1) % self.buffer.len();
+        out
+    }
+}
+
+// A small Freeverb: parallel damped combs followed by allpasses, with the
+// right channel's delays slightly longer for stereo width.
+const COMB_LENGTHS: [usize; 4] = [1116, 1188, 1277, 1356];
+const ALLPASS_LENGTHS: [usize; 2] = [556, 441];
+const STEREO_SPREAD: usize = 23;
+
+struct Delay {
+    buffer: Vec<f32>,
+    position: usize,
+    filtered: f32,
+}
+
+impl Delay {
+    fn new(length: usize) -> Self {
+        Self { buffer: vec![0.0; length], position: 0, filtered: 0.0 }
+    }
+
+    fn comb(&mut self, input: f32, feedback: f32, damping: f32) -> f32 {
+        let output = self.buffer[self.position];
+        self.filtered = output * (1.0 - damping) + self.filtered * damping;
+        self.buffer[self.position] = input + self.filtered * feedback;
+        self.position = (self.position + 1) % self.buffer.len();
+        output
+    }
+
+    fn allpass(&mut self, input: f32) -> f32 {
+        let delayed = self.buffer[self.position];
+        self.buffer[self.position] = input + delayed * 0.5;
+        self.position = (self.position + 1) % self.buffer.len();
+        delayed - input
+    }
+}
+
+struct Reverb {
+    combs: [Vec<Delay>; 2],
+    allpasses: [Vec<Delay>; 2],
+    feedback: f32,
+    damping: f32,
+    mix: f32,
+}
+
+impl Reverb {
+    fn new() -> Self {
+        let delays = |lengths: &[usize], spread: usize| lengths.iter().map(|&l| Delay::new(l + spread)).collect();
+        Self {
+            combs: [delays(&COMB_LENGTHS, 0), delays(&COMB_LENGTHS, STEREO_SPREAD)],
+            allpasses: [delays(&ALLPASS_LENGTHS, 0), delays(&ALLPASS_LENGTHS, STEREO_SPREAD)],
+            feedback: 0.84,
+            damping: 0.2,
+            mix: 0.3,
+        }
+    }
+
+    fn process(&mut self, frame: [f32; 2]) -> [f32; 2] {
+        let input = (frame[0] + frame[1]) * 0.015;
+        let mut out = [0.0; 2];
+        for c in 0..2 {
+            let mut wet: f32 = self.combs[c].iter_mut().map(|comb| comb.comb(input, self.feedback, self.damping)).sum();
+            for allpass in &mut self.allpasses[c] {
+                wet = allpass.allpass(wet);
+            }
+            out[c] = frame[c] * (1.0 - self.mix) + wet * 3.0 * self.mix;
+        }
+        out
+    }
+}
+
+#[derive(Default)]
+struct Bitcrush {
+    levels: f32,
+    downsample: u32,
+    held: [f32; 2],
+    counter: u32,
+}
+
+impl Bitcrush {
+    fn process(&mut self, frame: [f32; 2]) -> [f32; 2] {
+        if self.counter == 0 {
+            self.held = frame.map(|x| (x * self.levels).round() / self.levels);
+        }
+        self.counter = (self.counter + 1) % self.downsample;
+        self.held
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn distort(drive: f32, mix: f32, x: f32) -> f32 {
+        EffectState::new(&AudioEffect::Distortion { drive, mix }).process([x, -x])[0]
+    }
+
+    #[test]
+    fn distortion_keeps_full_scale() {
+        for drive in [1.0, 4.0, 20.0] {
+            assert!((distort(drive, 1.0, 1.0) - 1.0).abs() < 1e-6);
+            // Quieter samples are pushed up towards full scale
+            assert!(distort(drive, 1.0, 0.5) > 0.5);
+        }
+        assert!(distort(20.0, 1.0, 0.1) > distort(4.0, 1.0, 0.1));
+    }
+
+    #[test]
+    fn distortion_without_mix_is_clean() {
+        for x in [-1.0, -0.3, 0.0, 0.25, 0.9] {
+            assert_eq!(distort(10.0, 0.0, x), x);
+        }
+    }
+}
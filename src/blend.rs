@@ -0,0 +1,113 @@
+use gl::types::*;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum BlendFactor {
+    Zero,
+    One,
+    SrcColor,
+    OneMinusSrcColor,
+    DstColor,
+    OneMinusDstColor,
+    SrcAlpha,
+    OneMinusSrcAlpha,
+    DstAlpha,
+    OneMinusDstAlpha,
+}
+
+impl BlendFactor {
+    fn gl(self) -> GLenum {
+        match self {
+            BlendFactor::Zero => gl::ZERO,
+            BlendFactor::One => gl::ONE,
+            BlendFactor::SrcColor => gl::SRC_COLOR,
+            BlendFactor::OneMinusSrcColor => gl::ONE_MINUS_SRC_COLOR,
+            BlendFactor::DstColor => gl::DST_COLOR,
+            BlendFactor::OneMinusDstColor => gl::ONE_MINUS_DST_COLOR,
+            BlendFactor::SrcAlpha => gl::SRC_ALPHA,
+            BlendFactor::OneMinusSrcAlpha => gl::ONE_MINUS_SRC_ALPHA,
+            BlendFactor::DstAlpha => gl::DST_ALPHA,
+            BlendFactor::OneMinusDstAlpha => gl::ONE_MINUS_DST_ALPHA,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum BlendEquation {
+    Add,
+    Subtract,
+    ReverseSubtract,
+    Min,
+    Max,
+}
+
+impl BlendEquation {
+    fn gl(self) -> GLenum {
+        match self {
+            BlendEquation::Add => gl::FUNC_ADD,
+            BlendEquation::Subtract => gl::FUNC_SUBTRACT,
+            BlendEquation::ReverseSubtract => gl::FUNC_REVERSE_SUBTRACT,
+            BlendEquation::Min => gl::MIN,
+            BlendEquation::Max => gl::MAX,
+        }
+    }
+}
+
+/// How the output of a draw is combined with what is already in the framebuffer.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Default)]
+pub enum BlendMode {
+    /// Regular "over" compositing for straight (non-premultiplied) alpha.
+    #[default]
+    Alpha,
+    /// "Over" compositing for colors that are already multiplied by their alpha.
+    PremultipliedAlpha,
+    /// Adds the source on top of the destination; good for glows, sparks and lights.
+    Additive,
+    /// Multiplies the destination by the source; good for shadows and tinting overlays.
+    Multiply,
+    /// Inverse of multiply, brightens the destination.
+    Screen,
+    /// Subtracts the source from the destination.
+    Subtract,
+    /// Writes the source as-is, ignoring the destination.
+    Replace,
+    Custom {
+        src_color: BlendFactor,
+        dst_color: BlendFactor,
+        src_alpha: BlendFactor,
+        dst_alpha: BlendFactor,
+        color_equation: BlendEquation,
+        alpha_equation: BlendEquation,
+    },
+}
+
+impl BlendMode {
+    fn params(self) -> (BlendFactor, BlendFactor, BlendFactor, BlendFactor, BlendEquation, BlendEquation) {
+        use BlendFactor::*;
+        use BlendEquation::*;
+        match self {
+            BlendMode::Alpha => (SrcAlpha, OneMinusSrcAlpha, One, OneMinusSrcAlpha, Add, Add),
+            BlendMode::PremultipliedAlpha => (One, OneMinusSrcAlpha, One, OneMinusSrcAlpha, Add, Add),
+            BlendMode::Additive => (SrcAlpha, One, Zero, One, Add, Add),
+            BlendMode::Multiply => (DstColor, OneMinusSrcAlpha, Zero, One, Add, Add),
+            BlendMode::Screen => (One, OneMinusSrcColor, Zero, One, Add, Add),
+            BlendMode::Subtract => (SrcAlpha, One, Zero, One, ReverseSubtract, Add),
+            BlendMode::Replace => (One, Zero, One, Zero, Add, Add),
+            BlendMode::Custom { src_color, dst_color, src_alpha, dst_alpha, color_equation, alpha_equation } => {
+                (src_color, dst_color, src_alpha, dst_alpha, color_equation, alpha_equation)
+            }
+        }
+    }
+
+    pub(crate) fn apply(self) {
+        let (src_color, dst_color, src_alpha, dst_alpha, color_equation, alpha_equation) = self.params();
+        unsafe {
+            if self == BlendMode::Replace {
+                gl::Disable(gl::BLEND);
+            } else {
+                gl::Enable(gl::BLEND);
+            }
+            gl::BlendEquationSeparate(color_equation.gl(), alpha_equation.gl());
+            gl::BlendFuncSeparate(src_color.gl(), dst_color.gl(), src_alpha.gl(), dst_alpha.gl());
+        }
+    }
+}
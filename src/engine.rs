@@ -5,7 +5,9 @@ use sdl2::video::{GLProfile, Window, GLContext, SwapInterval};
 use sdl2::keyboard::Mod;
 use std::collections::HashMap;
 use std::rc::Rc;
+use std::cell::Cell;
 use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 
 use std::{ptr, mem};
 use gl::types::*;
@@ -13,10 +15,22 @@ use stb_image::{self, image::LoadResult};
 
 use rusttype::{point, Font, Scale, PositionedGlyph};
 
-use super::types::{Rect, Color, Point};
-use super::opengl::{create_program, debug_callback};
+use super::types::{Rect, Color, Point, Insets};
+use super::opengl::debug_callback;
 use super::imgui::Imgui;
-use super::sound::{SoundEngine, Sound};
+use super::audio_backend::AudioBackend;
+use super::audio_effects::EffectChain;
+use super::sound::{SoundEngine, Sound, SoundHandle, PlayOptions, Bus, Ducking, Playlist, SoundMode, SoundReport, SpatialConfig, AudioStream, MusicEvent};
+use super::blend::BlendMode;
+use super::image::{Image, HdrImage};
+use super::sprite::Animation;
+use super::tilemap::TileMap;
+use super::tiled::TiledMap;
+use super::particles::{Emitter, ParticleShape, ParticleSystem};
+use super::render_target::RenderTarget;
+use super::shader::{Shader, ShaderBinding};
+use super::post_process::PostProcess;
+use super::resources::{self, ResourceKind, ResourceReport};
 use std::collections::HashSet;
 
 pub type Scancode = sdl2::keyboard::Scancode;
@@ -31,12 +45,21 @@ pub trait App {
 
 pub struct AppBuilder<T: App> {
     title: String,
+    audio_backend: Option<AudioBackend>,
     _phantom: std::marker::PhantomData<T>,
 }
 
 impl<T: App> AppBuilder<T> {
+    /// Overrides the audio backend, e.g. to run headless tests.
+    pub fn audio_backend(self, backend: AudioBackend) -> Self {
+        Self { audio_backend: Some(backend), ..self }
+    }
+
     pub fn run(self) -> Result<(), String> {
         let mut engine = Engine::new(&self.title);
+        if let Some(backend) = self.audio_backend {
+            engine.sound = SoundEngine::with_backend(backend)?;
+        }
         let mut app = T::new(&mut engine);
         while engine.update() {
             app.update(&mut engine);
@@ -48,12 +71,37 @@ impl<T: App> AppBuilder<T> {
 pub fn app<T: App>(title: &str) -> AppBuilder<T> {
     AppBuilder {
         title: title.to_string(),
+        audio_backend: None,
         _phantom: std::marker::PhantomData,
     }
 }
 
 // Struct ============================================================
 
+#[derive(Copy, Clone)]
+struct Viewport {
+    framebuffer: u32,
+    width: f32,
+    height: f32,
+    // Render targets are drawn upside down so that their textures end up
+    // with the same row order as images loaded from disk.
+    flip_y: bool,
+}
+
+impl Viewport {
+    fn ndc_x(&self, x: f32) -> f32 {
+        x * 2.0 / self.width - 1.0
+    }
+
+    fn ndc_y(&self, y: f32) -> f32 {
+        if self.flip_y {
+            y * 2.0 / self.height - 1.0
+        } else {
+            1.0 - y * 2.0 / self.height
+        }
+    }
+}
+
 #[derive(PartialEq)]
 #[allow(dead_code)]
 enum DrawType {
@@ -70,9 +118,12 @@ pub struct Engine<'a> {
     _gl_ctx: GLContext,
 
     // OpenGL
-    program_2d: u32,
-    program_text: u32,
-    program_texture: u32,
+    program_2d: Shader,
+    program_text: Shader,
+    program_texture: Shader,
+    program_tilemap: Shader,
+    shader_hot_reload: Option<Instant>,
+    shader_errors: Vec<String>,
     tri_buffer: u32,
     text_buffer: u32,
     tri_vertices: Vec<f32>,
@@ -80,12 +131,21 @@ pub struct Engine<'a> {
     tex_vertices: Vec<f32>,
     text_entries: Vec<(u32, Vec<f32>)>,
     last_draw_type: DrawType,
+    blend_mode: BlendMode,
+    blend_mode_stack: Vec<BlendMode>,
+    render_target: Option<Viewport>,
+    scene_framebuffer: u32,
+    shader: Option<ShaderBinding>,
 
     // Window
     pub window_width: f32,
     pub window_height: f32,
     pub window_size_changed: bool,
 
+    // Timing
+    pub delta_time: f32,
+    last_frame: Instant,
+
     // Events
     pub has_events: bool,
     quit_requested: bool,
@@ -95,6 +155,7 @@ pub struct Engine<'a> {
     pub font_size: f32,
     pub font: Font<'a>,
     font_cache: HashMap<String, Rc<FontCacheEntry>>,
+    frame: u64,
 
     // Input
     pub mouse: Point,
@@ -121,6 +182,7 @@ pub struct Engine<'a> {
     // Subsystems
     pub sound: SoundEngine,
     pub ui: Imgui,
+    pub post_process: PostProcess,
 
     draw_ui_this_frame: bool,
     pub resource_path: PathBuf,
@@ -155,9 +217,10 @@ impl<'a> Engine<'a> {
             gl::Enable(gl::BLEND);
         }
 
-        let program_2d = create_program(include_str!("shaders/2d.vert"), include_str!("shaders/2d.frag"));
-        let program_text = create_program(include_str!("shaders/text.vert"), include_str!("shaders/text.frag"));
-        let program_texture = create_program(include_str!("shaders/texture.vert"), include_str!("shaders/texture.frag"));
+        let program_2d = Shader::new(include_str!("shaders/2d.vert"), include_str!("shaders/2d.frag")).unwrap();
+        let program_text = Shader::new(include_str!("shaders/text.vert"), include_str!("shaders/text.frag")).unwrap();
+        let program_texture = Shader::new(include_str!("shaders/texture.vert"), include_str!("shaders/texture.frag")).unwrap();
+        let program_tilemap = Shader::new(include_str!("shaders/tilemap.vert"), include_str!("shaders/texture.frag")).unwrap();
 
         let mut tri_buffer = 0;
         let mut text_buffer = 0;
@@ -174,6 +237,8 @@ impl<'a> Engine<'a> {
                 gl::DYNAMIC_DRAW,
             );
         }
+        resources::track(ResourceKind::Buffer, tri_buffer, 0, "triangle batch");
+        resources::track(ResourceKind::Buffer, text_buffer, 48 * mem::size_of::<GLfloat>(), "text batch");
 
         // Text
         let font = Font::try_from_vec(include_bytes!("../res/fonts/vera/Vera.ttf").to_vec()).unwrap();
@@ -184,6 +249,7 @@ impl<'a> Engine<'a> {
         // Subsystems
         let ui = Imgui::new(&window);
         let sound = SoundEngine::new();
+        let post_process = PostProcess::new();
 
         // Resources
         let resource_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
@@ -197,11 +263,17 @@ impl<'a> Engine<'a> {
             window_size_changed: false,
             font,
             font_cache: HashMap::new(),
+            frame: 0,
             window,
             _gl_ctx,
             program_2d,
             program_text,
             program_texture,
+            program_tilemap,
+            shader_hot_reload: None,
+            shader_errors: Vec::new(),
+            delta_time: 0.0,
+            last_frame: Instant::now(),
             has_events: true,
             quit_requested: false,
             mouse: Point::new(0.0, 0.0),
@@ -230,8 +302,14 @@ impl<'a> Engine<'a> {
             tex_vertices: Vec::new(),
             text_entries: Vec::new(),
             last_draw_type: DrawType::Any,
+            blend_mode: BlendMode::Alpha,
+            blend_mode_stack: Vec::new(),
+            render_target: None,
+            scene_framebuffer: 0,
+            shader: None,
             sound,
             ui,
+            post_process,
             draw_ui_this_frame: false,
             resource_path,
         }
@@ -241,11 +319,47 @@ impl<'a> Engine<'a> {
         self.window_size_changed = true;
         self.window_width = width;
         self.window_height = height;
+        if self.render_target.is_none() {
+            unsafe {
+                gl::Viewport(0, 0, width as i32, height as i32);
+            }
+        }
+    }
+
+    fn viewport(&self) -> Viewport {
+        self.render_target.unwrap_or(Viewport {
+            framebuffer: self.scene_framebuffer,
+            width: self.window_width,
+            height: self.window_height,
+            flip_y: self.scene_framebuffer != 0,
+        })
+    }
+
+    fn bind_viewport(&self) {
+        let viewport = self.viewport();
         unsafe {
-            gl::Viewport(0, 0, width as i32, height as i32);
+            gl::BindFramebuffer(gl::FRAMEBUFFER, viewport.framebuffer);
+            gl::Viewport(0, 0, viewport.width as i32, viewport.height as i32);
         }
     }
 
+    pub fn set_render_target(&mut self, target: &RenderTarget) {
+        self.flush();
+        self.render_target = Some(Viewport {
+            framebuffer: target.framebuffer(),
+            width: target.width(),
+            height: target.height(),
+            flip_y: true,
+        });
+        self.bind_viewport();
+    }
+
+    pub fn reset_render_target(&mut self) {
+        self.flush();
+        self.render_target = None;
+        self.bind_viewport();
+    }
+
     // pub fn set_active_region(&mut self, rect: Rect) {
     //     self.rect = rect;
     // }
@@ -278,6 +392,108 @@ impl<'a> Engine<'a> {
         }
         self.last_draw_type = target_type;
     }
+
+    pub fn blend_mode(&self) -> BlendMode {
+        self.blend_mode
+    }
+
+    pub fn set_blend_mode(&mut self, mode: BlendMode) {
+        if mode != self.blend_mode {
+            self.flush();
+            self.blend_mode = mode;
+        }
+    }
+
+    pub fn push_blend_mode(&mut self, mode: BlendMode) {
+        self.blend_mode_stack.push(self.blend_mode);
+        self.set_blend_mode(mode);
+    }
+
+    pub fn pop_blend_mode(&mut self) {
+        if let Some(mode) = self.blend_mode_stack.pop() {
+            self.set_blend_mode(mode);
+        }
+    }
+
+    pub fn with_blend_mode(&mut self, mode: BlendMode, f: impl FnOnce(&mut Self)) {
+        self.push_blend_mode(mode);
+        f(self);
+        self.pop_blend_mode();
+    }
+
+    pub fn with_shader(&mut self, shader: &Shader, f: impl FnOnce(&mut Self)) {
+        self.flush();
+        let previous = self.shader.replace(shader.binding());
+        f(self);
+        self.flush();
+        self.shader = previous;
+    }
+
+    /// Loads the built-in shaders from `dir` (using the same file names as
+    /// `src/shaders`) and recompiles them whenever they change on disk, along
+    /// with post-processing passes loaded with `Shader::from_files`. Compile
+    /// errors are collected in `take_shader_errors`.
+    pub fn enable_shader_hot_reload(&mut self, dir: impl AsRef<Path>) {
+        let dir = dir.as_ref();
+        let shaders = [
+            (&mut self.program_2d, "2d", "2d"),
+            (&mut self.program_text, "text", "text"),
+            (&mut self.program_texture, "texture", "texture"),
+            (&mut self.program_tilemap, "tilemap", "texture"),
+        ];
+        for (shader, vert, frag) in shaders {
+            let (vertex_path, fragment_path) = (dir.join(format!("{vert}.vert")), dir.join(format!("{frag}.frag")));
+            match Shader::from_files(&vertex_path, &fragment_path) {
+                Ok(loaded) => *shader = loaded,
+                Err(e) => {
+                    // Keep the embedded shader, and pick up the files once fixed
+                    shader.watch_files(&vertex_path, &fragment_path);
+                    self.shader_errors.push(e);
+                }
+            }
+        }
+        self.shader_hot_reload = Some(Instant::now());
+    }
+
+    pub fn disable_shader_hot_reload(&mut self) {
+        self.shader_hot_reload = None;
+    }
+
+    pub fn resource_report(&self) -> ResourceReport {
+        resources::report()
+    }
+
+    /// Shader compile errors, and post-processing buffers that failed to
+    /// be created.
+    pub fn take_shader_errors(&mut self) -> Vec<String> {
+        mem::take(&mut self.shader_errors)
+    }
+
+    fn poll_shader_hot_reload(&mut self) {
+        match self.shader_hot_reload {
+            Some(last_poll) if last_poll.elapsed() >= Duration::from_millis(250) => (),
+            _ => return,
+        }
+        self.shader_hot_reload = Some(Instant::now());
+        for shader in [&mut self.program_2d, &mut self.program_text, &mut self.program_texture, &mut self.program_tilemap] {
+            if let Err(e) = shader.reload_if_changed() {
+                self.shader_errors.push(e);
+            }
+        }
+        let errors = self.post_process.reload_changed_shaders();
+        self.shader_errors.extend(errors);
+    }
+
+    fn use_program(&self, default_program: u32) -> u32 {
+        let program = self.shader.as_ref().map_or(default_program, |shader| shader.program);
+        unsafe {
+            gl::UseProgram(program);
+        }
+        if let Some(shader) = &self.shader {
+            shader.apply_uniforms();
+        }
+        program
+    }
 }
 
 // Input ============================================================
@@ -287,14 +503,29 @@ impl<'a> Engine<'a> {
     pub fn update(&mut self) -> bool {
         let mut event_pump = self.sdl.event_pump().unwrap();
 
+        self.flush();
+
+        if self.scene_framebuffer != 0 {
+            self.post_process.apply(self.window_width, self.window_height);
+        }
+
         if self.draw_ui_this_frame {
             self.ui.render();
             self.draw_ui_this_frame = false;
         }
 
-        self.flush();
         self.window.gl_swap_window();
 
+        let now = Instant::now();
+        self.delta_time = (now - self.last_frame).as_secs_f32();
+        self.last_frame = now;
+
+        self.frame += 1;
+        let frame = self.frame;
+        self.font_cache.retain(|_, entry| frame - entry.last_used.get() <= FONT_CACHE_MAX_UNUSED_FRAMES);
+        resources::delete_released();
+        self.sound.update();
+
         // ========================================
 
         let mut should_quit = self.quit_requested;
@@ -414,6 +645,13 @@ impl<'a> Engine<'a> {
         }
         // TODO
 
+        self.poll_shader_hot_reload();
+        self.scene_framebuffer = self.post_process.begin_frame(self.window_width, self.window_height).unwrap_or_else(|e| {
+            self.shader_errors.push(e);
+            0
+        });
+        self.bind_viewport();
+
         !should_quit
     }
 
@@ -464,7 +702,7 @@ impl<'a> Engine<'a> {
 
 // Shapes ============================================================
 
-fn get_rect_vertices(rect: Rect, origin: Point, rotation: f32, window_width: f32, window_height: f32) -> [f32; 8] {
+fn get_rect_vertices(rect: Rect, origin: Point, rotation: f32, viewport: &Viewport) -> [f32; 8] {
     let x = rect.x;
     let y = rect.y;
     let width = rect.width;
@@ -497,14 +735,14 @@ fn get_rect_vertices(rect: Rect, origin: Point, rotation: f32, window_width: f32
     };
 
     [
-        x1 * 2.0 / window_width - 1.0,
-        x2 * 2.0 / window_width - 1.0,
-        x3 * 2.0 / window_width - 1.0,
-        x4 * 2.0 / window_width - 1.0,
-        1.0 - y1 * 2.0 / window_height,
-        1.0 - y2 * 2.0 / window_height,
-        1.0 - y3 * 2.0 / window_height,
-        1.0 - y4 * 2.0 / window_height,
+        viewport.ndc_x(x1),
+        viewport.ndc_x(x2),
+        viewport.ndc_x(x3),
+        viewport.ndc_x(x4),
+        viewport.ndc_y(y1),
+        viewport.ndc_y(y2),
+        viewport.ndc_y(y3),
+        viewport.ndc_y(y4),
     ]
 }
 
@@ -513,6 +751,7 @@ impl<'a> Engine<'a> {
     fn flush_triangles(&mut self) {
         let mut vao_2d = 0;
         unsafe {
+            self.blend_mode.apply();
             gl::Disable(gl::DEPTH_TEST);
 
             gl::GenVertexArrays(1, &mut vao_2d);
@@ -531,16 +770,17 @@ impl<'a> Engine<'a> {
                     self.tri_vertices.as_ptr() as *const _,
                     gl::DYNAMIC_DRAW
                 );
+                resources::set_bytes(ResourceKind::Buffer, self.tri_buffer, self.tri_vertices.len() * mem::size_of::<GLfloat>());
             }
 
             gl::BindVertexArray(vao_2d);
             let stride = 6 * mem::size_of::<GLfloat>() as GLsizei;
             gl::EnableVertexAttribArray(0);
             gl::VertexAttribPointer(0, 2, gl::FLOAT, gl::FALSE, stride, ptr::null());
-            gl::EnableVertexAttribArray(1);
-            gl::VertexAttribPointer(1, 4, gl::FLOAT, gl::FALSE, stride, (2 * mem::size_of::<GLfloat>()) as *const _);
+            gl::EnableVertexAttribArray(2);
+            gl::VertexAttribPointer(2, 4, gl::FLOAT, gl::FALSE, stride, (2 * mem::size_of::<GLfloat>()) as *const _);
 
-            gl::UseProgram(self.program_2d);
+            self.use_program(self.program_2d.program());
 
             gl::DrawArrays(gl::TRIANGLES, 0, self.tri_vertices.len() as GLsizei / 6);
 
@@ -556,7 +796,7 @@ impl<'a> Engine<'a> {
 
     pub fn draw_rotated_rect(&mut self, rect: Rect, color: Color, origin: Point, rotation: f32) {
 
-        let [x1, x2, x3, x4, y1, y2, y3, y4] = get_rect_vertices(rect, origin, rotation, self.window_width, self.window_height);
+        let [x1, x2, x3, x4, y1, y2, y3, y4] = get_rect_vertices(rect, origin, rotation, &self.viewport());
 
         self.tri_vertices.extend_from_slice(&[
             x1, y1, color.r as f32 / 255.0, color.g as f32 / 255.0, color.b as f32 / 255.0, color.a as f32 / 255.0,
@@ -573,63 +813,513 @@ impl<'a> Engine<'a> {
     pub fn draw_rect(&mut self, rect: Rect, color: Color) {
         self.draw_rotated_rect(rect, color, Point::new(0.0, 0.0), 0.0);
     }
+
+    pub fn draw_circle(&mut self, center: Point, radius: f32, color: Color) {
+        self.process_batch(DrawType::Triangles);
+        let viewport = self.viewport();
+        let [r, g, b, a] = [color.r, color.g, color.b, color.a].map(|c| c as f32 / 255.0);
+        let segments = (radius.sqrt() * 4.0).clamp(8.0, 64.0) as usize;
+        let point = |i: usize| {
+            let angle = i as f32 / segments as f32 * std::f32::consts::TAU;
+            [viewport.ndc_x(center.x + radius * angle.cos()), viewport.ndc_y(center.y + radius * angle.sin())]
+        };
+        let (cx, cy) = (viewport.ndc_x(center.x), viewport.ndc_y(center.y));
+        for i in 0..segments {
+            let ([x1, y1], [x2, y2]) = (point(i), point(i + 1));
+            self.tri_vertices.extend_from_slice(&[
+                cx, cy, r, g, b, a,
+                x1, y1, r, g, b, a,
+                x2, y2, r, g, b, a,
+            ]);
+        }
+    }
 }
 
 
 // Textures ============================================================
 
+// Not exposed by the core 3.3 bindings; from GL 4.6 / EXT_texture_filter_anisotropic.
+const TEXTURE_MAX_ANISOTROPY: GLenum = 0x84FE;
+const MAX_TEXTURE_MAX_ANISOTROPY: GLenum = 0x84FF;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FilterMode {
+    Nearest,
+    Linear,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum WrapMode {
+    Clamp,
+    Repeat,
+    MirroredRepeat,
+}
+
+impl WrapMode {
+    fn gl(self) -> GLint {
+        match self {
+            WrapMode::Clamp => gl::CLAMP_TO_EDGE as GLint,
+            WrapMode::Repeat => gl::REPEAT as GLint,
+            WrapMode::MirroredRepeat => gl::MIRRORED_REPEAT as GLint,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Sampling {
+    pub min_filter: FilterMode,
+    pub mag_filter: FilterMode,
+    /// When set, mipmaps are generated and `min_filter` is also used between
+    /// mip levels (so `Linear` gives trilinear filtering).
+    pub mipmaps: bool,
+    pub wrap_x: WrapMode,
+    pub wrap_y: WrapMode,
+    /// Clamped to what the driver supports; 1.0 disables anisotropic filtering.
+    pub anisotropy: f32,
+}
+
+impl Default for Sampling {
+    fn default() -> Self {
+        Self::nearest()
+    }
+}
+
+impl Sampling {
+
+    pub fn nearest() -> Self {
+        Self {
+            min_filter: FilterMode::Nearest,
+            mag_filter: FilterMode::Nearest,
+            mipmaps: false,
+            wrap_x: WrapMode::Clamp,
+            wrap_y: WrapMode::Clamp,
+            anisotropy: 1.0,
+        }
+    }
+
+    pub fn linear() -> Self {
+        Self {
+            min_filter: FilterMode::Linear,
+            mag_filter: FilterMode::Linear,
+            ..Self::nearest()
+        }
+    }
+
+    pub fn trilinear() -> Self {
+        Self {
+            mipmaps: true,
+            ..Self::linear()
+        }
+    }
+
+    pub fn with_wrap(self, wrap: WrapMode) -> Self {
+        Self {
+            wrap_x: wrap,
+            wrap_y: wrap,
+            ..self
+        }
+    }
+
+    pub fn with_anisotropy(self, anisotropy: f32) -> Self {
+        Self {
+            anisotropy,
+            ..self
+        }
+    }
+
+    // Applies to the texture bound to TEXTURE_2D.
+    fn apply(&self) {
+        let min_filter = match (self.min_filter, self.mipmaps) {
+            (FilterMode::Nearest, false) => gl::NEAREST,
+            (FilterMode::Linear, false) => gl::LINEAR,
+            (FilterMode::Nearest, true) => gl::NEAREST_MIPMAP_NEAREST,
+            (FilterMode::Linear, true) => gl::LINEAR_MIPMAP_LINEAR,
+        };
+        let mag_filter = match self.mag_filter {
+            FilterMode::Nearest => gl::NEAREST,
+            FilterMode::Linear => gl::LINEAR,
+        };
+        unsafe {
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, min_filter as GLint);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, mag_filter as GLint);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, self.wrap_x.gl());
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, self.wrap_y.gl());
+            if self.mipmaps {
+                gl::GenerateMipmap(gl::TEXTURE_2D);
+            }
+            // Always set when supported, so going back to 1.0 turns it off again
+            let mut max_anisotropy = 0.0;
+            gl::GetFloatv(MAX_TEXTURE_MAX_ANISOTROPY, &mut max_anisotropy);
+            if max_anisotropy >= 1.0 {
+                gl::TexParameterf(gl::TEXTURE_2D, TEXTURE_MAX_ANISOTROPY, self.anisotropy.clamp(1.0, max_anisotropy));
+            }
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TextureFormat {
+    /// Single channel, drawn as grayscale.
+    R8,
+    /// Two channels, drawn as grayscale + alpha.
+    RG8,
+    RGB8,
+    RGBA8,
+    /// Half float RGBA; pixel data is given as raw little-endian `f16` bytes.
+    RGBA16F,
+    /// 8-bit RGBA stored in BGRA order, as produced by many video decoders.
+    BGRA8,
+}
+
+impl TextureFormat {
+
+    pub fn bytes_per_pixel(self) -> usize {
+        match self {
+            TextureFormat::R8 => 1,
+            TextureFormat::RG8 => 2,
+            TextureFormat::RGB8 => 3,
+            TextureFormat::RGBA8 | TextureFormat::BGRA8 => 4,
+            TextureFormat::RGBA16F => 8,
+        }
+    }
+
+    // (internal format, pixel format, pixel type)
+    fn gl(self) -> (GLenum, GLenum, GLenum) {
+        match self {
+            TextureFormat::R8 => (gl::R8, gl::RED, gl::UNSIGNED_BYTE),
+            TextureFormat::RG8 => (gl::RG8, gl::RG, gl::UNSIGNED_BYTE),
+            TextureFormat::RGB8 => (gl::RGB8, gl::RGB, gl::UNSIGNED_BYTE),
+            TextureFormat::RGBA8 => (gl::RGBA8, gl::RGBA, gl::UNSIGNED_BYTE),
+            TextureFormat::RGBA16F => (gl::RGBA16F, gl::RGBA, gl::HALF_FLOAT),
+            TextureFormat::BGRA8 => (gl::RGBA8, gl::BGRA, gl::UNSIGNED_BYTE),
+        }
+    }
+
+    fn swizzle(self) -> [GLenum; 4] {
+        match self {
+            TextureFormat::R8 => [gl::RED, gl::RED, gl::RED, gl::ONE],
+            TextureFormat::RG8 => [gl::RED, gl::RED, gl::RED, gl::GREEN],
+            _ => [gl::RED, gl::GREEN, gl::BLUE, gl::ALPHA],
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum NineSliceMode {
+    Stretch,
+    Tile,
+}
+
+/// Optional parameters for `Engine::draw_texture_ex`.
+///
+/// `origin` is the point of the destination rectangle (relative to its top
+/// left corner, before scaling) that is placed at the rectangle's `x`/`y`
+/// and that rotation and scaling happen around.
+#[derive(Copy, Clone)]
+pub struct DrawParams {
+    pub tint: Color,
+    pub alpha: f32,
+    pub flip_x: bool,
+    pub flip_y: bool,
+    pub origin: Point,
+    pub rotation: f32,
+    pub scale: Point,
+}
+
+impl Default for DrawParams {
+    fn default() -> Self {
+        Self {
+            tint: Color::WHITE,
+            alpha: 1.0,
+            flip_x: false,
+            flip_y: false,
+            origin: Point::ZERO,
+            rotation: 0.0,
+            scale: Point::new(1.0, 1.0),
+        }
+    }
+}
+
+impl DrawParams {
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn tint(self, tint: Color) -> Self {
+        Self { tint, ..self }
+    }
+
+    pub fn alpha(self, alpha: f32) -> Self {
+        Self { alpha, ..self }
+    }
+
+    pub fn flip_x(self, flip_x: bool) -> Self {
+        Self { flip_x, ..self }
+    }
+
+    pub fn flip_y(self, flip_y: bool) -> Self {
+        Self { flip_y, ..self }
+    }
+
+    pub fn origin(self, origin: Point) -> Self {
+        Self { origin, ..self }
+    }
+
+    pub fn rotation(self, rotation: f32) -> Self {
+        Self { rotation, ..self }
+    }
+
+    pub fn scale(self, x: f32, y: f32) -> Self {
+        Self { scale: Point::new(x, y), ..self }
+    }
+}
+
 pub struct Texture {
     pub width: f32,
     pub height: f32,
+    /// CPU copy of the pixels, in `format`. Kept in sync by `update` and
+    /// `resize`; empty if the texture was created without data or discarded.
     pub data: Vec<u8>,
-    texture_id: u32,
+    pub(crate) texture_id: u32,
+    format: TextureFormat,
+    sampling: Sampling,
 }
 
 impl Texture {
 
     pub fn new(width: usize, height: usize, data: Vec<u8>) -> Self {
+        Self::with_sampling(width, height, data, Sampling::default())
+    }
+
+    pub fn with_sampling(width: usize, height: usize, data: Vec<u8>, sampling: Sampling) -> Self {
+        Self::create(width, height, TextureFormat::RGBA8, data, sampling)
+    }
+
+    pub fn with_format(width: usize, height: usize, format: TextureFormat, data: Vec<u8>) -> Result<Self, String> {
+        if !data.is_empty() && data.len() != width * height * format.bytes_per_pixel() {
+            return Err(format!(
+                "Expected {} bytes of {:?} data for a {}x{} texture, got {}",
+                width * height * format.bytes_per_pixel(), format, width, height, data.len(),
+            ));
+        }
+        Ok(Self::create(width, height, format, data, Sampling::default()))
+    }
+
+    fn create(width: usize, height: usize, format: TextureFormat, data: Vec<u8>, sampling: Sampling) -> Self {
         let texture_id = unsafe {
             let mut texture_id: u32 = 0;
             gl::ActiveTexture(gl::TEXTURE0);
             gl::GenTextures(1, &mut texture_id);
             gl::BindTexture(gl::TEXTURE_2D, texture_id);
+            let swizzle = format.swizzle().map(|c| c as GLint);
+            gl::TexParameteriv(gl::TEXTURE_2D, gl::TEXTURE_SWIZZLE_RGBA, swizzle.as_ptr());
+            texture_id
+        };
 
+        let mut texture = Texture {
+            width: width as f32,
+            height: height as f32,
+            data,
+            texture_id,
+            format,
+            sampling,
+        };
+        texture.upload();
+        sampling.apply();
+        resources::track(ResourceKind::Texture, texture_id, texture.gpu_bytes(), format!("texture {}x{}", width, height));
+        texture
+    }
+
+    // (Re)allocates the GPU storage from `data`, leaving the texture bound.
+    fn upload(&mut self) {
+        let (internal_format, pixel_format, pixel_type) = self.format.gl();
+        unsafe {
+            gl::BindTexture(gl::TEXTURE_2D, self.texture_id);
+            gl::PixelStorei(gl::UNPACK_ALIGNMENT, 1);
             gl::TexImage2D(
                 gl::TEXTURE_2D,
                 0,
-                gl::RGBA as GLint,
+                internal_format as GLint,
+                self.width as GLint,
+                self.height as GLint,
+                0,
+                pixel_format,
+                pixel_type,
+                if self.data.is_empty() { ptr::null() } else { self.data.as_ptr() as *const _ },
+            );
+        }
+    }
+
+    pub fn format(&self) -> TextureFormat {
+        self.format
+    }
+
+    /// Replaces the pixels inside `rect` (in texels) with `pixels`, given
+    /// row by row from the top in the texture's format.
+    pub fn update(&mut self, rect: Rect, pixels: &[u8]) -> Result<(), String> {
+        let (x, y) = (rect.x as usize, rect.y as usize);
+        let (width, height) = (rect.width as usize, rect.height as usize);
+        if x + width > self.width as usize || y + height > self.height as usize {
+            return Err(format!("Update region {:?} is outside of the {}x{} texture", rect, self.width, self.height));
+        }
+        let bpp = self.format.bytes_per_pixel();
+        if pixels.len() != width * height * bpp {
+            return Err(format!("Expected {} bytes for a {}x{} update, got {}", width * height * bpp, width, height, pixels.len()));
+        }
+
+        let (_, pixel_format, pixel_type) = self.format.gl();
+        unsafe {
+            gl::BindTexture(gl::TEXTURE_2D, self.texture_id);
+            gl::PixelStorei(gl::UNPACK_ALIGNMENT, 1);
+            gl::TexSubImage2D(
+                gl::TEXTURE_2D,
+                0,
+                x as GLint,
+                y as GLint,
                 width as GLint,
                 height as GLint,
-                0,
-                gl::RGBA,
-                gl::UNSIGNED_BYTE,
-                data.as_ptr() as *const _,
+                pixel_format,
+                pixel_type,
+                pixels.as_ptr() as *const _,
             );
+            if self.sampling.mipmaps {
+                gl::GenerateMipmap(gl::TEXTURE_2D);
+            }
+        }
 
-            texture_id
-        };
+        if !self.data.is_empty() {
+            let stride = self.width as usize * bpp;
+            for row in 0..height {
+                let start = (y + row) * stride + x * bpp;
+                self.data[start..start + width * bpp].copy_from_slice(&pixels[row * width * bpp..(row + 1) * width * bpp]);
+            }
+        }
+        Ok(())
+    }
 
-        Texture {
-            width: width as f32,
-            height: height as f32,
-            data,
-            texture_id,
+    /// Reads the pixels back from the GPU, row by row from the top in the
+    /// texture's format.
+    pub fn read_pixels(&self) -> Vec<u8> {
+        let mut pixels = vec![0; self.width as usize * self.height as usize * self.format.bytes_per_pixel()];
+        let (_, pixel_format, pixel_type) = self.format.gl();
+        unsafe {
+            gl::BindTexture(gl::TEXTURE_2D, self.texture_id);
+            gl::PixelStorei(gl::PACK_ALIGNMENT, 1);
+            gl::GetTexImage(gl::TEXTURE_2D, 0, pixel_format, pixel_type, pixels.as_mut_ptr() as *mut _);
         }
+        pixels
     }
 
-    pub fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
-        match stb_image::image::load_from_memory(bytes) {
-            LoadResult::ImageU8(image) => Ok(Self::new(image.width, image.height, image.data)),
-            _ => Err("Failed to load texture".to_string()),
+    /// Reallocates the texture at a new size. The previous contents are lost;
+    /// `data` may be empty to leave the new contents undefined.
+    pub fn resize(&mut self, width: usize, height: usize, data: Vec<u8>) -> Result<(), String> {
+        if !data.is_empty() && data.len() != width * height * self.format.bytes_per_pixel() {
+            return Err(format!("Expected {} bytes for a {}x{} texture, got {}", width * height * self.format.bytes_per_pixel(), width, height, data.len()));
+        }
+        self.width = width as f32;
+        self.height = height as f32;
+        self.data = data;
+        self.upload();
+        self.sampling.apply();
+        resources::set_bytes(ResourceKind::Texture, self.texture_id, self.gpu_bytes());
+        Ok(())
+    }
+
+    pub fn set_label(&self, label: &str) {
+        resources::set_label(ResourceKind::Texture, self.texture_id, label);
+    }
+
+    /// Frees the CPU copy of the pixels; the texture stays on the GPU.
+    pub fn discard_data(&mut self) {
+        self.data = Vec::new();
+    }
+
+    pub fn without_data(mut self) -> Self {
+        self.discard_data();
+        self
+    }
+
+    fn gpu_bytes(&self) -> usize {
+        let bytes = self.width as usize * self.height as usize * self.format.bytes_per_pixel();
+        if self.sampling.mipmaps { bytes * 4 / 3 } else { bytes }
+    }
+
+    pub fn sampling(&self) -> Sampling {
+        self.sampling
+    }
+
+    pub fn set_sampling(&mut self, sampling: Sampling) {
+        unsafe {
+            gl::BindTexture(gl::TEXTURE_2D, self.texture_id);
+        }
+        sampling.apply();
+        self.sampling = sampling;
+        resources::set_bytes(ResourceKind::Texture, self.texture_id, self.gpu_bytes());
+    }
+
+    pub fn set_filter(&mut self, filter: FilterMode) {
+        self.set_sampling(Sampling {
+            min_filter: filter,
+            mag_filter: filter,
+            ..self.sampling
+        });
+    }
+
+    pub fn set_wrap(&mut self, wrap: WrapMode) {
+        self.set_sampling(self.sampling.with_wrap(wrap));
+    }
+
+    fn from_load_result(result: LoadResult) -> Result<Self, String> {
+        match result {
+            LoadResult::ImageU8(image) => {
+                let format = match image.depth {
+                    1 => TextureFormat::R8,
+                    2 => TextureFormat::RG8,
+                    3 => TextureFormat::RGB8,
+                    _ => TextureFormat::RGBA8,
+                };
+                Self::with_format(image.width, image.height, format, image.data)
+            }
+            LoadResult::ImageF32(_) => Ok(Self::from_hdr_image(&HdrImage::from_load_result(result)?)),
+            LoadResult::Error(_) => Err("Failed to load texture".to_string()),
         }
     }
 
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        Self::from_load_result(stb_image::image::load_from_memory(bytes))
+    }
+
     pub fn from_file(path: impl AsRef<Path>) -> Result<Self, String> {
-        match stb_image::image::load(path) {
-            LoadResult::ImageU8(image) => Ok(Self::new(image.width, image.height, image.data)),
-            _ => Err("Failed to load texture".to_string()),
+        Self::from_load_result(stb_image::image::load(path))
+    }
+
+    pub fn from_image(image: &Image) -> Self {
+        Self::new(image.width, image.height, image.data.clone())
+    }
+
+    // A texture with no GL object behind it, for tests of CPU-side code
+    #[cfg(test)]
+    pub(crate) fn headless(width: f32, height: f32) -> Self {
+        Texture {
+            width,
+            height,
+            data: Vec::new(),
+            texture_id: 0,
+            format: TextureFormat::RGBA8,
+            sampling: Sampling::default(),
         }
     }
+
+    pub fn from_hdr_image(image: &HdrImage) -> Self {
+        Self::create(image.width, image.height, TextureFormat::RGBA16F, image.to_rgba16f(), Sampling::default())
+    }
+}
+
+impl Drop for Texture {
+    fn drop(&mut self) {
+        resources::release(ResourceKind::Texture, self.texture_id);
+    }
 }
 
 impl<'a> Engine<'a> {
@@ -647,44 +1337,153 @@ impl<'a> Engine<'a> {
         Texture::from_file(&self.res_path(path))
     }
 
-    pub fn load_sound(&mut self, bytes: &[u8]) -> Sound {
+    pub fn load_tiled_map(&self, path: impl AsRef<Path>) -> Result<TiledMap, String> {
+        TiledMap::from_file(self.res_path(path))
+    }
+
+    pub fn load_sound(&mut self, bytes: &[u8]) -> Result<Sound, String> {
         Sound::from_bytes(bytes)
     }
 
-    pub fn load_sound_file(&mut self, path: impl AsRef<Path>) -> Sound {
+    pub fn load_sound_file(&mut self, path: impl AsRef<Path>) -> Result<Sound, String> {
         Sound::from_file(path)
     }
 
-    pub fn play_sound(&mut self, sound: &Sound) {
+    pub fn load_sound_file_with(&mut self, path: impl AsRef<Path>, mode: SoundMode) -> Result<Sound, String> {
+        Sound::from_file_with(path, mode)
+    }
+
+    pub fn sound_report(&self) -> SoundReport {
+        self.sound.memory_report()
+    }
+
+    pub fn play_sound(&mut self, sound: &Sound) -> SoundHandle {
         self.sound.play(sound)
     }
 
+    pub fn play_sound_with(&mut self, sound: &Sound, options: PlayOptions) -> SoundHandle {
+        self.sound.play_with(sound, options)
+    }
+
+    pub fn play_sound_with_effects(&mut self, sound: &Sound, options: PlayOptions, effects: &EffectChain) -> SoundHandle {
+        self.sound.play_with_effects(sound, options, effects)
+    }
+
+    pub fn play_stream(&mut self, channels: u16, sample_rate: u32, options: PlayOptions) -> AudioStream {
+        self.sound.play_stream(channels, sample_rate, options)
+    }
+
+    pub fn play_sound_at(&mut self, sound: &Sound, position: Point) -> SoundHandle {
+        self.sound.play_at(sound, position)
+    }
+
+    pub fn set_listener(&mut self, listener: Point) {
+        self.sound.set_listener(listener)
+    }
+
+    /// Puts the listener at the center of the view, for a `camera` at its
+    /// top left as in `draw_tilemap`.
+    pub fn set_listener_camera(&mut self, camera: Point) {
+        let viewport = self.viewport();
+        self.sound.set_listener(camera + Point::new(viewport.width / 2.0, viewport.height / 2.0))
+    }
+
+    pub fn set_spatial_config(&mut self, config: SpatialConfig) {
+        self.sound.set_spatial_config(config)
+    }
+
     pub fn play_music(&mut self, sound: &Sound) {
         self.sound.play_music(sound)
     }
 
+    pub fn play_music_with(&mut self, sound: &Sound, fade_in: f32, crossfade: bool) {
+        self.sound.play_music_with(sound, fade_in, crossfade)
+    }
+
+    pub fn stop_music(&mut self, fade_out: f32) {
+        self.sound.stop_music(fade_out)
+    }
+
+    pub fn play_playlist(&mut self, playlist: Playlist) {
+        self.sound.play_playlist(playlist)
+    }
+
+    pub fn next_track(&mut self, crossfade: f32) {
+        self.sound.next_track(crossfade)
+    }
+
     pub fn pause_music(&mut self) {
         self.sound.pause_music()
     }
 
+    pub fn music_position(&self) -> Option<f64> {
+        self.sound.music_position()
+    }
+
+    pub fn seek_music(&mut self, seconds: f64) -> Result<(), String> {
+        self.sound.seek_music(seconds)
+    }
+
+    pub fn set_music_markers(&mut self, markers: &[(f64, &str)]) {
+        self.sound.set_music_markers(markers)
+    }
+
+    pub fn set_music_tempo(&mut self, bpm: f64, offset: f64) {
+        self.sound.set_music_tempo(bpm, offset)
+    }
+
+    pub fn music_beat(&self) -> Option<f64> {
+        self.sound.music_beat()
+    }
+
+    pub fn music_events(&self) -> &[MusicEvent] {
+        self.sound.music_events()
+    }
+
+    pub fn audio_latency(&self) -> Duration {
+        self.sound.latency()
+    }
+
     pub fn resume_music(&mut self) {
         self.sound.resume_music()
     }
 
+    pub fn set_bus_volume(&mut self, bus: Bus, volume: f32) {
+        self.sound.set_bus_volume(bus, volume)
+    }
+
+    pub fn bus_volume(&self, bus: Bus) -> f32 {
+        self.sound.bus_volume(bus)
+    }
+
+    pub fn set_bus_muted(&mut self, bus: Bus, muted: bool) {
+        self.sound.set_bus_muted(bus, muted)
+    }
+
+    pub fn is_bus_muted(&self, bus: Bus) -> bool {
+        self.sound.is_bus_muted(bus)
+    }
+
+    pub fn bus_effects(&self, bus: Bus) -> &EffectChain {
+        self.sound.bus_effects(bus)
+    }
+
+    pub fn set_master_volume(&mut self, volume: f32) {
+        self.sound.set_bus_volume(Bus::Master, volume)
+    }
+
+    pub fn set_ducking(&mut self, ducking: Option<Ducking>) {
+        self.sound.set_ducking(ducking)
+    }
+
     pub fn flush_textures(&mut self, texture_id: u32) {
         let (mut vao, mut vbo) = (0, 0);
         unsafe {
             gl::ActiveTexture(gl::TEXTURE0);
             gl::BindTexture(gl::TEXTURE_2D, texture_id);
-            gl::BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
+            self.blend_mode.apply();
             gl::Disable(gl::DEPTH_TEST);
 
-            // TODO Decide what these should be.
-            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::NEAREST as GLint);
-            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::NEAREST as GLint);
-            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as GLint);
-            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as GLint);
-
             gl::GenVertexArrays(1, &mut vao);
             gl::GenBuffers(1, &mut vbo);
             gl::BindBuffer(gl::ARRAY_BUFFER, vbo);
@@ -695,19 +1494,21 @@ impl<'a> Engine<'a> {
                 gl::STATIC_DRAW
             );
             gl::BindVertexArray(vao);
-            let stride = 4 * mem::size_of::<GLfloat>() as GLsizei;
+            let stride = 8 * mem::size_of::<GLfloat>() as GLsizei;
 
 
             gl::EnableVertexAttribArray(0);
             gl::VertexAttribPointer(0, 2, gl::FLOAT, gl::FALSE, stride, ptr::null());
             gl::EnableVertexAttribArray(1);
             gl::VertexAttribPointer(1, 2, gl::FLOAT, gl::FALSE, stride, (2 * mem::size_of::<GLfloat>()) as *const _);
+            gl::EnableVertexAttribArray(2);
+            gl::VertexAttribPointer(2, 4, gl::FLOAT, gl::FALSE, stride, (4 * mem::size_of::<GLfloat>()) as *const _);
 
-            let uniform = gl::GetUniformLocation(self.program_texture, b"tex\0".as_ptr() as *const _);
-            gl::UseProgram(self.program_texture);
+            let program = self.use_program(self.program_texture.program());
+            let uniform = gl::GetUniformLocation(program, b"tex\0".as_ptr() as *const _);
             gl::Uniform1i(uniform, 0);
 
-            gl::DrawArrays(gl::TRIANGLES, 0, self.tex_vertices.len() as GLsizei / 4);
+            gl::DrawArrays(gl::TRIANGLES, 0, self.tex_vertices.len() as GLsizei / 8);
 
             gl::BindBuffer(gl::ARRAY_BUFFER, 0);
             gl::BindVertexArray(0);
@@ -720,38 +1521,203 @@ impl<'a> Engine<'a> {
         self.tex_vertices.clear();
     }
 
+    #[deprecated(note = "use `draw_texture_ex` with `DrawParams`")]
     pub fn draw_rotated_texture(&mut self, texture: &Texture, src_rect: Rect, dest_rect: Rect, origin: Point, rotation: f32) {
+        self.draw_texture_ex(texture, src_rect, dest_rect, DrawParams::new().origin(origin).rotation(rotation));
+    }
+
+    pub fn draw_texture_ex(&mut self, texture: &Texture, src_rect: Rect, dest_rect: Rect, params: DrawParams) {
         self.process_batch(DrawType::Textures(texture.texture_id));
 
-        let [x1, x2, x3, x4, y1, y2, y3, y4] = get_rect_vertices(dest_rect, origin, rotation, self.window_width, self.window_height);
+        let dest_rect = Rect::new(dest_rect.x, dest_rect.y, dest_rect.width * params.scale.x, dest_rect.height * params.scale.y);
+        let origin = Point::new(params.origin.x * params.scale.x, params.origin.y * params.scale.y);
+        let [x1, x2, x3, x4, y1, y2, y3, y4] = get_rect_vertices(dest_rect, origin, params.rotation, &self.viewport());
 
-        let u0 = src_rect.x / texture.width;
-        let u1 = (src_rect.x + src_rect.width) / texture.width;
-        let v0 = (src_rect.y + src_rect.height) / texture.height;
-        let v1 = src_rect.y / texture.height;
+        let mut u0 = src_rect.x / texture.width;
+        let mut u1 = (src_rect.x + src_rect.width) / texture.width;
+        let mut v0 = (src_rect.y + src_rect.height) / texture.height;
+        let mut v1 = src_rect.y / texture.height;
+        if params.flip_x {
+            mem::swap(&mut u0, &mut u1);
+        }
+        if params.flip_y {
+            mem::swap(&mut v0, &mut v1);
+        }
 
+        let r = params.tint.r as f32 / 255.0;
+        let g = params.tint.g as f32 / 255.0;
+        let b = params.tint.b as f32 / 255.0;
+        let a = params.tint.a as f32 / 255.0 * params.alpha;
         let new_vertices = [
-            x1, y1, u0, v1,
-            x2, y2, u1, v1,
-            x4, y4, u1, v0,
-            x1, y1, u0, v1,
-            x4, y4, u1, v0,
-            x3, y3, u0, v0,
+            x1, y1, u0, v1, r, g, b, a,
+            x2, y2, u1, v1, r, g, b, a,
+            x4, y4, u1, v0, r, g, b, a,
+            x1, y1, u0, v1, r, g, b, a,
+            x4, y4, u1, v0, r, g, b, a,
+            x3, y3, u0, v0, r, g, b, a,
         ];
         self.tex_vertices.extend_from_slice(&new_vertices);
     }
 
     pub fn draw_texture(&mut self, texture: &Texture, src_rect: Rect, dest_rect: Rect) {
-        self.draw_rotated_texture(texture, src_rect, dest_rect, Point::new(0.0, 0.0), 0.0);
+        self.draw_texture_ex(texture, src_rect, dest_rect, DrawParams::default());
+    }
+
+    /// Draws `src_rect` scaled to `dest_rect` while keeping the corners given
+    /// by `insets` at their original size, stretching the edges and center.
+    pub fn draw_nine_slice(&mut self, texture: &Texture, src_rect: Rect, insets: Insets, dest_rect: Rect) {
+        self.draw_nine_slice_ex(texture, src_rect, insets, dest_rect, NineSliceMode::Stretch, Color::WHITE);
+    }
+
+    pub fn draw_nine_slice_ex(&mut self, texture: &Texture, src_rect: Rect, insets: Insets, dest_rect: Rect, mode: NineSliceMode, tint: Color) {
+        // Shrink the corners if the destination is too small to fit them
+        let scale_x = f32::min(1.0, dest_rect.width / (insets.left + insets.right).max(f32::EPSILON));
+        let scale_y = f32::min(1.0, dest_rect.height / (insets.top + insets.bottom).max(f32::EPSILON));
+
+        let src_x = [src_rect.x, src_rect.x + insets.left, src_rect.x + src_rect.width - insets.right, src_rect.x + src_rect.width];
+        let src_y = [src_rect.y, src_rect.y + insets.top, src_rect.y + src_rect.height - insets.bottom, src_rect.y + src_rect.height];
+        let dest_x = [
+            dest_rect.x,
+            dest_rect.x + insets.left * scale_x,
+            dest_rect.x + dest_rect.width - insets.right * scale_x,
+            dest_rect.x + dest_rect.width,
+        ];
+        let dest_y = [
+            dest_rect.y,
+            dest_rect.y + insets.top * scale_y,
+            dest_rect.y + dest_rect.height - insets.bottom * scale_y,
+            dest_rect.y + dest_rect.height,
+        ];
+
+        let params = DrawParams::new().tint(tint);
+        for row in 0..3 {
+            for col in 0..3 {
+                let src = Rect::new(src_x[col], src_y[row], src_x[col + 1] - src_x[col], src_y[row + 1] - src_y[row]);
+                let dest = Rect::new(dest_x[col], dest_y[row], dest_x[col + 1] - dest_x[col], dest_y[row + 1] - dest_y[row]);
+                if src.width <= 0.0 || src.height <= 0.0 || dest.width <= 0.0 || dest.height <= 0.0 {
+                    continue;
+                }
+                let tile_x = mode == NineSliceMode::Tile && col == 1;
+                let tile_y = mode == NineSliceMode::Tile && row == 1;
+                if !tile_x && !tile_y {
+                    self.draw_texture_ex(texture, src, dest, params);
+                    continue;
+                }
+
+                // Repeat the slice at its original size along the tiled axes,
+                // cropping the last tile to fit.
+                let step_x = if tile_x { src.width } else { dest.width };
+                let step_y = if tile_y { src.height } else { dest.height };
+                let mut y = 0.0;
+                while y < dest.height {
+                    let h = f32::min(step_y, dest.height - y);
+                    let src_h = if tile_y { h } else { src.height };
+                    let mut x = 0.0;
+                    while x < dest.width {
+                        let w = f32::min(step_x, dest.width - x);
+                        let src_w = if tile_x { w } else { src.width };
+                        self.draw_texture_ex(
+                            texture,
+                            Rect::new(src.x, src.y, src_w, src_h),
+                            Rect::new(dest.x + x, dest.y + y, w, h),
+                            params,
+                        );
+                        x += step_x;
+                    }
+                    y += step_y;
+                }
+            }
+        }
+    }
+
+    pub fn draw_sprite(&mut self, animation: &Animation, dest_rect: Rect, flip_x: bool, flip_y: bool) {
+        self.draw_sprite_ex(animation, dest_rect, DrawParams::new().flip_x(flip_x).flip_y(flip_y));
+    }
+
+    pub fn draw_sprite_ex(&mut self, animation: &Animation, dest_rect: Rect, params: DrawParams) {
+        self.draw_texture_ex(animation.sheet().texture(), animation.current_rect(), dest_rect, params);
+    }
+
+    pub fn draw_emitter(&mut self, emitter: &Emitter) {
+        let config = &emitter.config;
+        self.push_blend_mode(config.blend_mode);
+        for particle in emitter.particles() {
+            let t = particle.progress();
+            let size = config.size.sample(t) * particle.size_scale;
+            let color = config.color.sample(t);
+            if size <= 0.0 || color.a == 0 {
+                continue;
+            }
+            let position = particle.position;
+            match &config.shape {
+                ParticleShape::Rect => {
+                    let rect = Rect::new(position.x, position.y, size, size);
+                    self.draw_rotated_rect(rect, color, Point::new(size / 2.0, size / 2.0), particle.rotation);
+                }
+                ParticleShape::Circle => self.draw_circle(position, size / 2.0, color),
+                ParticleShape::Texture(texture, src_rect) => {
+                    let height = size * src_rect.height / src_rect.width.max(f32::EPSILON);
+                    let params = DrawParams::new()
+                        .tint(color)
+                        .origin(Point::new(size / 2.0, height / 2.0))
+                        .rotation(particle.rotation);
+                    self.draw_texture_ex(texture, *src_rect, Rect::new(position.x, position.y, size, height), params);
+                }
+            }
+        }
+        self.pop_blend_mode();
+    }
+
+    pub fn draw_particles(&mut self, particles: &ParticleSystem) {
+        for emitter in particles.iter() {
+            self.draw_emitter(emitter);
+        }
+    }
+
+    /// Draws every layer of the map, with `camera` (in map pixels) at the
+    /// top left of the viewport. Tile maps are drawn with their own shader,
+    /// ignoring `with_shader`.
+    pub fn draw_tilemap(&mut self, map: &mut TileMap, camera: Point) {
+        for layer in 0..map.layers().len() {
+            self.draw_tilemap_layer(map, layer, camera);
+        }
+    }
+
+    pub fn draw_tilemap_layer(&mut self, map: &mut TileMap, layer: usize, camera: Point) {
+        self.flush();
+        let viewport = self.viewport();
+        let program = self.program_tilemap.program();
+        unsafe {
+            gl::ActiveTexture(gl::TEXTURE0);
+            self.blend_mode.apply();
+            gl::Disable(gl::DEPTH_TEST);
+            gl::UseProgram(program);
+            let location = gl::GetUniformLocation(program, b"tex\0".as_ptr() as *const _);
+            gl::Uniform1i(location, 0);
+            let location = gl::GetUniformLocation(program, b"viewport_size\0".as_ptr() as *const _);
+            gl::Uniform2f(location, viewport.width, viewport.height);
+            let location = gl::GetUniformLocation(program, b"flip_y\0".as_ptr() as *const _);
+            gl::Uniform1i(location, viewport.flip_y as i32);
+        }
+        map.draw_layer(layer, Rect::new(camera.x, camera.y, viewport.width, viewport.height), program);
     }
 }
 
 // Text ============================================================
 
+const FONT_CACHE_MAX_UNUSED_FRAMES: u64 = 60;
+
 struct FontCacheEntry {
     texture_id: u32,
     width: i32,
     height: i32,
+    last_used: Cell<u64>,
+}
+
+impl Drop for FontCacheEntry {
+    fn drop(&mut self) {
+        resources::release(ResourceKind::Texture, self.texture_id);
+    }
 }
 
 impl<'a> Engine<'a> {
@@ -761,11 +1727,11 @@ impl<'a> Engine<'a> {
         let mut vao = 0;
         unsafe {
             gl::ActiveTexture(gl::TEXTURE0);
-            gl::BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
+            self.blend_mode.apply();
             gl::Disable(gl::DEPTH_TEST);
 
-            gl::UseProgram(self.program_text);
-            let uniform = gl::GetUniformLocation(self.program_text, b"tex\0".as_ptr() as *const _);
+            let program = self.use_program(self.program_text.program());
+            let uniform = gl::GetUniformLocation(program, b"tex\0".as_ptr() as *const _);
             gl::Uniform1i(uniform, 0);
 
             gl::BindBuffer(gl::ARRAY_BUFFER, self.text_buffer);
@@ -876,24 +1842,27 @@ impl<'a> Engine<'a> {
                 );
                 id
             };
+            resources::track(ResourceKind::Texture, id, glyphs_width * glyphs_height, format!("text {:?}", text));
             let resource = Rc::new(FontCacheEntry {
                 texture_id: id,
                 width: glyphs_width as i32,
                 height: glyphs_height as i32,
+                last_used: Cell::new(self.frame),
             });
             self.font_cache.insert(key, resource.clone());
             resource
         });
+        tex.last_used.set(self.frame);
 
         let id = tex.texture_id;
         let glyphs_width = tex.width;
         let glyphs_height = tex.height;
 
-        let x = x * 2.0 / self.window_width - 1.0;
-        let y = 1.0 - y * 2.0 / self.window_height;
-        let height = glyphs_height as f32 * 2.0 / self.window_height;
-        let width = glyphs_width as f32 * 2.0 / self.window_width;
-        let y = y - height;
+        let viewport = self.viewport();
+        let x0 = viewport.ndc_x(x);
+        let x1 = viewport.ndc_x(x + glyphs_width as f32);
+        let y0 = viewport.ndc_y(y + glyphs_height as f32);
+        let y1 = viewport.ndc_y(y);
         let color = [
             color.r as f32 / 255.0,
             color.g as f32 / 255.0,
@@ -901,12 +1870,12 @@ impl<'a> Engine<'a> {
             color.a as f32 / 255.0,
         ];
         let vertices = [
-            x, y, 0.0, 1.0, color[0], color[1], color[2], color[3],
-            x + width, y, 1.0, 1.0, color[0], color[1], color[2], color[3],
-            x + width, y + height, 1.0, 0.0, color[0], color[1], color[2], color[3],
-            x, y, 0.0, 1.0, color[0], color[1], color[2], color[3],
-            x + width, y + height, 1.0, 0.0, color[0], color[1], color[2], color[3],
-            x, y + height, 0.0, 0.0, color[0], color[1], color[2], color[3],
+            x0, y0, 0.0, 1.0, color[0], color[1], color[2], color[3],
+            x1, y0, 1.0, 1.0, color[0], color[1], color[2], color[3],
+            x1, y1, 1.0, 0.0, color[0], color[1], color[2], color[3],
+            x0, y0, 0.0, 1.0, color[0], color[1], color[2], color[3],
+            x1, y1, 1.0, 0.0, color[0], color[1], color[2], color[3],
+            x0, y1, 0.0, 0.0, color[0], color[1], color[2], color[3],
         ];
 
         self.text_entries.push((id, Vec::from(vertices)));
@@ -936,6 +1905,10 @@ impl<'a> Engine<'a> {
         self.char_width = self.font.glyph('o').scaled(Scale::uniform(self.font_size)).h_metrics().advance_width;
     }
 
+    pub fn clear_font_cache(&mut self) {
+        self.font_cache.clear();
+    }
+
     pub fn set_resource_path(&mut self, path: impl AsRef<Path>) {
         self.resource_path = PathBuf::from(path.as_ref());
     }
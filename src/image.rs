@@ -0,0 +1,471 @@
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+use stb_image::image::LoadResult;
+
+use super::engine::FilterMode;
+use super::types::{Color, Rect};
+
+/// An RGBA8 image in CPU memory, stored row by row from the top.
+///
+/// Unlike `Texture`, an `Image` needs no GL context, so it can be loaded,
+/// edited and saved in headless code and uploaded with `Texture::from_image`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub data: Vec<u8>,
+}
+
+impl Image {
+
+    pub fn new(width: usize, height: usize) -> Self {
+        Self::filled(width, height, Color::rgba(0, 0, 0, 0))
+    }
+
+    pub fn filled(width: usize, height: usize, color: Color) -> Self {
+        Self {
+            width,
+            height,
+            data: [color.r, color.g, color.b, color.a].repeat(width * height),
+        }
+    }
+
+    pub fn from_rgba(width: usize, height: usize, data: Vec<u8>) -> Result<Self, String> {
+        if data.len() != width * height * 4 {
+            return Err(format!("Expected {} bytes of RGBA data for a {}x{} image, got {}", width * height * 4, width, height, data.len()));
+        }
+        Ok(Self { width, height, data })
+    }
+
+    fn from_load_result(result: LoadResult) -> Result<Self, String> {
+        match result {
+            LoadResult::ImageU8(image) => Self::from_rgba(image.width, image.height, image.data),
+            LoadResult::ImageF32(_) => Err("Unexpected floating point image".to_string()),
+            LoadResult::Error(e) => Err(format!("Failed to load image: {}", e)),
+        }
+    }
+
+    /// Decodes a PNG, JPEG, BMP, TGA, GIF or HDR image. 16-bit images are
+    /// reduced to 8 bits per channel, and HDR images are tone mapped to 8
+    /// bits with a gamma of 2.2; `HdrImage` keeps both at full precision.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        if is_png16(bytes) {
+            let image = HdrImage::from_png16(bytes)?;
+            let data = image.data.iter().map(|&value| (value * 255.0).round() as u8).collect();
+            return Self::from_rgba(image.width, image.height, data);
+        }
+        Self::from_load_result(stb_image::image::load_from_memory_with_depth(bytes, 4, true))
+    }
+
+    /// Loads an image file, with the precision loss of `from_bytes`.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, String> {
+        let path = path.as_ref();
+        let bytes = std::fs::read(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        Self::from_bytes(&bytes)
+    }
+
+    pub fn save_png(&self, path: impl AsRef<Path>) -> Result<(), String> {
+        let file = File::create(path).map_err(|e| e.to_string())?;
+        self.write_png(BufWriter::new(file))
+    }
+
+    pub fn to_png(&self) -> Result<Vec<u8>, String> {
+        let mut bytes = Vec::new();
+        self.write_png(&mut bytes)?;
+        Ok(bytes)
+    }
+
+    fn write_png(&self, writer: impl std::io::Write) -> Result<(), String> {
+        let mut encoder = png::Encoder::new(writer, self.width as u32, self.height as u32);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header().map_err(|e| e.to_string())?;
+        writer.write_image_data(&self.data).map_err(|e| e.to_string())
+    }
+
+    fn index(&self, x: usize, y: usize) -> usize {
+        (y * self.width + x) * 4
+    }
+
+    pub fn get_pixel(&self, x: usize, y: usize) -> Color {
+        let i = self.index(x, y);
+        Color::rgba(self.data[i], self.data[i + 1], self.data[i + 2], self.data[i + 3])
+    }
+
+    pub fn set_pixel(&mut self, x: usize, y: usize, color: Color) {
+        let i = self.index(x, y);
+        self.data[i..i + 4].copy_from_slice(&[color.r, color.g, color.b, color.a]);
+    }
+
+    /// Returns the part of the image inside `rect`, clipped to the image bounds.
+    pub fn crop(&self, rect: Rect) -> Image {
+        let rect = rect.intersection(Rect::new(0.0, 0.0, self.width as f32, self.height as f32));
+        let (x, y) = (rect.x as usize, rect.y as usize);
+        let (width, height) = (rect.width as usize, rect.height as usize);
+        let mut data = Vec::with_capacity(width * height * 4);
+        for row in y..y + height {
+            let start = self.index(x, row);
+            data.extend_from_slice(&self.data[start..start + width * 4]);
+        }
+        Image { width, height, data }
+    }
+
+    pub fn flip_horizontal(&mut self) {
+        for y in 0..self.height {
+            for x in 0..self.width / 2 {
+                let (a, b) = (self.index(x, y), self.index(self.width - 1 - x, y));
+                for c in 0..4 {
+                    self.data.swap(a + c, b + c);
+                }
+            }
+        }
+    }
+
+    pub fn flip_vertical(&mut self) {
+        let stride = self.width * 4;
+        for y in 0..self.height / 2 {
+            let (top, bottom) = self.data.split_at_mut((self.height - 1 - y) * stride);
+            top[y * stride..(y + 1) * stride].swap_with_slice(&mut bottom[..stride]);
+        }
+    }
+
+    pub fn resize(&self, width: usize, height: usize, filter: FilterMode) -> Image {
+        let mut resized = Image::new(width, height);
+        if self.width == 0 || self.height == 0 {
+            return resized;
+        }
+        let scale_x = self.width as f32 / width as f32;
+        let scale_y = self.height as f32 / height as f32;
+        for y in 0..height {
+            for x in 0..width {
+                // Sample at pixel centers
+                let sx = (x as f32 + 0.5) * scale_x - 0.5;
+                let sy = (y as f32 + 0.5) * scale_y - 0.5;
+                let pixel = match filter {
+                    FilterMode::Nearest => {
+                        let sx = (sx.round().max(0.0) as usize).min(self.width - 1);
+                        let sy = (sy.round().max(0.0) as usize).min(self.height - 1);
+                        let i = self.index(sx, sy);
+                        [self.data[i], self.data[i + 1], self.data[i + 2], self.data[i + 3]]
+                    }
+                    FilterMode::Linear => self.sample_bilinear(sx, sy),
+                };
+                let i = resized.index(x, y);
+                resized.data[i..i + 4].copy_from_slice(&pixel);
+            }
+        }
+        resized
+    }
+
+    fn sample_bilinear(&self, x: f32, y: f32) -> [u8; 4] {
+        let x = x.clamp(0.0, (self.width - 1) as f32);
+        let y = y.clamp(0.0, (self.height - 1) as f32);
+        let (x0, y0) = (x.floor() as usize, y.floor() as usize);
+        let (x1, y1) = ((x0 + 1).min(self.width - 1), (y0 + 1).min(self.height - 1));
+        let (fx, fy) = (x - x0 as f32, y - y0 as f32);
+        let mut pixel = [0; 4];
+        for (c, value) in pixel.iter_mut().enumerate() {
+            let p = |px: usize, py: usize| self.data[self.index(px, py) + c] as f32;
+            let top = p(x0, y0) * (1.0 - fx) + p(x1, y0) * fx;
+            let bottom = p(x0, y1) * (1.0 - fx) + p(x1, y1) * fx;
+            *value = (top * (1.0 - fy) + bottom * fy).round() as u8;
+        }
+        pixel
+    }
+
+    pub fn premultiply_alpha(&mut self) {
+        for pixel in self.data.chunks_exact_mut(4) {
+            let a = pixel[3] as u32;
+            for c in &mut pixel[..3] {
+                *c = ((*c as u32 * a + 127) / 255) as u8;
+            }
+        }
+    }
+
+    /// Makes every pixel matching `key` (ignoring alpha) fully transparent.
+    pub fn color_key(&mut self, key: Color) {
+        for pixel in self.data.chunks_exact_mut(4) {
+            if pixel[0] == key.r && pixel[1] == key.g && pixel[2] == key.b {
+                pixel[3] = 0;
+            }
+        }
+    }
+
+    /// Draws `src` on top of this image with its top left corner at `(x, y)`,
+    /// alpha blending it with the existing pixels.
+    pub fn blit(&mut self, src: &Image, x: i32, y: i32) {
+        for sy in 0..src.height {
+            let dy = y + sy as i32;
+            if dy < 0 || dy >= self.height as i32 {
+                continue;
+            }
+            for sx in 0..src.width {
+                let dx = x + sx as i32;
+                if dx < 0 || dx >= self.width as i32 {
+                    continue;
+                }
+                let s = src.index(sx, sy);
+                let d = self.index(dx as usize, dy as usize);
+                let src_a = src.data[s + 3] as f32 / 255.0;
+                let dst_a = self.data[d + 3] as f32 / 255.0;
+                let out_a = src_a + dst_a * (1.0 - src_a);
+                for c in 0..3 {
+                    let out = if out_a > 0.0 {
+                        (src.data[s + c] as f32 * src_a + self.data[d + c] as f32 * dst_a * (1.0 - src_a)) / out_a
+                    } else {
+                        0.0
+                    };
+                    self.data[d + c] = out.round() as u8;
+                }
+                self.data[d + 3] = (out_a * 255.0).round() as u8;
+            }
+        }
+    }
+}
+
+/// A floating point RGBA image in linear color, stored row by row from the top.
+///
+/// Loads Radiance HDR files without clamping them to 8 bits, and 16-bit PNGs
+/// at full precision; other formats are converted to 0..1. Upload with `Texture::from_hdr_image`, which stores it
+/// as `TextureFormat::RGBA16F`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct HdrImage {
+    pub width: usize,
+    pub height: usize,
+    pub data: Vec<f32>,
+}
+
+impl HdrImage {
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        if is_png16(bytes) {
+            return Self::from_png16(bytes);
+        }
+        Self::from_load_result(stb_image::image::load_from_memory_with_depth(bytes, 4, false))
+    }
+
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, String> {
+        let path = path.as_ref();
+        let bytes = std::fs::read(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        Self::from_bytes(&bytes)
+    }
+
+    // stb_image can't load 16-bit PNGs, so they are decoded here
+    fn from_png16(bytes: &[u8]) -> Result<Self, String> {
+        let mut decoder = png::Decoder::new(bytes);
+        decoder.set_transformations(png::Transformations::EXPAND);
+        let mut reader = decoder.read_info().map_err(|e| format!("Failed to load image: {}", e))?;
+        let mut buffer = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buffer).map_err(|e| format!("Failed to load image: {}", e))?;
+        let samples: Vec<f32> = buffer[..info.buffer_size()]
+            .chunks_exact(2)
+            .map(|sample| u16::from_be_bytes([sample[0], sample[1]]) as f32 / 65535.0)
+            .collect();
+        let data = match info.color_type {
+            png::ColorType::Grayscale => samples.iter().flat_map(|&v| [v, v, v, 1.0]).collect(),
+            png::ColorType::GrayscaleAlpha => samples.chunks_exact(2).flat_map(|p| [p[0], p[0], p[0], p[1]]).collect(),
+            png::ColorType::Rgb => samples.chunks_exact(3).flat_map(|p| [p[0], p[1], p[2], 1.0]).collect(),
+            png::ColorType::Rgba => samples,
+            png::ColorType::Indexed => return Err("Unexpected indexed 16-bit image".to_string()),
+        };
+        Ok(Self { width: info.width as usize, height: info.height as usize, data })
+    }
+
+    pub(crate) fn from_load_result(result: LoadResult) -> Result<Self, String> {
+        let (width, height, depth, data) = match result {
+            LoadResult::ImageU8(image) => {
+                let data = image.data.iter().map(|&c| c as f32 / 255.0).collect();
+                (image.width, image.height, image.depth, data)
+            }
+            LoadResult::ImageF32(image) => (image.width, image.height, image.depth, image.data),
+            LoadResult::Error(e) => return Err(format!("Failed to load image: {}", e)),
+        };
+        // stb_image gives HDR files as RGB unless 4 channels are forced
+        let data = match depth {
+            4 => data,
+            3 => data.chunks_exact(3).flat_map(|p| [p[0], p[1], p[2], 1.0]).collect(),
+            _ => return Err(format!("Unexpected {} channel image", depth)),
+        };
+        Ok(Self { width, height, data })
+    }
+
+    /// The pixels as little-endian half floats, the layout of `TextureFormat::RGBA16F`.
+    pub fn to_rgba16f(&self) -> Vec<u8> {
+        self.data.iter().flat_map(|&value| f16_bits(value).to_le_bytes()).collect()
+    }
+}
+
+// Rounds to the nearest half float, saturating to infinity.
+// The bit depth is the first byte after the IHDR chunk's width and height
+fn is_png16(bytes: &[u8]) -> bool {
+    bytes.starts_with(b"\x89PNG\r\n\x1a\n") && bytes.get(24) == Some(&16)
+}
+
+fn f16_bits(value: f32) -> u16 {
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xff) as i32;
+    let mantissa = bits & 0x7f_ffff;
+    if exponent == 0xff {
+        return sign | 0x7c00 | if mantissa != 0 { 0x200 } else { 0 };
+    }
+    let exponent = exponent - 127 + 15;
+    if exponent >= 0x1f {
+        return sign | 0x7c00;
+    }
+    if exponent <= 0 {
+        // Subnormal, or too small for a half float
+        if exponent < -10 {
+            return sign;
+        }
+        let mantissa = mantissa | 0x80_0000;
+        let shift = (14 - exponent) as u32;
+        let round = (mantissa >> (shift - 1)) & 1;
+        return sign | ((mantissa >> shift) + round) as u16;
+    }
+    // A carry out of the mantissa correctly bumps the exponent
+    let round = (mantissa >> 12) & 1;
+    sign | ((((exponent as u32) << 10) | (mantissa >> 13)) + round) as u16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn red() -> Color {
+        Color::rgba(255, 0, 0, 255)
+    }
+
+    fn blue() -> Color {
+        Color::rgba(0, 0, 255, 255)
+    }
+
+    // 2x2: red, blue / blue, red
+    fn checker() -> Image {
+        let mut image = Image::filled(2, 2, blue());
+        image.set_pixel(0, 0, red());
+        image.set_pixel(1, 1, red());
+        image
+    }
+
+    #[test]
+    fn crop_is_clipped_to_the_image() {
+        let image = checker();
+        let cropped = image.crop(Rect::new(1.0, 0.0, 5.0, 1.0));
+        assert_eq!((cropped.width, cropped.height), (1, 1));
+        assert_eq!(cropped.get_pixel(0, 0), blue());
+    }
+
+    #[test]
+    fn flips() {
+        let mut image = Image::new(3, 2);
+        image.set_pixel(0, 0, red());
+        image.flip_horizontal();
+        assert_eq!(image.get_pixel(2, 0), red());
+        assert_eq!(image.get_pixel(0, 0).a, 0);
+        image.flip_vertical();
+        assert_eq!(image.get_pixel(2, 1), red());
+        assert_eq!(image.get_pixel(2, 0).a, 0);
+    }
+
+    #[test]
+    fn resize_nearest_and_linear() {
+        let image = checker();
+        let nearest = image.resize(4, 4, FilterMode::Nearest);
+        assert_eq!(nearest.get_pixel(0, 0), red());
+        assert_eq!(nearest.get_pixel(3, 0), blue());
+        assert_eq!(nearest.get_pixel(3, 3), red());
+
+        let linear = Image::filled(2, 2, red()).resize(3, 5, FilterMode::Linear);
+        assert_eq!((linear.width, linear.height), (3, 5));
+        assert!(linear.data.chunks_exact(4).all(|p| p == [255, 0, 0, 255]));
+    }
+
+    #[test]
+    fn premultiply_alpha() {
+        let mut image = Image::filled(1, 1, Color::rgba(255, 100, 0, 128));
+        image.premultiply_alpha();
+        assert_eq!(image.get_pixel(0, 0), Color::rgba(128, 50, 0, 128));
+    }
+
+    #[test]
+    fn color_key_ignores_alpha() {
+        let mut image = checker();
+        image.set_pixel(1, 1, Color::rgba(255, 0, 0, 10));
+        image.color_key(red());
+        assert_eq!(image.get_pixel(0, 0).a, 0);
+        assert_eq!(image.get_pixel(1, 1).a, 0);
+        assert_eq!(image.get_pixel(1, 0), blue());
+    }
+
+    #[test]
+    fn blit_clips_to_the_destination() {
+        let mut image = Image::new(2, 2);
+        image.blit(&Image::filled(2, 2, red()), -1, 1);
+        assert_eq!(image.get_pixel(0, 1), red());
+        assert_eq!(image.get_pixel(0, 0).a, 0);
+        assert_eq!(image.get_pixel(1, 0).a, 0);
+        assert_eq!(image.get_pixel(1, 1).a, 0);
+    }
+
+    #[test]
+    fn blit_blends_alpha() {
+        let mut image = Image::filled(1, 1, blue());
+        image.blit(&Image::filled(1, 1, Color::rgba(255, 0, 0, 0)), 0, 0);
+        assert_eq!(image.get_pixel(0, 0), blue());
+        image.blit(&Image::filled(1, 1, red()), 0, 0);
+        assert_eq!(image.get_pixel(0, 0), red());
+    }
+
+    #[test]
+    fn png_round_trip() {
+        let mut image = checker();
+        image.set_pixel(1, 0, Color::rgba(1, 2, 3, 4));
+        let png = image.to_png().unwrap();
+        assert_eq!(Image::from_bytes(&png).unwrap(), image);
+    }
+
+    #[test]
+    fn hdr_keeps_values_above_one() {
+        // A 2x1 Radiance file with flat RGBE pixels (2, 0.5, 0) and (0, 0, 0)
+        let mut bytes = b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y 1 +X 2\n".to_vec();
+        bytes.extend_from_slice(&[128, 32, 0, 130, 0, 0, 0, 0]);
+        let image = HdrImage::from_bytes(&bytes).unwrap();
+        assert_eq!((image.width, image.height), (2, 1));
+        assert_eq!(&image.data[..4], &[2.0, 0.5, 0.0, 1.0]);
+        assert_eq!(image.to_rgba16f()[..8], [0x00, 0x40, 0x00, 0x38, 0x00, 0x00, 0x00, 0x3c]);
+    }
+
+    #[test]
+    fn ldr_images_load_as_hdr() {
+        let image = HdrImage::from_bytes(&checker().to_png().unwrap()).unwrap();
+        assert_eq!(&image.data[..8], &[1.0, 0.0, 0.0, 1.0, 0.0, 0.0, 1.0, 1.0]);
+    }
+
+    #[test]
+    fn png16_keeps_full_precision() {
+        let mut bytes = Vec::new();
+        let mut encoder = png::Encoder::new(&mut bytes, 2, 1);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Sixteen);
+        let mut writer = encoder.write_header().unwrap();
+        writer.write_image_data(&[0, 1, 0x80, 0, 0xff, 0xff, 0, 0, 0, 0, 0, 0]).unwrap();
+        writer.finish().unwrap();
+
+        let image = HdrImage::from_bytes(&bytes).unwrap();
+        assert_eq!((image.width, image.height), (2, 1));
+        assert_eq!(&image.data[..4], &[1.0 / 65535.0, 32768.0 / 65535.0, 1.0, 1.0]);
+        assert_eq!(Image::from_bytes(&bytes).unwrap().get_pixel(0, 0), Color::rgba(0, 128, 255, 255));
+    }
+
+    #[test]
+    fn half_floats() {
+        assert_eq!(f16_bits(0.0), 0);
+        assert_eq!(f16_bits(-1.0), 0xbc00);
+        assert_eq!(f16_bits(65504.0), 0x7bff);
+        assert_eq!(f16_bits(1.0e6), 0x7c00);
+        assert_eq!(f16_bits(2.0f32.powi(-24)), 1);
+        assert_eq!(f16_bits(f32::NAN) & 0x7c00, 0x7c00);
+    }
+}
@@ -4,7 +4,34 @@ mod opengl;
 mod imgui_sdl2_support;
 mod imgui;
 mod sound;
+mod audio_effects;
+mod audio_backend;
+mod synth;
+mod blend;
+mod render_target;
+mod shader;
+mod post_process;
+mod resources;
+mod image;
+mod sprite;
+mod tilemap;
+mod tiled;
+mod particles;
+mod rng;
 
-pub use engine::{app, App, Engine, Texture, Key};
+pub use engine::{app, App, Engine, DrawParams, NineSliceMode, Texture, TextureFormat, Sampling, FilterMode, WrapMode, Key};
 pub use types::*;
-pub use sound::Sound;
+pub use audio_effects::{AudioEffect, EffectChain};
+pub use audio_backend::AudioBackend;
+pub use sound::{Sound, SoundEngine, SoundHandle, PlayOptions, Bus, Ducking, LoopPoints, Playlist, SoundMode, SoundInfo, SoundReport, SpatialConfig, AttenuationModel, AudioStream, PlayEvent, MusicEvent};
+pub use synth::{Tone, Waveform, Envelope, SfxPreset};
+pub use blend::{BlendMode, BlendFactor, BlendEquation};
+pub use render_target::RenderTarget;
+pub use shader::{Shader, Uniform};
+pub use post_process::{PostProcess, PostEffect};
+pub use resources::{ResourceKind, ResourceInfo, ResourceReport};
+pub use image::{Image, HdrImage};
+pub use sprite::{SpriteSheet, Animation, AnimationFrame, PlayMode};
+pub use tilemap::{TileMap, TileLayer, Tileset, Orientation, tile_id, TILE_FLIP_HORIZONTAL, TILE_FLIP_VERTICAL, TILE_FLIP_DIAGONAL};
+pub use tiled::{TiledMap, TiledObject, ObjectShape, ObjectLayer, MapLayer, Properties, PropertyValue};
+pub use particles::{ParticleSystem, Emitter, EmitterConfig, Particle, ParticleShape, EmissionShape, Curve, Gradient};
@@ -1,10 +1,10 @@
 use gl::types::*;
 use std::ffi::{CString, c_void, CStr};
 
-fn create_shader(shader_type: u32, source: &str) -> u32 {
+fn create_shader(shader_type: u32, source: &str) -> Result<u32, String> {
     unsafe {
         let id = gl::CreateShader(shader_type);
-        let source_cstr = CString::new(source).unwrap();
+        let source_cstr = CString::new(source).map_err(|e| e.to_string())?;
         gl::ShaderSource(
             id,
             1,
@@ -23,40 +23,49 @@ fn create_shader(shader_type: u32, source: &str) -> u32 {
                 CString::from_vec_unchecked(buffer)
             };
             gl::GetShaderInfoLog(id, len, std::ptr::null_mut(), error.as_ptr() as *mut gl::types::GLchar);
-            eprintln!("{}", error.to_string_lossy());
+            gl::DeleteShader(id);
+            return Err(error.to_string_lossy().trim_end_matches(['\0', '\n']).to_string());
         }
-        id
+        Ok(id)
     }
 }
 
 pub fn create_program(
     vertex_shader: &str,
     fragment_shader: &str,
-) -> u32 {
-    let vs = create_shader(gl::VERTEX_SHADER, vertex_shader);
-    let fs = create_shader(gl::FRAGMENT_SHADER, fragment_shader);
-    
+) -> Result<u32, String> {
+    let vs = create_shader(gl::VERTEX_SHADER, vertex_shader)
+        .map_err(|e| format!("Vertex shader failed to compile: {}", e))?;
+    let fs = match create_shader(gl::FRAGMENT_SHADER, fragment_shader) {
+        Ok(fs) => fs,
+        Err(e) => {
+            unsafe { gl::DeleteShader(vs) };
+            return Err(format!("Fragment shader failed to compile: {}", e));
+        }
+    };
+
     unsafe {
         let program = gl::CreateProgram();
         gl::AttachShader(program, vs);
         gl::AttachShader(program, fs);
         gl::LinkProgram(program);
+        gl::DeleteShader(vs);
+        gl::DeleteShader(fs);
         let mut success: gl::types::GLint = 1;
         gl::GetProgramiv(program, gl::LINK_STATUS, &mut success);
         if success == 0 {
             let mut len: gl::types::GLint = 0;
-            gl::GetShaderiv(program, gl::INFO_LOG_LENGTH, &mut len);
+            gl::GetProgramiv(program, gl::INFO_LOG_LENGTH, &mut len);
             let error = {
                 let mut buffer: Vec<u8> = Vec::with_capacity(len as usize + 1);
                 buffer.extend([b' '].iter().cycle().take(len as usize));
                 CString::from_vec_unchecked(buffer)
             };
             gl::GetProgramInfoLog(program, len, std::ptr::null_mut(), error.as_ptr() as *mut gl::types::GLchar);
-            eprintln!("{}", error.to_string_lossy());
+            gl::DeleteProgram(program);
+            return Err(format!("Shader program failed to link: {}", error.to_string_lossy().trim_end_matches(['\0', '\n'])));
         }
-        gl::DeleteShader(vs);
-        gl::DeleteShader(fs);
-        program
+        Ok(program)
     }
 
 }
@@ -0,0 +1,497 @@
+use std::f32::consts::PI;
+use std::rc::Rc;
+
+use super::blend::BlendMode;
+use super::engine::Texture;
+use super::rng::Rng;
+use super::types::{Color, Point, Rect};
+
+// Curves ============================================================
+
+fn sample<T: Copy>(points: &[(f32, T)], t: f32, lerp: impl Fn(T, T, f32) -> T) -> T {
+    let i = points.partition_point(|(time, _)| *time <= t);
+    if i == 0 {
+        return points[0].1;
+    }
+    if i == points.len() {
+        return points[i - 1].1;
+    }
+    let (t0, a) = points[i - 1];
+    let (t1, b) = points[i];
+    lerp(a, b, (t - t0) / (t1 - t0))
+}
+
+fn sorted<T: Copy>(points: &[(f32, T)], default: T) -> Vec<(f32, T)> {
+    let mut points = if points.is_empty() { vec![(0.0, default)] } else { points.to_vec() };
+    points.sort_by(|a, b| a.0.total_cmp(&b.0));
+    points
+}
+
+/// A value over a particle's life, given as `(t, value)` points where `t`
+/// goes from 0 at birth to 1 at death. Values in between are interpolated
+/// linearly.
+#[derive(Debug, Clone)]
+pub struct Curve {
+    points: Vec<(f32, f32)>,
+}
+
+impl Curve {
+
+    pub fn new(points: &[(f32, f32)]) -> Self {
+        Self { points: sorted(points, 0.0) }
+    }
+
+    pub fn constant(value: f32) -> Self {
+        Self::new(&[(0.0, value)])
+    }
+
+    pub fn linear(start: f32, end: f32) -> Self {
+        Self::new(&[(0.0, start), (1.0, end)])
+    }
+
+    pub fn sample(&self, t: f32) -> f32 {
+        sample(&self.points, t, |a, b, t| a + (b - a) * t)
+    }
+}
+
+/// Like `Curve`, for colors.
+#[derive(Debug, Clone)]
+pub struct Gradient {
+    stops: Vec<(f32, Color)>,
+}
+
+impl Gradient {
+
+    pub fn new(stops: &[(f32, Color)]) -> Self {
+        Self { stops: sorted(stops, Color::WHITE) }
+    }
+
+    pub fn constant(color: Color) -> Self {
+        Self::new(&[(0.0, color)])
+    }
+
+    pub fn linear(start: Color, end: Color) -> Self {
+        Self::new(&[(0.0, start), (1.0, end)])
+    }
+
+    pub fn sample(&self, t: f32) -> Color {
+        sample(&self.stops, t, |a, b, t| {
+            let lerp = |a: u8, b: u8| (a as f32 + (b as f32 - a as f32) * t).round() as u8;
+            Color::rgba(lerp(a.r, b.r), lerp(a.g, b.g), lerp(a.b, b.b), lerp(a.a, b.a))
+        })
+    }
+}
+
+// Emitters ============================================================
+
+/// Where new particles appear, relative to the emitter position.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum EmissionShape {
+    Point,
+    Circle(f32),
+    /// Width and height, centered on the emitter
+    Rect(f32, f32),
+}
+
+#[derive(Clone)]
+pub enum ParticleShape {
+    Rect,
+    Circle,
+    /// A region of a texture, scaled to the particle size along its width
+    Texture(Rc<Texture>, Rect),
+}
+
+/// Settings of an emitter. Pairs are `(min, max)` ranges that every particle
+/// picks a random value from; angles are in radians.
+#[derive(Clone)]
+pub struct EmitterConfig {
+    /// Particles per second while emitting
+    pub rate: f32,
+    /// Seconds to emit for, or forever if `None`
+    pub duration: Option<f32>,
+    pub max_particles: usize,
+    /// Seconds
+    pub lifetime: (f32, f32),
+    pub emission: EmissionShape,
+    /// Pixels per second
+    pub speed: (f32, f32),
+    /// Direction particles are emitted in; 0 points right and `-PI / 2` up.
+    pub direction: f32,
+    /// Maximum deviation from `direction`, either way
+    pub spread: f32,
+    /// Acceleration along the particle's velocity, in pixels per second squared
+    pub acceleration: f32,
+    /// In pixels per second squared
+    pub gravity: Point,
+    /// Fraction of the velocity lost per second
+    pub damping: f32,
+    pub rotation: (f32, f32),
+    /// Radians per second
+    pub angular_velocity: (f32, f32),
+    /// Size in pixels over the particle's life
+    pub size: Curve,
+    /// Random factor applied to `size` for each particle
+    pub size_scale: (f32, f32),
+    pub color: Gradient,
+    pub shape: ParticleShape,
+    pub blend_mode: BlendMode,
+}
+
+impl Default for EmitterConfig {
+    fn default() -> Self {
+        Self {
+            rate: 20.0,
+            duration: None,
+            max_particles: 1000,
+            lifetime: (1.0, 1.0),
+            emission: EmissionShape::Point,
+            speed: (50.0, 100.0),
+            direction: -PI / 2.0,
+            spread: PI,
+            acceleration: 0.0,
+            gravity: Point::ZERO,
+            damping: 0.0,
+            rotation: (0.0, 0.0),
+            angular_velocity: (0.0, 0.0),
+            size: Curve::constant(4.0),
+            size_scale: (1.0, 1.0),
+            color: Gradient::constant(Color::WHITE),
+            shape: ParticleShape::Rect,
+            blend_mode: BlendMode::Alpha,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Particle {
+    pub position: Point,
+    pub velocity: Point,
+    pub rotation: f32,
+    pub angular_velocity: f32,
+    /// Seconds since the particle was emitted
+    pub age: f32,
+    pub lifetime: f32,
+    pub size_scale: f32,
+}
+
+impl Particle {
+    /// From 0 at birth to 1 at death.
+    pub fn progress(&self) -> f32 {
+        if self.lifetime > 0.0 { (self.age / self.lifetime).min(1.0) } else { 1.0 }
+    }
+}
+
+pub struct Emitter {
+    pub config: EmitterConfig,
+    pub position: Point,
+    pub emitting: bool,
+    particles: Vec<Particle>,
+    // Fraction of a particle carried over between updates
+    accumulator: f32,
+    elapsed: f32,
+    rng: Rng,
+}
+
+impl Emitter {
+
+    pub fn new(config: EmitterConfig, position: Point) -> Self {
+        Self::from_rng(config, position, Rng::from_time())
+    }
+
+    /// Creates an emitter whose particles are the same on every run for the
+    /// same seed and sequence of updates.
+    pub fn with_seed(config: EmitterConfig, position: Point, seed: u64) -> Self {
+        Self::from_rng(config, position, Rng::new(seed))
+    }
+
+    fn from_rng(config: EmitterConfig, position: Point, rng: Rng) -> Self {
+        Self {
+            config,
+            position,
+            emitting: true,
+            particles: Vec::new(),
+            accumulator: 0.0,
+            elapsed: 0.0,
+            rng,
+        }
+    }
+
+    pub fn particles(&self) -> &[Particle] {
+        &self.particles
+    }
+
+    /// Whether the emitter stopped emitting and all its particles died.
+    pub fn is_finished(&self) -> bool {
+        !self.emitting && self.particles.is_empty()
+    }
+
+    /// Emits `count` particles at once, up to `max_particles`.
+    pub fn burst(&mut self, count: usize) {
+        for _ in 0..count {
+            self.spawn();
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.particles.clear();
+        self.accumulator = 0.0;
+    }
+
+    /// Clears the particles and starts emitting again from the beginning of `duration`.
+    pub fn restart(&mut self) {
+        self.clear();
+        self.elapsed = 0.0;
+        self.emitting = true;
+    }
+
+    pub fn update(&mut self, dt: f32) {
+        let config = &self.config;
+        let (gravity, acceleration) = (config.gravity, config.acceleration);
+        let damping = (1.0 - config.damping * dt).max(0.0);
+        self.particles.retain_mut(|p| {
+            p.age += dt;
+            if p.age >= p.lifetime {
+                return false;
+            }
+            let speed = (p.velocity.x * p.velocity.x + p.velocity.y * p.velocity.y).sqrt();
+            if speed > 0.0 && acceleration != 0.0 {
+                let factor = (speed + acceleration * dt).max(0.0) / speed;
+                p.velocity = Point::new(p.velocity.x * factor, p.velocity.y * factor);
+            }
+            p.velocity = Point::new(
+                (p.velocity.x + gravity.x * dt) * damping,
+                (p.velocity.y + gravity.y * dt) * damping,
+            );
+            p.position = p.position + Point::new(p.velocity.x * dt, p.velocity.y * dt);
+            p.rotation += p.angular_velocity * dt;
+            true
+        });
+
+        if !self.emitting {
+            return;
+        }
+        let mut emit_time = dt;
+        if let Some(duration) = self.config.duration {
+            emit_time = emit_time.min(duration - self.elapsed).max(0.0);
+            if self.elapsed + dt >= duration {
+                self.emitting = false;
+            }
+        }
+        self.elapsed += dt;
+        self.accumulator += self.config.rate * emit_time;
+        while self.accumulator >= 1.0 {
+            self.accumulator -= 1.0;
+            self.spawn();
+        }
+    }
+
+    fn spawn(&mut self) {
+        if self.particles.len() >= self.config.max_particles {
+            return;
+        }
+        let config = &self.config;
+        let rng = &mut self.rng;
+        let offset = match config.emission {
+            EmissionShape::Point => Point::ZERO,
+            EmissionShape::Circle(radius) => {
+                // sqrt keeps the points evenly spread over the disc
+                let r = radius * rng.next_f32().sqrt();
+                let angle = rng.range(0.0, 2.0 * PI);
+                Point::new(r * angle.cos(), r * angle.sin())
+            }
+            EmissionShape::Rect(width, height) => {
+                Point::new(rng.range(-width / 2.0, width / 2.0), rng.range(-height / 2.0, height / 2.0))
+            }
+        };
+        let angle = config.direction + rng.range(-config.spread, config.spread);
+        let speed = rng.range(config.speed.0, config.speed.1);
+        self.particles.push(Particle {
+            position: self.position + offset,
+            velocity: Point::new(speed * angle.cos(), speed * angle.sin()),
+            rotation: rng.range(config.rotation.0, config.rotation.1),
+            angular_velocity: rng.range(config.angular_velocity.0, config.angular_velocity.1),
+            age: 0.0,
+            lifetime: rng.range(config.lifetime.0, config.lifetime.1),
+            size_scale: rng.range(config.size_scale.0, config.size_scale.1),
+        });
+    }
+}
+
+// Particle systems ============================================================
+
+/// A set of emitters updated and drawn together. Emitters added with `add`
+/// stay until removed; the ones started with `burst` are dropped once all
+/// their particles died.
+pub struct ParticleSystem {
+    emitters: Vec<Option<Emitter>>,
+    bursts: Vec<Emitter>,
+    rng: Rng,
+}
+
+impl Default for ParticleSystem {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ParticleSystem {
+
+    pub fn new() -> Self {
+        Self::from_rng(Rng::from_time())
+    }
+
+    /// Seeds the emitters started with `burst`, for reproducible effects.
+    pub fn with_seed(seed: u64) -> Self {
+        Self::from_rng(Rng::new(seed))
+    }
+
+    fn from_rng(rng: Rng) -> Self {
+        Self {
+            emitters: Vec::new(),
+            bursts: Vec::new(),
+            rng,
+        }
+    }
+
+    /// Adds an emitter and returns its index for `get_mut` and `remove`.
+    pub fn add(&mut self, emitter: Emitter) -> usize {
+        self.emitters.push(Some(emitter));
+        self.emitters.len() - 1
+    }
+
+    pub fn get_mut(&mut self, index: usize) -> Option<&mut Emitter> {
+        self.emitters.get_mut(index).and_then(Option::as_mut)
+    }
+
+    pub fn remove(&mut self, index: usize) -> Option<Emitter> {
+        self.emitters.get_mut(index).and_then(Option::take)
+    }
+
+    /// Emits `count` particles at `position` once, e.g. for an explosion.
+    pub fn burst(&mut self, config: &EmitterConfig, position: Point, count: usize) {
+        let seed = self.rng.next_u64();
+        let mut emitter = Emitter::with_seed(config.clone(), position, seed);
+        emitter.emitting = false;
+        emitter.burst(count);
+        self.bursts.push(emitter);
+    }
+
+    pub fn update(&mut self, dt: f32) {
+        for emitter in self.iter_mut() {
+            emitter.update(dt);
+        }
+        self.bursts.retain(|emitter| !emitter.is_finished());
+    }
+
+    pub fn clear(&mut self) {
+        self.bursts.clear();
+        for emitter in self.emitters.iter_mut().flatten() {
+            emitter.clear();
+        }
+    }
+
+    /// Number of live particles.
+    pub fn len(&self) -> usize {
+        self.iter().map(|emitter| emitter.particles.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = &Emitter> {
+        self.emitters.iter().flatten().chain(self.bursts.iter())
+    }
+
+    fn iter_mut(&mut self) -> impl Iterator<Item = &mut Emitter> {
+        self.emitters.iter_mut().flatten().chain(self.bursts.iter_mut())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> EmitterConfig {
+        EmitterConfig {
+            rate: 50.0,
+            lifetime: (0.5, 2.0),
+            emission: EmissionShape::Circle(10.0),
+            gravity: Point::new(0.0, 100.0),
+            angular_velocity: (-1.0, 1.0),
+            size_scale: (0.5, 1.5),
+            ..EmitterConfig::default()
+        }
+    }
+
+    fn run(seed: u64) -> Vec<Particle> {
+        let mut emitter = Emitter::with_seed(config(), Point::new(100.0, 100.0), seed);
+        emitter.burst(10);
+        for _ in 0..120 {
+            emitter.update(1.0 / 60.0);
+        }
+        emitter.particles().to_vec()
+    }
+
+    fn same(a: &[Particle], b: &[Particle]) -> bool {
+        a.len() == b.len()
+            && a.iter().zip(b).all(|(a, b)| {
+                a.position == b.position
+                    && a.velocity == b.velocity
+                    && a.rotation == b.rotation
+                    && a.angular_velocity == b.angular_velocity
+                    && a.age == b.age
+                    && a.lifetime == b.lifetime
+                    && a.size_scale == b.size_scale
+            })
+    }
+
+    #[test]
+    fn same_seed_same_particles() {
+        let particles = run(42);
+        assert!(!particles.is_empty());
+        assert!(same(&particles, &run(42)));
+        assert!(!same(&particles, &run(43)));
+    }
+
+    #[test]
+    fn seeded_systems_repeat_bursts() {
+        let run = |seed| {
+            let mut system = ParticleSystem::with_seed(seed);
+            system.burst(&config(), Point::ZERO, 20);
+            system.burst(&config(), Point::new(50.0, 0.0), 20);
+            for _ in 0..30 {
+                system.update(1.0 / 60.0);
+            }
+            system.iter().flat_map(|emitter| emitter.particles().to_vec()).collect::<Vec<_>>()
+        };
+        assert!(same(&run(5), &run(5)));
+    }
+
+    #[test]
+    fn spawned_particles_respect_the_config() {
+        let mut emitter = Emitter::with_seed(config(), Point::new(100.0, 100.0), 1);
+        emitter.burst(500);
+        for p in emitter.particles() {
+            let offset = p.position - Point::new(100.0, 100.0);
+            assert!((offset.x * offset.x + offset.y * offset.y).sqrt() <= 10.0 + 1e-3);
+            assert!((0.5..=2.0).contains(&p.lifetime));
+            assert!((0.5..=1.5).contains(&p.size_scale));
+        }
+    }
+
+    #[test]
+    fn rate_and_duration() {
+        let config = EmitterConfig { duration: Some(1.0), lifetime: (2.0, 2.0), ..config() };
+        let mut emitter = Emitter::with_seed(config, Point::ZERO, 1);
+        for _ in 0..4 {
+            emitter.update(0.25);
+        }
+        assert_eq!(emitter.particles().len(), 50);
+        assert!(!emitter.emitting);
+        emitter.update(0.5);
+        assert_eq!(emitter.particles().len(), 50);
+        emitter.update(2.0);
+        assert!(emitter.is_finished());
+    }
+}
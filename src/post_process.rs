@@ -0,0 +1,320 @@
+use std::time::Instant;
+use std::{ptr, mem};
+use gl::types::*;
+
+use super::engine::{Texture, Sampling};
+use super::render_target::RenderTarget;
+use super::shader::Shader;
+use super::resources::{self, ResourceKind};
+
+// Post-processing ============================================================
+
+/// A fullscreen effect made of one or more shader passes.
+///
+/// Every pass is drawn with the output of the previous pass bound to `tex`.
+/// The input of the effect as a whole is bound to `effect_input`, and the
+/// `resolution` (in pixels) and `time` (in seconds) uniforms are set as well.
+pub struct PostEffect {
+    pub enabled: bool,
+    passes: Vec<Shader>,
+    // Keeps textures referenced by the passes (e.g. LUTs) alive.
+    textures: Vec<Texture>,
+}
+
+impl PostEffect {
+
+    pub fn new(shader: Shader) -> Self {
+        Self::from_passes(vec![shader])
+    }
+
+    pub fn from_passes(passes: Vec<Shader>) -> Self {
+        Self {
+            enabled: true,
+            passes,
+            textures: Vec::new(),
+        }
+    }
+
+    pub fn gaussian_blur(radius: f32) -> Result<Self, String> {
+        Ok(Self::from_passes(blur_passes(radius)?))
+    }
+
+    pub fn bloom(threshold: f32, radius: f32, intensity: f32) -> Result<Self, String> {
+        let mut bright = Shader::from_fragment(include_str!("shaders/post_bright.frag"))?;
+        bright.set_float("threshold", threshold);
+        let mut composite = Shader::from_fragment(include_str!("shaders/post_bloom.frag"))?;
+        composite.set_float("intensity", intensity);
+
+        let mut passes = vec![bright];
+        passes.extend(blur_passes(radius)?);
+        passes.push(composite);
+        Ok(Self::from_passes(passes))
+    }
+
+    /// Color grading using a lookup table laid out as a horizontal strip of
+    /// `lut_size` square slices (e.g. a 256x16 texture for `lut_size` 16).
+    pub fn color_grade(mut lut: Texture, lut_size: f32, intensity: f32) -> Result<Self, String> {
+        lut.set_sampling(Sampling::linear());
+        let mut shader = Shader::from_fragment(include_str!("shaders/post_color_grade.frag"))?;
+        shader.set_texture("lut", 2, &lut);
+        shader.set_float("lut_size", lut_size);
+        shader.set_float("intensity", intensity);
+        let mut effect = Self::new(shader);
+        effect.textures.push(lut);
+        Ok(effect)
+    }
+
+    pub fn vignette(strength: f32, radius: f32) -> Result<Self, String> {
+        let mut shader = Shader::from_fragment(include_str!("shaders/post_vignette.frag"))?;
+        shader.set_float("strength", strength);
+        shader.set_float("radius", radius);
+        Ok(Self::new(shader))
+    }
+
+    pub fn crt(curvature: f32, scanline_intensity: f32) -> Result<Self, String> {
+        let mut shader = Shader::from_fragment(include_str!("shaders/post_crt.frag"))?;
+        shader.set_float("curvature", curvature);
+        shader.set_float("scanline_intensity", scanline_intensity);
+        Ok(Self::new(shader))
+    }
+
+    pub fn pixelate(pixel_size: f32) -> Result<Self, String> {
+        let mut shader = Shader::from_fragment(include_str!("shaders/post_pixelate.frag"))?;
+        shader.set_float("pixel_size", pixel_size);
+        Ok(Self::new(shader))
+    }
+
+    /// Sets a float uniform on every pass of the effect, e.g. `"radius"` on a blur.
+    pub fn set_float(&mut self, name: &str, value: f32) {
+        for pass in &mut self.passes {
+            pass.set_float(name, value);
+        }
+    }
+
+    pub fn passes_mut(&mut self) -> &mut [Shader] {
+        &mut self.passes
+    }
+}
+
+fn blur_passes(radius: f32) -> Result<Vec<Shader>, String> {
+    let mut horizontal = Shader::from_fragment(include_str!("shaders/post_blur.frag"))?;
+    horizontal.set_vec2("direction", 1.0, 0.0);
+    horizontal.set_float("radius", radius);
+    let mut vertical = Shader::from_fragment(include_str!("shaders/post_blur.frag"))?;
+    vertical.set_vec2("direction", 0.0, 1.0);
+    vertical.set_float("radius", radius);
+    Ok(vec![horizontal, vertical])
+}
+
+pub struct PostProcess {
+    effects: Vec<(String, PostEffect)>,
+    // The scene, followed by scratch buffers for the passes.
+    buffers: Vec<RenderTarget>,
+    // The size the buffers last failed to be created at, not retried
+    failed_size: Option<(f32, f32)>,
+    copy_shader: Shader,
+    vao: u32,
+    vbo: u32,
+    start: Instant,
+}
+
+impl PostProcess {
+
+    pub(crate) fn new() -> Self {
+        let (mut vao, mut vbo) = (0, 0);
+        unsafe {
+            gl::GenVertexArrays(1, &mut vao);
+            gl::GenBuffers(1, &mut vbo);
+            gl::BindVertexArray(vao);
+            gl::BindBuffer(gl::ARRAY_BUFFER, vbo);
+            gl::BufferData(
+                gl::ARRAY_BUFFER,
+                (24 * mem::size_of::<GLfloat>()) as GLsizeiptr,
+                ptr::null(),
+                gl::DYNAMIC_DRAW,
+            );
+            let stride = 4 * mem::size_of::<GLfloat>() as GLsizei;
+            gl::EnableVertexAttribArray(0);
+            gl::VertexAttribPointer(0, 2, gl::FLOAT, gl::FALSE, stride, ptr::null());
+            gl::EnableVertexAttribArray(1);
+            gl::VertexAttribPointer(1, 2, gl::FLOAT, gl::FALSE, stride, (2 * mem::size_of::<GLfloat>()) as *const _);
+            gl::BindVertexArray(0);
+            gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+        }
+        resources::track(ResourceKind::VertexArray, vao, 0, "post-processing quad");
+        resources::track(ResourceKind::Buffer, vbo, 24 * mem::size_of::<GLfloat>(), "post-processing quad");
+
+        Self {
+            effects: Vec::new(),
+            buffers: Vec::new(),
+            failed_size: None,
+            copy_shader: Shader::from_fragment(include_str!("shaders/texture.frag")).unwrap(),
+            vao,
+            vbo,
+            start: Instant::now(),
+        }
+    }
+
+    /// Adds an effect to the end of the chain, replacing any effect with the same name.
+    pub fn add(&mut self, name: &str, effect: PostEffect) {
+        match self.effects.iter_mut().find(|(n, _)| n == name) {
+            Some((_, existing)) => *existing = effect,
+            None => self.effects.push((name.to_string(), effect)),
+        }
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<PostEffect> {
+        let index = self.effects.iter().position(|(n, _)| n == name)?;
+        Some(self.effects.remove(index).1)
+    }
+
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut PostEffect> {
+        self.effects.iter_mut().find(|(n, _)| n == name).map(|(_, effect)| effect)
+    }
+
+    pub fn set_enabled(&mut self, name: &str, enabled: bool) {
+        if let Some(effect) = self.get_mut(name) {
+            effect.enabled = enabled;
+        }
+    }
+
+    pub fn is_enabled(&self, name: &str) -> bool {
+        self.effects.iter().any(|(n, effect)| n == name && effect.enabled)
+    }
+
+    pub fn clear(&mut self) {
+        self.effects.clear();
+    }
+
+    // Reloads the passes whose files changed, returning the compile errors
+    pub(crate) fn reload_changed_shaders(&mut self) -> Vec<String> {
+        self.effects
+            .iter_mut()
+            .flat_map(|(_, effect)| effect.passes.iter_mut())
+            .filter_map(|pass| pass.reload_if_changed().err())
+            .collect()
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.effects.iter().any(|(_, effect)| effect.enabled && !effect.passes.is_empty())
+    }
+
+    // Returns the framebuffer the scene should be drawn into this frame, or 0
+    // to draw straight to the window. Buffers that fail to be created are
+    // reported once, and tried again when the size changes.
+    pub(crate) fn begin_frame(&mut self, width: f32, height: f32) -> Result<u32, String> {
+        if !self.is_active() || width < 1.0 || height < 1.0 || self.failed_size == Some((width, height)) {
+            return Ok(0);
+        }
+        let resized = self.buffers.first().is_none_or(|b| b.width() != width || b.height() != height);
+        if resized {
+            self.buffers.clear();
+            self.failed_size = None;
+            for _ in 0..4 {
+                match RenderTarget::new(width as usize, height as usize) {
+                    Ok(target) => self.buffers.push(target),
+                    Err(e) => {
+                        self.buffers.clear();
+                        self.failed_size = Some((width, height));
+                        return Err(format!("Failed to create post-processing buffers: {}", e));
+                    }
+                }
+            }
+        }
+        Ok(self.buffers[0].framebuffer())
+    }
+
+    // Runs the enabled effects over the scene and draws the result to the window.
+    pub(crate) fn apply(&mut self, width: f32, height: f32) {
+        let passes: Vec<(usize, &Shader)> = self.effects
+            .iter()
+            .filter(|(_, effect)| effect.enabled)
+            .enumerate()
+            .flat_map(|(i, (_, effect))| effect.passes.iter().map(move |pass| (i, pass)))
+            .collect();
+        let passes = if passes.is_empty() { vec![(0, &self.copy_shader)] } else { passes };
+        let time = self.start.elapsed().as_secs_f32();
+
+        unsafe {
+            gl::Disable(gl::BLEND);
+            gl::Disable(gl::DEPTH_TEST);
+            gl::BindVertexArray(self.vao);
+            gl::BindBuffer(gl::ARRAY_BUFFER, self.vbo);
+            gl::VertexAttrib4f(2, 1.0, 1.0, 1.0, 1.0);
+        }
+
+        let mut source = 0;
+        let mut effect_input = 0;
+        let mut current_effect = None;
+        for (n, (effect, pass)) in passes.iter().enumerate() {
+            if current_effect != Some(*effect) {
+                current_effect = Some(*effect);
+                effect_input = source;
+            }
+
+            let last = n == passes.len() - 1;
+            let dest = if last {
+                None
+            } else {
+                (1..self.buffers.len()).find(|&i| i != source && i != effect_input)
+            };
+
+            let binding = pass.binding();
+            unsafe {
+                match dest {
+                    Some(i) => gl::BindFramebuffer(gl::FRAMEBUFFER, self.buffers[i].framebuffer()),
+                    None => gl::BindFramebuffer(gl::FRAMEBUFFER, 0),
+                }
+                gl::Viewport(0, 0, width as i32, height as i32);
+
+                gl::UseProgram(binding.program);
+                binding.apply_uniforms();
+                gl::ActiveTexture(gl::TEXTURE1);
+                gl::BindTexture(gl::TEXTURE_2D, self.buffers[effect_input].texture().texture_id);
+                gl::ActiveTexture(gl::TEXTURE0);
+                gl::BindTexture(gl::TEXTURE_2D, self.buffers[source].texture().texture_id);
+                set_uniform_i(binding.program, b"tex\0", 0);
+                set_uniform_i(binding.program, b"effect_input\0", 1);
+                let location = gl::GetUniformLocation(binding.program, b"resolution\0".as_ptr() as *const _);
+                gl::Uniform2f(location, width, height);
+                let location = gl::GetUniformLocation(binding.program, b"time\0".as_ptr() as *const _);
+                gl::Uniform1f(location, time);
+
+                // The buffers store rows top to bottom, the window bottom to top.
+                let (top, bottom) = if dest.is_none() { (0.0, 1.0) } else { (1.0, 0.0) };
+                let vertices: [f32; 24] = [
+                    -1.0, -1.0, 0.0, bottom,
+                    1.0, -1.0, 1.0, bottom,
+                    1.0, 1.0, 1.0, top,
+                    -1.0, -1.0, 0.0, bottom,
+                    1.0, 1.0, 1.0, top,
+                    -1.0, 1.0, 0.0, top,
+                ];
+                gl::BufferSubData(
+                    gl::ARRAY_BUFFER,
+                    0,
+                    (vertices.len() * mem::size_of::<GLfloat>()) as GLsizeiptr,
+                    vertices.as_ptr() as *const _,
+                );
+                gl::DrawArrays(gl::TRIANGLES, 0, 6);
+            }
+
+            if let Some(dest) = dest {
+                source = dest;
+            }
+        }
+
+        unsafe {
+            gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+            gl::BindVertexArray(0);
+            gl::Enable(gl::BLEND);
+        }
+    }
+}
+
+fn set_uniform_i(program: u32, name: &[u8], value: i32) {
+    unsafe {
+        let location = gl::GetUniformLocation(program, name.as_ptr() as *const _);
+        gl::Uniform1i(location, value);
+    }
+}
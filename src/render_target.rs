@@ -0,0 +1,69 @@
+use super::engine::Texture;
+use super::resources::{self, ResourceKind};
+
+pub struct RenderTarget {
+    framebuffer: u32,
+    texture: Texture,
+}
+
+impl RenderTarget {
+
+    pub fn new(width: usize, height: usize) -> Result<Self, String> {
+        let texture = Texture::new(width, height, Vec::new());
+        let framebuffer = unsafe {
+            let mut previous = 0;
+            gl::GetIntegerv(gl::FRAMEBUFFER_BINDING, &mut previous);
+
+            let mut framebuffer = 0;
+            gl::GenFramebuffers(1, &mut framebuffer);
+            gl::BindFramebuffer(gl::FRAMEBUFFER, framebuffer);
+            gl::FramebufferTexture2D(
+                gl::FRAMEBUFFER,
+                gl::COLOR_ATTACHMENT0,
+                gl::TEXTURE_2D,
+                texture.texture_id,
+                0,
+            );
+            let status = gl::CheckFramebufferStatus(gl::FRAMEBUFFER);
+            gl::BindFramebuffer(gl::FRAMEBUFFER, previous as u32);
+            if status != gl::FRAMEBUFFER_COMPLETE {
+                gl::DeleteFramebuffers(1, &framebuffer);
+                return Err(format!("Failed to create render target (status 0x{:x})", status));
+            }
+            framebuffer
+        };
+        resources::track(ResourceKind::Framebuffer, framebuffer, 0, format!("render target {}x{}", width, height));
+        texture.set_label(&format!("render target {}x{}", width, height));
+
+        Ok(Self {
+            framebuffer,
+            texture,
+        })
+    }
+
+    pub fn texture(&self) -> &Texture {
+        &self.texture
+    }
+
+    pub fn texture_mut(&mut self) -> &mut Texture {
+        &mut self.texture
+    }
+
+    pub fn width(&self) -> f32 {
+        self.texture.width
+    }
+
+    pub fn height(&self) -> f32 {
+        self.texture.height
+    }
+
+    pub(crate) fn framebuffer(&self) -> u32 {
+        self.framebuffer
+    }
+}
+
+impl Drop for RenderTarget {
+    fn drop(&mut self) {
+        resources::release(ResourceKind::Framebuffer, self.framebuffer);
+    }
+}
@@ -0,0 +1,106 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::Mutex;
+
+// GPU resources ============================================================
+//
+// Every GL object owned by a pgfx type is registered here so the engine can
+// report what is alive. Objects are never deleted from `Drop` directly, since
+// that may run without a current GL context (or on another thread); instead
+// they are queued and deleted by the engine at the end of the frame.
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ResourceKind {
+    Texture,
+    Buffer,
+    VertexArray,
+    Framebuffer,
+    Program,
+}
+
+#[derive(Debug, Clone)]
+pub struct ResourceInfo {
+    pub kind: ResourceKind,
+    pub id: u32,
+    pub bytes: usize,
+    pub label: String,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ResourceReport {
+    pub resources: Vec<ResourceInfo>,
+}
+
+impl ResourceReport {
+
+    pub fn count(&self, kind: ResourceKind) -> usize {
+        self.resources.iter().filter(|r| r.kind == kind).count()
+    }
+
+    pub fn bytes(&self, kind: ResourceKind) -> usize {
+        self.resources.iter().filter(|r| r.kind == kind).map(|r| r.bytes).sum()
+    }
+
+    pub fn total_bytes(&self) -> usize {
+        self.resources.iter().map(|r| r.bytes).sum()
+    }
+}
+
+impl fmt::Display for ResourceReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{} GPU resources, {} bytes", self.resources.len(), self.total_bytes())?;
+        for r in &self.resources {
+            writeln!(f, "  {:?} {:>6} {:>12} bytes  {}", r.kind, r.id, r.bytes, r.label)?;
+        }
+        Ok(())
+    }
+}
+
+static LIVE: Mutex<BTreeMap<(ResourceKind, u32), ResourceInfo>> = Mutex::new(BTreeMap::new());
+static PENDING_DELETE: Mutex<Vec<(ResourceKind, u32)>> = Mutex::new(Vec::new());
+
+pub(crate) fn track(kind: ResourceKind, id: u32, bytes: usize, label: impl Into<String>) {
+    let info = ResourceInfo { kind, id, bytes, label: label.into() };
+    LIVE.lock().unwrap().insert((kind, id), info);
+}
+
+pub(crate) fn set_bytes(kind: ResourceKind, id: u32, bytes: usize) {
+    if let Some(info) = LIVE.lock().unwrap().get_mut(&(kind, id)) {
+        info.bytes = bytes;
+    }
+}
+
+pub(crate) fn set_label(kind: ResourceKind, id: u32, label: &str) {
+    if let Some(info) = LIVE.lock().unwrap().get_mut(&(kind, id)) {
+        info.label = label.to_string();
+    }
+}
+
+// Queues a GL object for deletion on the GL thread.
+pub(crate) fn release(kind: ResourceKind, id: u32) {
+    LIVE.lock().unwrap().remove(&(kind, id));
+    PENDING_DELETE.lock().unwrap().push((kind, id));
+}
+
+// Must be called with the GL context current, once queued draws that may
+// still reference the objects have been flushed.
+pub(crate) fn delete_released() {
+    let pending = std::mem::take(&mut *PENDING_DELETE.lock().unwrap());
+    for (kind, id) in pending {
+        unsafe {
+            match kind {
+                ResourceKind::Texture => gl::DeleteTextures(1, &id),
+                ResourceKind::Buffer => gl::DeleteBuffers(1, &id),
+                ResourceKind::VertexArray => gl::DeleteVertexArrays(1, &id),
+                ResourceKind::Framebuffer => gl::DeleteFramebuffers(1, &id),
+                ResourceKind::Program => gl::DeleteProgram(id),
+            }
+        }
+    }
+}
+
+pub(crate) fn report() -> ResourceReport {
+    ResourceReport {
+        resources: LIVE.lock().unwrap().values().cloned().collect(),
+    }
+}
@@ -0,0 +1,100 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// A small xorshift64* generator. Subsystems that need randomness (particles,
+// shuffled playlists, noise) take an explicit seed so their output can be
+// reproduced in tests.
+#[derive(Debug, Clone)]
+pub(crate) struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub(crate) fn new(seed: u64) -> Self {
+        // Zero is a fixed point of xorshift, so mix the seed first
+        let mut state = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        state = (state ^ (state >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        state = (state ^ (state >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        state ^= state >> 31;
+        Self { state: if state == 0 { 1 } else { state } }
+    }
+
+    pub(crate) fn from_time() -> Self {
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_nanos() as u64);
+        Self::new(nanos)
+    }
+
+    pub(crate) fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    // In [0, 1)
+    pub(crate) fn next_f32(&mut self) -> f32 {
+        (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+    }
+
+    // Between min and max inclusive, since rounding can land on max
+    pub(crate) fn range(&mut self, min: f32, max: f32) -> f32 {
+        min + (max - min) * self.next_f32()
+    }
+
+    // In [0, n), for n > 0
+    pub(crate) fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+
+    pub(crate) fn shuffle<T>(&mut self, items: &mut [T]) {
+        for i in (1..items.len()).rev() {
+            items.swap(i, self.below(i + 1));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_seed_same_sequence() {
+        let (mut a, mut b) = (Rng::new(7), Rng::new(7));
+        assert!((0..100).all(|_| a.next_u64() == b.next_u64()));
+        assert_ne!(Rng::new(7).next_u64(), Rng::new(8).next_u64());
+        // Zero would be a fixed point without the seed mixing
+        assert_ne!(Rng::new(0).next_u64(), 0);
+    }
+
+    #[test]
+    fn next_f32_is_below_one() {
+        let mut rng = Rng::new(1);
+        assert!((0..10_000).map(|_| rng.next_f32()).all(|x| (0.0..1.0).contains(&x)));
+    }
+
+    #[test]
+    fn range_bounds() {
+        let mut rng = Rng::new(2);
+        for (min, max) in [(0.0, 1.0), (-5.0, 5.0), (0.25, 0.5), (400.0, 1200.0), (-8000.0, -3000.0)] {
+            let values: Vec<f32> = (0..10_000).map(|_| rng.range(min, max)).collect();
+            assert!(values.iter().all(|x| (min..=max).contains(x)), "range({}, {})", min, max);
+            // Both ends of the range are reached
+            assert!(values.iter().any(|&x| x < min + (max - min) * 0.01));
+            assert!(values.iter().any(|&x| x > max - (max - min) * 0.01));
+        }
+        // Reversed and empty ranges stay between their ends
+        assert!((0..100).map(|_| rng.range(1.0, -1.0)).all(|x| (-1.0..=1.0).contains(&x)));
+        assert!((0..100).all(|_| rng.range(3.0, 3.0) == 3.0));
+    }
+
+    #[test]
+    fn below_and_shuffle() {
+        let mut rng = Rng::new(3);
+        assert!((0..1000).all(|_| rng.below(3) < 3));
+        assert!((0..100).all(|_| rng.below(1) == 0));
+        let mut items: Vec<u32> = (0..20).collect();
+        rng.shuffle(&mut items);
+        assert_ne!(items, (0..20).collect::<Vec<_>>());
+        items.sort();
+        assert_eq!(items, (0..20).collect::<Vec<_>>());
+    }
+}
@@ -0,0 +1,233 @@
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use super::engine::Texture;
+use super::opengl::create_program;
+use super::resources::{self, ResourceKind};
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Uniform {
+    Float(f32),
+    Vec2([f32; 2]),
+    Vec3([f32; 3]),
+    Vec4([f32; 4]),
+    Mat3([f32; 9]),
+    Mat4([f32; 16]),
+    Int(i32),
+    Texture { slot: u32, texture_id: u32 },
+}
+
+impl Uniform {
+    fn apply(&self, location: i32) {
+        unsafe {
+            match *self {
+                Uniform::Float(v) => gl::Uniform1f(location, v),
+                Uniform::Vec2(v) => gl::Uniform2f(location, v[0], v[1]),
+                Uniform::Vec3(v) => gl::Uniform3f(location, v[0], v[1], v[2]),
+                Uniform::Vec4(v) => gl::Uniform4f(location, v[0], v[1], v[2], v[3]),
+                Uniform::Mat3(m) => gl::UniformMatrix3fv(location, 1, gl::FALSE, m.as_ptr()),
+                Uniform::Mat4(m) => gl::UniformMatrix4fv(location, 1, gl::FALSE, m.as_ptr()),
+                Uniform::Int(v) => gl::Uniform1i(location, v),
+                Uniform::Texture { slot, texture_id } => {
+                    gl::ActiveTexture(gl::TEXTURE0 + slot);
+                    gl::BindTexture(gl::TEXTURE_2D, texture_id);
+                    gl::ActiveTexture(gl::TEXTURE0);
+                    gl::Uniform1i(location, slot as i32);
+                }
+            }
+        }
+    }
+}
+
+// A snapshot of a shader and its uniforms, taken when the engine starts
+// batching with it so later changes to the `Shader` don't affect queued draws.
+#[derive(Clone)]
+pub(crate) struct ShaderBinding {
+    pub(crate) program: u32,
+    uniforms: Vec<(CString, Uniform)>,
+}
+
+impl ShaderBinding {
+    pub(crate) fn apply_uniforms(&self) {
+        for (name, uniform) in &self.uniforms {
+            let location = unsafe { gl::GetUniformLocation(self.program, name.as_ptr()) };
+            if location >= 0 {
+                uniform.apply(location);
+            }
+        }
+    }
+}
+
+/// A user supplied shader program for 2D draws.
+///
+/// Vertex attributes are laid out the same way for every kind of draw:
+/// `location = 0` is the position (`vec2`, already in clip space),
+/// `location = 1` the texture coordinates (`vec2`) and `location = 2` the
+/// color (`vec4`). The texture being drawn is bound to the `tex` sampler.
+pub struct Shader {
+    program: u32,
+    uniforms: HashMap<String, Uniform>,
+    files: Option<ShaderFiles>,
+}
+
+struct ShaderFiles {
+    vertex: PathBuf,
+    fragment: PathBuf,
+    modified: [Option<SystemTime>; 2],
+}
+
+impl ShaderFiles {
+    fn modified(&self) -> [Option<SystemTime>; 2] {
+        let mtime = |path: &Path| std::fs::metadata(path).and_then(|m| m.modified()).ok();
+        [mtime(&self.vertex), mtime(&self.fragment)]
+    }
+
+    fn compile(&self) -> Result<u32, String> {
+        let read = |path: &Path| {
+            std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))
+        };
+        create_program(&read(&self.vertex)?, &read(&self.fragment)?)
+            .map_err(|e| format!("{} + {}: {}", self.vertex.display(), self.fragment.display(), e))
+    }
+}
+
+impl Shader {
+
+    pub fn new(vertex_source: &str, fragment_source: &str) -> Result<Self, String> {
+        let program = create_program(vertex_source, fragment_source)?;
+        resources::track(ResourceKind::Program, program, 0, "shader");
+        Ok(Self {
+            program,
+            uniforms: HashMap::new(),
+            files: None,
+        })
+    }
+
+    pub fn from_fragment(fragment_source: &str) -> Result<Self, String> {
+        Self::new(include_str!("shaders/text.vert"), fragment_source)
+    }
+
+    /// Loads a shader from disk, remembering the paths so it can be
+    /// recompiled with `reload_if_changed` while developing.
+    pub fn from_files(vertex_path: impl AsRef<Path>, fragment_path: impl AsRef<Path>) -> Result<Self, String> {
+        let mut files = ShaderFiles {
+            vertex: vertex_path.as_ref().to_path_buf(),
+            fragment: fragment_path.as_ref().to_path_buf(),
+            modified: [None, None],
+        };
+        files.modified = files.modified();
+        let program = files.compile()?;
+        resources::track(ResourceKind::Program, program, 0, format!("shader {}", files.fragment.display()));
+        Ok(Self {
+            program,
+            uniforms: HashMap::new(),
+            files: Some(files),
+        })
+    }
+
+    // Watches the files without compiling them now, so a shader whose files
+    // failed to compile keeps its current program until they are fixed
+    pub(crate) fn watch_files(&mut self, vertex_path: &Path, fragment_path: &Path) {
+        let mut files = ShaderFiles {
+            vertex: vertex_path.to_path_buf(),
+            fragment: fragment_path.to_path_buf(),
+            modified: [None, None],
+        };
+        files.modified = files.modified();
+        self.files = Some(files);
+    }
+
+    /// Recompiles the shader if its files changed since they were last read.
+    /// Returns whether the shader was reloaded; if compiling fails, the
+    /// previous program is kept and the GL info log is returned.
+    ///
+    /// With hot reload enabled, the engine calls this for its built-in
+    /// shaders and for post-processing passes. Shaders owned by the game
+    /// need to be polled by the game.
+    pub fn reload_if_changed(&mut self) -> Result<bool, String> {
+        let Some(files) = &mut self.files else {
+            return Ok(false);
+        };
+        let modified = files.modified();
+        if modified == files.modified {
+            return Ok(false);
+        }
+        files.modified = modified;
+        let program = files.compile()?;
+        resources::track(ResourceKind::Program, program, 0, format!("shader {}", files.fragment.display()));
+        resources::release(ResourceKind::Program, self.program);
+        self.program = program;
+        Ok(true)
+    }
+
+    /// Names containing a NUL byte can't exist in GLSL and are ignored, as
+    /// are textures for slot 0.
+    pub fn set_uniform(&mut self, name: &str, uniform: Uniform) {
+        if name.contains('\0') || matches!(uniform, Uniform::Texture { slot: 0, .. }) {
+            return;
+        }
+        self.uniforms.insert(name.to_string(), uniform);
+    }
+
+    pub fn set_float(&mut self, name: &str, value: f32) {
+        self.set_uniform(name, Uniform::Float(value));
+    }
+
+    pub fn set_vec2(&mut self, name: &str, x: f32, y: f32) {
+        self.set_uniform(name, Uniform::Vec2([x, y]));
+    }
+
+    pub fn set_vec3(&mut self, name: &str, x: f32, y: f32, z: f32) {
+        self.set_uniform(name, Uniform::Vec3([x, y, z]));
+    }
+
+    pub fn set_vec4(&mut self, name: &str, x: f32, y: f32, z: f32, w: f32) {
+        self.set_uniform(name, Uniform::Vec4([x, y, z, w]));
+    }
+
+    /// Sets a 3x3 matrix, given in column-major order.
+    pub fn set_mat3(&mut self, name: &str, value: [f32; 9]) {
+        self.set_uniform(name, Uniform::Mat3(value));
+    }
+
+    /// Sets a 4x4 matrix, given in column-major order.
+    pub fn set_mat4(&mut self, name: &str, value: [f32; 16]) {
+        self.set_uniform(name, Uniform::Mat4(value));
+    }
+
+    pub fn set_int(&mut self, name: &str, value: i32) {
+        self.set_uniform(name, Uniform::Int(value));
+    }
+
+    /// Binds `texture` to the given texture slot for this shader. Slot 0 is
+    /// reserved for the texture being drawn, so it is ignored.
+    pub fn set_texture(&mut self, name: &str, slot: u32, texture: &Texture) {
+        self.set_uniform(name, Uniform::Texture { slot, texture_id: texture.texture_id });
+    }
+
+    pub fn uniform(&self, name: &str) -> Option<Uniform> {
+        self.uniforms.get(name).copied()
+    }
+
+    pub(crate) fn program(&self) -> u32 {
+        self.program
+    }
+
+    pub(crate) fn binding(&self) -> ShaderBinding {
+        ShaderBinding {
+            program: self.program,
+            uniforms: self.uniforms
+                .iter()
+                .filter_map(|(name, uniform)| Some((CString::new(name.as_str()).ok()?, *uniform)))
+                .collect(),
+        }
+    }
+}
+
+impl Drop for Shader {
+    fn drop(&mut self) {
+        resources::release(ResourceKind::Program, self.program);
+    }
+}
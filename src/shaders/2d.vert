@@ -1,7 +1,7 @@
 #version 330 core
 
 layout (location = 0) in vec2 position;
-layout (location = 1) in vec4 color;
+layout (location = 2) in vec4 color;
 
 out vec4 v_color;
 
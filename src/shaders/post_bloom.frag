@@ -0,0 +1,13 @@
+#version 330 core
+
+uniform sampler2D tex;
+uniform sampler2D effect_input;
+uniform float intensity;
+in vec2 v_tex_coords;
+out vec4 f_color;
+
+void main() {
+    vec4 base = texture(effect_input, v_tex_coords);
+    vec3 glow = texture(tex, v_tex_coords).rgb * intensity;
+    f_color = vec4(base.rgb + glow, base.a);
+}
@@ -0,0 +1,27 @@
+#version 330 core
+
+uniform sampler2D tex;
+uniform vec2 resolution;
+uniform vec2 direction;
+uniform float radius;
+in vec2 v_tex_coords;
+out vec4 f_color;
+
+const int MAX_RADIUS = 32;
+
+void main() {
+    vec2 texel = direction / resolution;
+    int r = int(min(ceil(radius), float(MAX_RADIUS)));
+    float sigma = max(radius / 2.0, 0.001);
+    vec4 sum = vec4(0.0);
+    float total = 0.0;
+    for (int i = -MAX_RADIUS; i <= MAX_RADIUS; i++) {
+        if (i < -r || i > r) {
+            continue;
+        }
+        float weight = exp(-float(i * i) / (2.0 * sigma * sigma));
+        sum += texture(tex, v_tex_coords + texel * float(i)) * weight;
+        total += weight;
+    }
+    f_color = sum / total;
+}
@@ -0,0 +1,12 @@
+#version 330 core
+
+uniform sampler2D tex;
+uniform float threshold;
+in vec2 v_tex_coords;
+out vec4 f_color;
+
+void main() {
+    vec4 color = texture(tex, v_tex_coords);
+    float brightness = dot(color.rgb, vec3(0.2126, 0.7152, 0.0722));
+    f_color = brightness > threshold ? vec4(color.rgb, 1.0) : vec4(0.0, 0.0, 0.0, 1.0);
+}
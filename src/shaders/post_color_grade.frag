@@ -0,0 +1,27 @@
+#version 330 core
+
+// The LUT is a horizontal strip of `lut_size` slices, each `lut_size` pixels
+// square, with red increasing to the right, green increasing downward and
+// blue increasing from slice to slice (e.g. 256x16 for a 16 entry LUT).
+uniform sampler2D tex;
+uniform sampler2D lut;
+uniform float lut_size;
+uniform float intensity;
+in vec2 v_tex_coords;
+out vec4 f_color;
+
+vec3 lookup(vec3 color, float slice) {
+    vec2 pixel = color.rg * (lut_size - 1.0) + 0.5;
+    vec2 uv = vec2((slice * lut_size + pixel.x) / (lut_size * lut_size), pixel.y / lut_size);
+    return texture(lut, uv).rgb;
+}
+
+void main() {
+    vec4 color = texture(tex, v_tex_coords);
+    vec3 c = clamp(color.rgb, 0.0, 1.0);
+    float blue = c.b * (lut_size - 1.0);
+    float slice0 = floor(blue);
+    float slice1 = min(slice0 + 1.0, lut_size - 1.0);
+    vec3 graded = mix(lookup(c, slice0), lookup(c, slice1), blue - slice0);
+    f_color = vec4(mix(color.rgb, graded, intensity), color.a);
+}
@@ -0,0 +1,22 @@
+#version 330 core
+
+uniform sampler2D tex;
+uniform vec2 resolution;
+uniform float curvature;
+uniform float scanline_intensity;
+in vec2 v_tex_coords;
+out vec4 f_color;
+
+void main() {
+    vec2 uv = v_tex_coords * 2.0 - 1.0;
+    uv *= 1.0 + curvature * dot(uv.yx, uv.yx);
+    uv = uv * 0.5 + 0.5;
+    if (uv.x < 0.0 || uv.x > 1.0 || uv.y < 0.0 || uv.y > 1.0) {
+        f_color = vec4(0.0, 0.0, 0.0, 1.0);
+        return;
+    }
+    vec4 color = texture(tex, uv);
+    float scanline = 0.5 + 0.5 * sin(uv.y * resolution.y * 3.14159265);
+    color.rgb *= mix(1.0, scanline, scanline_intensity);
+    f_color = color;
+}
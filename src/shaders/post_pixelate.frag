@@ -0,0 +1,13 @@
+#version 330 core
+
+uniform sampler2D tex;
+uniform vec2 resolution;
+uniform float pixel_size;
+in vec2 v_tex_coords;
+out vec4 f_color;
+
+void main() {
+    vec2 cell = max(pixel_size, 1.0) / resolution;
+    vec2 uv = (floor(v_tex_coords / cell) + 0.5) * cell;
+    f_color = texture(tex, uv);
+}
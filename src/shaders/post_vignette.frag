@@ -0,0 +1,14 @@
+#version 330 core
+
+uniform sampler2D tex;
+uniform float strength;
+uniform float radius;
+in vec2 v_tex_coords;
+out vec4 f_color;
+
+void main() {
+    vec4 color = texture(tex, v_tex_coords);
+    float dist = distance(v_tex_coords, vec2(0.5));
+    float vignette = 1.0 - smoothstep(radius - 0.45, radius, dist);
+    f_color = vec4(color.rgb * mix(1.0, vignette, strength), color.a);
+}
@@ -0,0 +1,22 @@
+#version 330 core
+
+layout (location = 0) in vec2 position;
+layout (location = 1) in vec2 tex_coords;
+layout (location = 2) in vec4 color;
+
+// Positions are in map pixels; `camera` is the map position shown at the
+// top left of the viewport.
+uniform vec2 camera;
+uniform vec2 viewport_size;
+uniform bool flip_y;
+
+out vec2 v_tex_coords;
+out vec4 v_color;
+
+void main() {
+    vec2 p = position - camera;
+    float y = 1.0 - p.y * 2.0 / viewport_size.y;
+    gl_Position = vec4(p.x * 2.0 / viewport_size.x - 1.0, flip_y ? -y : y, 0.0, 1.0);
+    v_tex_coords = tex_coords;
+    v_color = color;
+}
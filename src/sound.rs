@@ -1,77 +1,2150 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt;
 use std::fs::File;
+use std::mem;
 use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering};
 use std::io::{BufReader, Cursor};
-use std::path::Path;
-use rodio::{Decoder, decoder::LoopedDecoder, OutputStream, OutputStreamHandle, Sink};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use rodio::Decoder;
+use rodio::buffer::SamplesBuffer;
 use rodio::source::{Buffered, Source};
 
+use super::audio_backend::{AudioBackend, AudioOutput};
+use super::audio_effects::{EffectChain, EffectSource};
+use super::rng::Rng;
+use super::types::Point;
+
+// Everything played through the engine is mixed at this rate, in stereo.
+pub(crate) const SAMPLE_RATE: u32 = 44100;
+const DEFAULT_MAX_VOICES: usize = 32;
+
+type BoxedSource = Box<dyn Source<Item = f32> + Send>;
+
+/// How a sound's audio is kept in memory.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum SoundMode {
+    /// The encoded file is kept and decoded again every time it plays;
+    /// looping plays decode their loop section when they start.
+    Compressed,
+    /// Decoded once at load; plays are cheap but use the most memory.
+    Decoded,
+    /// Read and decoded from disk while playing, for long music tracks.
+    Streamed,
+}
+
+enum SoundStorage {
+    Compressed(Arc<[u8]>),
+    // Every play clones the buffer, which shares the decoded frames
+    Decoded(Buffered<BoxedSource>),
+    Streamed(PathBuf),
+}
+
+impl SoundStorage {
+    fn mode(&self) -> SoundMode {
+        match self {
+            SoundStorage::Compressed(_) => SoundMode::Compressed,
+            SoundStorage::Decoded(_) => SoundMode::Decoded,
+            SoundStorage::Streamed(_) => SoundMode::Streamed,
+        }
+    }
+}
+
+#[derive(Copy, Clone)]
+struct Metadata {
+    channels: u16,
+    sample_rate: u32,
+    duration: Option<Duration>,
+}
+
+impl Metadata {
+    // Reads what the header gives, without decoding
+    fn probe(source: BoxedSource) -> Self {
+        Self {
+            channels: source.channels(),
+            sample_rate: source.sample_rate(),
+            duration: source.total_duration(),
+        }
+    }
+}
+
+fn frames_duration(frames: u64, sample_rate: u32) -> Duration {
+    Duration::from_secs_f64(frames as f64 / sample_rate.max(1) as f64)
+}
+
+// Shared by the clones of a sound, so its memory is counted once
+struct SoundData {
+    id: u64,
+    storage: SoundStorage,
+    metadata: Metadata,
+}
+
+impl SoundData {
+    fn new(storage: SoundStorage, metadata: Metadata, bytes: usize, label: String) -> Arc<Self> {
+        let id = NEXT_SOUND_ID.fetch_add(1, Ordering::Relaxed);
+        LIVE_SOUNDS.lock().unwrap().insert(id, SoundInfo { mode: storage.mode(), bytes, label });
+        Arc::new(Self { id, storage, metadata })
+    }
+}
+
+impl Drop for SoundData {
+    fn drop(&mut self) {
+        LIVE_SOUNDS.lock().unwrap().remove(&self.id);
+    }
+}
+
+#[derive(Clone)]
 pub struct Sound {
-    data: Arc<[u8]>,
+    data: Arc<SoundData>,
+    loop_points: Option<LoopPoints>,
+}
+
+impl Sound {
+    /// Loads an encoded sound: WAV or Ogg Vorbis, and MP3 or FLAC with the
+    /// `mp3` and `flac` features. Fails if the format is not recognized.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        Self::compressed(Arc::from(bytes), "bytes".to_string())
+    }
+
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, String> {
+        let path = path.as_ref();
+        let bytes = std::fs::read(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        Self::compressed(Arc::from(bytes), path.display().to_string()).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    fn compressed(bytes: Arc<[u8]>, label: String) -> Result<Self, String> {
+        // Only the header is read; counting would decode the whole sound
+        let metadata = Metadata::probe(open_bytes(&bytes)?);
+        let size = bytes.len();
+        Ok(Self {
+            data: SoundData::new(SoundStorage::Compressed(bytes), metadata, size, label),
+            loop_points: None,
+        })
+    }
+
+    pub fn from_file_with(path: impl AsRef<Path>, mode: SoundMode) -> Result<Self, String> {
+        let path = path.as_ref();
+        match mode {
+            SoundMode::Compressed => Self::from_file(path),
+            SoundMode::Decoded => {
+                let bytes = std::fs::read(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+                let source = open_bytes(&Arc::from(bytes)).map_err(|e| format!("{}: {}", path.display(), e))?;
+                Ok(Self {
+                    data: decoded(source, path.display().to_string()),
+                    loop_points: None,
+                })
+            }
+            SoundMode::Streamed => {
+                let metadata = Metadata::probe(open_file(path)?);
+                let storage = SoundStorage::Streamed(path.to_path_buf());
+                Ok(Self {
+                    data: SoundData::new(storage, metadata, 0, path.display().to_string()),
+                    loop_points: None,
+                })
+            }
+        }
+    }
+
+    /// Returns a copy of the sound that is decoded up front, so playing it
+    /// doesn't decode it again.
+    pub fn decode(&self) -> Result<Self, String> {
+        if let SoundStorage::Decoded(_) = self.data.storage {
+            return Ok(self.clone());
+        }
+        Ok(Self {
+            data: decoded(self.open()?, self.label()),
+            loop_points: self.loop_points,
+        })
+    }
+
+    /// Makes a sound from interleaved samples between -1.0 and 1.0.
+    pub fn from_samples(channels: u16, sample_rate: u32, samples: &[f32]) -> Self {
+        let source = SamplesBuffer::new(channels.max(1), sample_rate.max(1), samples.to_vec());
+        Self {
+            data: decoded(Box::new(source), "samples".to_string()),
+            loop_points: None,
+        }
+    }
+
+    pub fn mode(&self) -> SoundMode {
+        self.data.storage.mode()
+    }
+
+    /// Known for decoded sounds, and otherwise only if the file's header
+    /// records it (as WAV does); `decode` makes it known.
+    pub fn duration(&self) -> Option<Duration> {
+        self.data.metadata.duration
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.data.metadata.sample_rate
+    }
+
+    pub fn channels(&self) -> u16 {
+        self.data.metadata.channels
+    }
+
+    fn label(&self) -> String {
+        LIVE_SOUNDS.lock().unwrap().get(&self.data.id).map(|info| info.label.clone()).unwrap_or_default()
+    }
+
+    /// When played looping, the part before the loop start plays once and
+    /// then the loop section repeats.
+    pub fn with_loop_points(self, loop_points: LoopPoints) -> Self {
+        Self { loop_points: Some(loop_points), ..self }
+    }
+
+    pub fn loop_points(&self) -> Option<LoopPoints> {
+        self.loop_points
+    }
+
+    // Seconds
+    fn loop_start(&self) -> f64 {
+        let sample_rate = self.sample_rate().max(1);
+        self.loop_points.map_or(0.0, |points| points.start.frames(sample_rate) as f64 / sample_rate as f64)
+    }
+
+    // Frames at the sound's sample rate
+    fn loop_frames(&self) -> (u64, Option<u64>) {
+        let sample_rate = self.sample_rate();
+        match self.loop_points {
+            Some(points) => (points.start.frames(sample_rate), points.end.map(|end| end.frames(sample_rate))),
+            None => (0, None),
+        }
+    }
+
+    fn open(&self) -> Result<BoxedSource, String> {
+        match &self.data.storage {
+            SoundStorage::Compressed(bytes) => open_bytes(bytes),
+            SoundStorage::Decoded(buffered) => Ok(Box::new(buffered.clone())),
+            SoundStorage::Streamed(path) => open_file(path),
+        }
+    }
+
+    // Decoders can't seek, so this decodes up to `frame`
+    fn open_at(&self, frame: u64) -> Result<BoxedSource, String> {
+        let mut source = self.open()?;
+        skip_frames(&mut source, frame);
+        Ok(source)
+    }
+
+    // The part of the sound a looping voice repeats, decoded so that
+    // restarting the loop only clones it. None for streamed sounds, which
+    // are reopened by `SoundEngine::update` instead.
+    fn loop_section(&self) -> Result<Option<Buffered<BoxedSource>>, String> {
+        let (start, end) = self.loop_frames();
+        match &self.data.storage {
+            SoundStorage::Decoded(buffered) => {
+                let mut section = buffered.clone();
+                skip_frames(&mut section, start);
+                Ok(Some(section))
+            }
+            SoundStorage::Compressed(_) => {
+                let source = self.open_at(start)?;
+                let (channels, sample_rate) = (source.channels().max(1), source.sample_rate().max(1));
+                let samples: Vec<f32> = match end {
+                    Some(end) => source.take(end.saturating_sub(start) as usize * channels as usize).collect(),
+                    None => source.collect(),
+                };
+                let section: BoxedSource = Box::new(SamplesBuffer::new(channels, sample_rate, samples));
+                let section = section.buffered();
+                // Fill the shared buffer now rather than on the audio thread
+                section.clone().for_each(drop);
+                Ok(Some(section))
+            }
+            SoundStorage::Streamed(_) => Ok(None),
+        }
+    }
+}
+
+fn decoded(source: BoxedSource, label: String) -> Arc<SoundData> {
+    let channels = source.channels();
+    let sample_rate = source.sample_rate();
+    let buffered = source.buffered();
+    // Walking a clone decodes every frame into the shared buffer
+    let samples = buffered.clone().count();
+    let metadata = Metadata {
+        channels,
+        sample_rate,
+        duration: Some(frames_duration(samples as u64 / channels.max(1) as u64, sample_rate)),
+    };
+    SoundData::new(SoundStorage::Decoded(buffered), metadata, samples * std::mem::size_of::<f32>(), label)
+}
+
+fn open_bytes(bytes: &Arc<[u8]>) -> Result<BoxedSource, String> {
+    let decoder = Decoder::new(Cursor::new(Arc::clone(bytes))).map_err(|e| e.to_string())?;
+    Ok(Box::new(decoder.convert_samples()))
+}
+
+fn open_file(path: &Path) -> Result<BoxedSource, String> {
+    let file = File::open(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let decoder = Decoder::new(BufReader::new(file)).map_err(|e| format!("{}: {}", path.display(), e))?;
+    Ok(Box::new(decoder.convert_samples()))
+}
+
+// Memory report ============================================================
+
+#[derive(Debug, Clone)]
+pub struct SoundInfo {
+    pub mode: SoundMode,
+    /// Encoded bytes for compressed sounds, decoded samples for decoded ones.
+    pub bytes: usize,
+    pub label: String,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SoundReport {
+    pub sounds: Vec<SoundInfo>,
+}
+
+impl SoundReport {
+
+    pub fn count(&self, mode: SoundMode) -> usize {
+        self.sounds.iter().filter(|s| s.mode == mode).count()
+    }
+
+    pub fn bytes(&self, mode: SoundMode) -> usize {
+        self.sounds.iter().filter(|s| s.mode == mode).map(|s| s.bytes).sum()
+    }
+
+    pub fn total_bytes(&self) -> usize {
+        self.sounds.iter().map(|s| s.bytes).sum()
+    }
+}
+
+impl fmt::Display for SoundReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{} sounds, {} bytes", self.sounds.len(), self.total_bytes())?;
+        for s in &self.sounds {
+            writeln!(f, "  {:?} {:>12} bytes  {}", s.mode, s.bytes, s.label)?;
+        }
+        Ok(())
+    }
+}
+
+static LIVE_SOUNDS: Mutex<BTreeMap<u64, SoundInfo>> = Mutex::new(BTreeMap::new());
+static NEXT_SOUND_ID: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum SoundTime {
+    // Frames, i.e. samples per channel
+    Samples(u64),
+    Seconds(f32),
+}
+
+impl SoundTime {
+    fn frames(self, sample_rate: u32) -> u64 {
+        match self {
+            SoundTime::Samples(frames) => frames,
+            SoundTime::Seconds(seconds) => (seconds.max(0.0) as f64 * sample_rate as f64).round() as u64,
+        }
+    }
+}
+
+/// The section of a sound that repeats when it is played looping.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct LoopPoints {
+    start: SoundTime,
+    end: Option<SoundTime>,
+}
+
+impl LoopPoints {
+    /// Positions in samples per channel, at the sound's own sample rate.
+    /// Without an end, the loop runs to the end of the sound.
+    pub fn samples(start: u64, end: Option<u64>) -> Self {
+        Self {
+            start: SoundTime::Samples(start),
+            end: end.map(SoundTime::Samples),
+        }
+    }
+
+    pub fn seconds(start: f32, end: Option<f32>) -> Self {
+        Self {
+            start: SoundTime::Seconds(start),
+            end: end.map(SoundTime::Seconds),
+        }
+    }
+}
+
+// Voices ============================================================
+
+// An f32 that can be shared with the audio thread.
+pub(crate) struct AtomicF32(AtomicU32);
+
+impl AtomicF32 {
+    pub(crate) fn new(value: f32) -> Self {
+        Self(AtomicU32::new(value.to_bits()))
+    }
+
+    pub(crate) fn get(&self) -> f32 {
+        f32::from_bits(self.0.load(Ordering::Relaxed))
+    }
+
+    pub(crate) fn set(&self, value: f32) {
+        self.0.store(value.to_bits(), Ordering::Relaxed);
+    }
+}
+
+struct AtomicF64(AtomicU64);
+
+impl AtomicF64 {
+    fn new(value: f64) -> Self {
+        Self(AtomicU64::new(value.to_bits()))
+    }
+
+    fn get(&self) -> f64 {
+        f64::from_bits(self.0.load(Ordering::Relaxed))
+    }
+
+    fn set(&self, value: f64) {
+        self.0.store(value.to_bits(), Ordering::Relaxed);
+    }
+}
+
+struct VoiceControls {
+    volume: AtomicF32,
+    pitch: AtomicF32,
+    pan: AtomicF32,
+    // Set by the engine from the voice's position relative to the listener
+    positional: AtomicBool,
+    x: AtomicF32,
+    y: AtomicF32,
+    spatial_gain: AtomicF32,
+    spatial_pan: AtomicF32,
+    // Fade multiplier, updated by the voice as it moves towards the target
+    fade: AtomicF32,
+    fade_target: AtomicF32,
+    // Change of the fade per frame
+    fade_step: AtomicF32,
+    stop_after_fade: AtomicBool,
+    // Seconds into the sound, as mixed
+    playback_position: AtomicF64,
+    // A source already advanced to the seek target, and that frame
+    seek: Mutex<Option<(BoxedSource, u64)>>,
+    seeking: AtomicBool,
+    // Reported as the position until the voice applies the seek
+    seek_position: AtomicF64,
+    // Counts seeks, so jumps in the position can be told from loop wraps
+    seeks: AtomicU64,
+    // A streamed sound reopened at its loop start, and whether the voice
+    // used it up and needs another
+    loop_source: Mutex<Option<BoxedSource>>,
+    wants_loop_source: AtomicBool,
+    paused: AtomicBool,
+    stopped: AtomicBool,
+    finished: AtomicBool,
+}
+
+/// Controls a sound started with `SoundEngine::play`. Dropping the handle
+/// does not stop the sound.
+#[derive(Clone)]
+pub struct SoundHandle {
+    controls: Arc<VoiceControls>,
+    effects: EffectChain,
+    // None for streams
+    sound: Option<Sound>,
+}
+
+impl SoundHandle {
+
+    fn new(options: &PlayOptions, effects: EffectChain, sound: Option<Sound>) -> Self {
+        let handle = Self {
+            effects,
+            sound,
+            controls: Arc::new(VoiceControls {
+                volume: AtomicF32::new(options.volume),
+                pitch: AtomicF32::new(options.pitch),
+                pan: AtomicF32::new(options.pan),
+                positional: AtomicBool::new(false),
+                x: AtomicF32::new(0.0),
+                y: AtomicF32::new(0.0),
+                spatial_gain: AtomicF32::new(1.0),
+                spatial_pan: AtomicF32::new(0.0),
+                fade: AtomicF32::new(1.0),
+                fade_target: AtomicF32::new(1.0),
+                fade_step: AtomicF32::new(0.0),
+                stop_after_fade: AtomicBool::new(false),
+                playback_position: AtomicF64::new(0.0),
+                seek: Mutex::new(None),
+                seeking: AtomicBool::new(false),
+                seek_position: AtomicF64::new(0.0),
+                seeks: AtomicU64::new(0),
+                loop_source: Mutex::new(None),
+                wants_loop_source: AtomicBool::new(false),
+                paused: AtomicBool::new(false),
+                stopped: AtomicBool::new(false),
+                finished: AtomicBool::new(false),
+            }),
+        };
+        if let Some(position) = options.position {
+            handle.set_position(position);
+        }
+        if options.fade_in > 0.0 {
+            handle.controls.fade.set(0.0);
+            handle.fade_to(1.0, options.fade_in);
+        }
+        handle
+    }
+
+    // A handle for a sound that could not be played
+    fn finished() -> Self {
+        let handle = Self::new(&PlayOptions::default(), EffectChain::new(), None);
+        handle.controls.finished.store(true, Ordering::Relaxed);
+        handle
+    }
+
+    pub fn stop(&self) {
+        self.controls.stopped.store(true, Ordering::Relaxed);
+    }
+
+    /// Fades the sound to `level` times its volume over `seconds`.
+    pub fn fade_to(&self, level: f32, seconds: f32) {
+        let level = level.max(0.0);
+        let distance = (level - self.controls.fade.get()).abs();
+        let step = if seconds > 0.0 { distance / (seconds * SAMPLE_RATE as f32) } else { f32::INFINITY };
+        self.controls.stop_after_fade.store(false, Ordering::Relaxed);
+        self.controls.fade_step.set(step);
+        self.controls.fade_target.set(level);
+    }
+
+    /// Fades the sound out over `seconds` and then stops it.
+    pub fn fade_out(&self, seconds: f32) {
+        self.fade_to(0.0, seconds);
+        self.controls.stop_after_fade.store(true, Ordering::Relaxed);
+    }
+
+    pub fn pause(&self) {
+        self.controls.paused.store(true, Ordering::Relaxed);
+    }
+
+    pub fn resume(&self) {
+        self.controls.paused.store(false, Ordering::Relaxed);
+    }
+
+    pub fn set_volume(&self, volume: f32) {
+        self.controls.volume.set(volume.max(0.0));
+    }
+
+    pub fn volume(&self) -> f32 {
+        self.controls.volume.get()
+    }
+
+    /// Playback speed; 2.0 plays an octave higher and twice as fast.
+    pub fn set_pitch(&self, pitch: f32) {
+        self.controls.pitch.set(pitch.max(0.0));
+    }
+
+    pub fn pitch(&self) -> f32 {
+        self.controls.pitch.get()
+    }
+
+    /// Stereo balance from -1.0 (left) to 1.0 (right).
+    pub fn set_pan(&self, pan: f32) {
+        self.controls.pan.set(pan.clamp(-1.0, 1.0));
+    }
+
+    pub fn pan(&self) -> f32 {
+        self.controls.pan.get()
+    }
+
+    /// Moves a positional sound, or makes the sound positional. Its volume
+    /// and pan follow on the next `SoundEngine::update`.
+    pub fn set_position(&self, position: Point) {
+        self.controls.x.set(position.x);
+        self.controls.y.set(position.y);
+        self.controls.positional.store(true, Ordering::Relaxed);
+    }
+
+    pub fn position(&self) -> Option<Point> {
+        let controls = &self.controls;
+        controls.positional.load(Ordering::Relaxed).then(|| Point::new(controls.x.get(), controls.y.get()))
+    }
+
+    /// Seconds into the sound that have been mixed. Sounds reach the
+    /// speakers `SoundEngine::latency` later.
+    pub fn playback_position(&self) -> f64 {
+        let controls = &self.controls;
+        if controls.seeking.load(Ordering::Acquire) {
+            controls.seek_position.get()
+        } else {
+            controls.playback_position.get()
+        }
+    }
+
+    /// Jumps to `seconds` into the sound. Compressed sounds are decoded up
+    /// to that point, on the calling thread.
+    pub fn seek(&self, seconds: f64) -> Result<(), String> {
+        let sound = self.sound.as_ref().ok_or("Streams can't seek")?;
+        let frame = (seconds.max(0.0) * sound.sample_rate() as f64) as u64;
+        let source = sound.open_at(frame)?;
+        *self.controls.seek.lock().unwrap() = Some((source, frame));
+        self.controls.seek_position.set(seconds.max(0.0));
+        self.controls.seeks.fetch_add(1, Ordering::Relaxed);
+        self.controls.seeking.store(true, Ordering::Release);
+        Ok(())
+    }
+
+    // Reopens a looping streamed sound at its loop start if the voice asked
+    // for it, so the audio thread never opens files or decodes to get there
+    fn prepare_loop_source(&self) {
+        if !self.controls.wants_loop_source.swap(false, Ordering::Relaxed) {
+            return;
+        }
+        let Some(sound) = &self.sound else { return };
+        match sound.open_at(sound.loop_frames().0) {
+            Ok(source) => *self.controls.loop_source.lock().unwrap() = Some(source),
+            Err(_) => self.stop(),
+        }
+    }
+
+    /// The effects applied to this sound only, adjustable while it plays.
+    pub fn effects(&self) -> &EffectChain {
+        &self.effects
+    }
+
+    // Volume after distance attenuation
+    fn audible_volume(&self) -> f32 {
+        self.controls.volume.get() * self.controls.spatial_gain.get()
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.controls.paused.load(Ordering::Relaxed) && !self.is_finished()
+    }
+
+    pub fn is_playing(&self) -> bool {
+        !self.is_finished() && !self.controls.paused.load(Ordering::Relaxed)
+    }
+
+    /// Whether the sound ended or was stopped.
+    pub fn is_finished(&self) -> bool {
+        self.controls.finished.load(Ordering::Relaxed) || self.controls.stopped.load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct PlayOptions {
+    pub volume: f32,
+    pub pitch: f32,
+    pub pan: f32,
+    /// When all voices are busy, a new sound replaces the lowest priority
+    /// voice, if its priority is not higher than the new sound's.
+    pub priority: i32,
+    pub looping: bool,
+    pub bus: Bus,
+    /// Seconds to fade in from silence.
+    pub fade_in: f32,
+    /// Attenuates and pans the sound relative to the listener.
+    pub position: Option<Point>,
+}
+
+impl Default for PlayOptions {
+    fn default() -> Self {
+        Self {
+            volume: 1.0,
+            pitch: 1.0,
+            pan: 0.0,
+            priority: 0,
+            looping: false,
+            bus: Bus::Sfx,
+            fade_in: 0.0,
+            position: None,
+        }
+    }
+}
+
+impl PlayOptions {
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn volume(self, volume: f32) -> Self {
+        Self { volume, ..self }
+    }
+
+    pub fn pitch(self, pitch: f32) -> Self {
+        Self { pitch, ..self }
+    }
+
+    pub fn pan(self, pan: f32) -> Self {
+        Self { pan, ..self }
+    }
+
+    pub fn priority(self, priority: i32) -> Self {
+        Self { priority, ..self }
+    }
+
+    pub fn looping(self, looping: bool) -> Self {
+        Self { looping, ..self }
+    }
+
+    pub fn bus(self, bus: Bus) -> Self {
+        Self { bus, ..self }
+    }
+
+    pub fn fade_in(self, fade_in: f32) -> Self {
+        Self { fade_in, ..self }
+    }
+
+    pub fn position(self, position: Point) -> Self {
+        Self { position: Some(position), ..self }
+    }
+}
+
+// Plays a sound on the audio thread, resampled to the mixer rate (taking
+// the pitch into account) and converted to stereo.
+struct Voice {
+    source: BoxedSource,
+    controls: Arc<VoiceControls>,
+    looping: bool,
+    // Cloned when the loop restarts; None for streamed sounds, which wait
+    // for `VoiceControls::loop_source` instead
+    loop_section: Option<Buffered<BoxedSource>>,
+    awaiting_loop: bool,
+    // Frames read from the source so far
+    position: u64,
+    loop_start: u64,
+    loop_end: Option<u64>,
+    // Frames of the source around the current position, for interpolation
+    previous: [f32; 2],
+    next: [f32; 2],
+    fraction: f32,
+    fade: f32,
+    spatial_gain: f32,
+    // The source ran out, and `next` repeats the last frame so it still plays
+    exhausted: bool,
+    ended: bool,
+    right: Option<f32>,
+}
+
+impl Voice {
+    // `sound` is None for streams, which can't loop
+    fn new(
+        sound: Option<&Sound>,
+        source: BoxedSource,
+        controls: Arc<VoiceControls>,
+        looping: bool,
+        loop_section: Option<Buffered<BoxedSource>>,
+    ) -> Self {
+        let (loop_start, loop_end) = sound.map_or((0, None), Sound::loop_frames);
+        let mut voice = Self {
+            looping: looping && sound.is_some(),
+            loop_section,
+            awaiting_loop: false,
+            source,
+            fade: controls.fade.get(),
+            spatial_gain: controls.spatial_gain.get(),
+            controls,
+            position: 0,
+            loop_start,
+            loop_end,
+            previous: [0.0; 2],
+            next: [0.0; 2],
+            fraction: 0.0,
+            exhausted: false,
+            ended: false,
+            right: None,
+        };
+        voice.prime();
+        voice
+    }
+
+    // Reads the first two frames, after starting or seeking
+    fn prime(&mut self) {
+        self.fraction = 0.0;
+        self.exhausted = false;
+        self.ended = false;
+        match self.read_frame() {
+            Some(frame) => self.next = frame,
+            None => self.ended = true,
+        }
+        self.pull();
+    }
+
+    // Moves on by a frame of the source
+    fn pull(&mut self) {
+        self.previous = self.next;
+        if self.exhausted {
+            self.ended = true;
+            return;
+        }
+        match self.read_frame() {
+            Some(frame) => self.next = frame,
+            None => self.exhausted = true,
+        }
+    }
+
+    fn read_frame(&mut self) -> Option<[f32; 2]> {
+        let at_loop_end = self.loop_end.is_some_and(|end| self.position >= end);
+        if !(self.awaiting_loop || self.looping && at_loop_end) {
+            if let Some(frame) = read_stereo_frame(&mut self.source) {
+                self.position += 1;
+                return Some(frame);
+            }
+            if !self.looping {
+                return None;
+            }
+        }
+        self.restart_loop()
+    }
+
+    // Switches to a source at the loop start that was prepared on the game
+    // thread, since decoding up to it here could take too long.
+    fn restart_loop(&mut self) -> Option<[f32; 2]> {
+        let source: BoxedSource = match &self.loop_section {
+            Some(section) => Box::new(section.clone()),
+            None => match self.controls.loop_source.try_lock().ok().and_then(|mut source| source.take()) {
+                Some(source) => {
+                    self.controls.wants_loop_source.store(true, Ordering::Relaxed);
+                    source
+                }
+                None => {
+                    // Not reopened yet; play silence until the next update
+                    self.awaiting_loop = true;
+                    return Some([0.0; 2]);
+                }
+            },
+        };
+        self.awaiting_loop = false;
+        self.source = source;
+        self.position = self.loop_start + 1;
+        read_stereo_frame(&mut self.source)
+    }
+
+    fn apply_seek(&mut self) {
+        // Never block the audio thread; the flag stays set to try again
+        let pending = match self.controls.seek.try_lock() {
+            Ok(mut seek) => {
+                let pending = seek.take();
+                if let Some((source, frame)) = &pending {
+                    let seconds = *frame as f64 / source.sample_rate().max(1) as f64;
+                    self.controls.playback_position.set(seconds);
+                }
+                self.controls.seeking.store(false, Ordering::Release);
+                pending
+            }
+            Err(_) => return,
+        };
+        let Some((source, frame)) = pending else { return };
+        self.source = source;
+        self.position = frame;
+        self.awaiting_loop = false;
+        self.prime();
+    }
+
+    fn update_fade(&mut self) {
+        let controls = &self.controls;
+        let target = controls.fade_target.get();
+        if self.fade == target {
+            if target == 0.0 && controls.stop_after_fade.load(Ordering::Relaxed) {
+                controls.stopped.store(true, Ordering::Relaxed);
+            }
+            return;
+        }
+        let step = controls.fade_step.get();
+        self.fade = if self.fade < target { (self.fade + step).min(target) } else { (self.fade - step).max(target) };
+        controls.fade.set(self.fade);
+    }
+}
+
+fn read_stereo_frame(source: &mut impl Source<Item = f32>) -> Option<[f32; 2]> {
+    let channels = source.channels();
+    let left = source.next()?;
+    let right = if channels > 1 { source.next().unwrap_or(left) } else { left };
+    for _ in 2..channels {
+        source.next();
+    }
+    Some([left, right])
+}
+
+fn skip_frames(source: &mut impl Source<Item = f32>, frames: u64) {
+    for _ in 0..frames {
+        if read_stereo_frame(source).is_none() {
+            break;
+        }
+    }
+}
+
+impl Iterator for Voice {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        if let Some(right) = self.right.take() {
+            return Some(right);
+        }
+        let controls = &self.controls;
+        if controls.stopped.load(Ordering::Relaxed) || self.ended {
+            controls.finished.store(true, Ordering::Relaxed);
+            return None;
+        }
+        if controls.seeking.load(Ordering::Acquire) {
+            self.apply_seek();
+        }
+        let controls = &self.controls;
+        if controls.paused.load(Ordering::Relaxed) {
+            self.update_fade();
+            self.right = Some(0.0);
+            return Some(0.0);
+        }
+
+        let t = self.fraction;
+        let mut frame = [0.0; 2];
+        for (c, sample) in frame.iter_mut().enumerate() {
+            *sample = self.previous[c] + (self.next[c] - self.previous[c]) * t;
+        }
+
+        // Positions are only updated once per frame, so smooth the gain
+        self.spatial_gain += (controls.spatial_gain.get() - self.spatial_gain) * 0.005;
+        let volume = controls.volume.get() * self.fade * self.spatial_gain;
+        let pan = (controls.pan.get() + controls.spatial_pan.get()).clamp(-1.0, 1.0);
+        let left = frame[0] * volume * (1.0 - pan).min(1.0);
+        let right = frame[1] * volume * (1.0 + pan).min(1.0);
+
+        let step = self.source.sample_rate() as f32 * controls.pitch.get() / SAMPLE_RATE as f32;
+        self.fraction += step;
+        while self.fraction >= 1.0 && !self.ended {
+            self.fraction -= 1.0;
+            self.pull();
+        }
+
+        // `next` is the frame after the one being played
+        let frame = self.position.saturating_sub(2) as f64 + self.fraction as f64;
+        self.controls.playback_position.set(frame / self.source.sample_rate().max(1) as f64);
+        self.update_fade();
+        self.right = Some(right);
+        Some(left)
+    }
+}
+
+impl Source for Voice {
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        2
+    }
+
+    fn sample_rate(&self) -> u32 {
+        SAMPLE_RATE
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+impl Drop for Voice {
+    fn drop(&mut self) {
+        self.controls.finished.store(true, Ordering::Relaxed);
+    }
+}
+
+// Mixer ============================================================
+
+// Inputs must be stereo at `SAMPLE_RATE`. Unlike rodio's dynamic mixer, the
+// output never ends, so sources can be added after everything went quiet.
+struct Mixer {
+    inputs: Vec<BoxedSource>,
+    pending: Arc<Mutex<Vec<BoxedSource>>>,
+    // Whether the next sample is a left one
+    left: bool,
+}
+
+#[derive(Clone)]
+struct MixerInput {
+    pending: Arc<Mutex<Vec<BoxedSource>>>,
+}
+
+impl MixerInput {
+    fn add(&self, source: impl Source<Item = f32> + Send + 'static) {
+        self.pending.lock().unwrap().push(Box::new(source));
+    }
+}
+
+fn mixer() -> (MixerInput, Mixer) {
+    let pending = Arc::new(Mutex::new(Vec::new()));
+    let input = MixerInput { pending: Arc::clone(&pending) };
+    (input, Mixer { inputs: Vec::new(), pending, left: true })
+}
+
+impl Iterator for Mixer {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        if self.left {
+            // Only start new inputs on frame boundaries
+            if let Ok(mut pending) = self.pending.try_lock() {
+                self.inputs.append(&mut pending);
+            }
+        }
+        self.left = !self.left;
+
+        let mut sum = 0.0;
+        self.inputs.retain_mut(|input| match input.next() {
+            Some(sample) => {
+                sum += sample;
+                true
+            }
+            None => false,
+        });
+        Some(sum)
+    }
+}
+
+impl Source for Mixer {
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        2
+    }
+
+    fn sample_rate(&self) -> u32 {
+        SAMPLE_RATE
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+// Buses ============================================================
+
+/// Mixer channels that sounds are routed to. Every bus feeds into `Master`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Bus {
+    Master,
+    Music,
+    Sfx,
+    Voice,
+    Ui,
+}
+
+impl Bus {
+    pub const ALL: [Bus; 5] = [Bus::Master, Bus::Music, Bus::Sfx, Bus::Voice, Bus::Ui];
+
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+/// Lowers the music bus while sounds play on the voice bus.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Ducking {
+    /// Music volume multiplier while ducked.
+    pub volume: f32,
+    /// Seconds to fade down to `volume`.
+    pub attack: f32,
+    /// Seconds to fade back up once the voice bus is quiet.
+    pub release: f32,
+}
+
+impl Default for Ducking {
+    fn default() -> Self {
+        Self {
+            volume: 0.3,
+            attack: 0.2,
+            release: 0.6,
+        }
+    }
+}
+
+struct BusControls {
+    volume: AtomicF32,
+    muted: AtomicBool,
+    duck: AtomicF32,
+    // Seconds for `duck` to go all the way between 0 and 1
+    duck_time: AtomicF32,
+}
+
+// Applies a bus' gain to the output of its mixer. Gain changes are smoothed
+// so volume sliders and ducking don't click.
+struct BusSource {
+    mixer: EffectSource<Mixer>,
+    controls: Arc<BusControls>,
+    gain: f32,
+    duck: f32,
+    right: Option<f32>,
+}
+
+impl Iterator for BusSource {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        if let Some(gain) = self.right.take() {
+            return self.mixer.next().map(|sample| sample * gain);
+        }
+        let controls = &self.controls;
+
+        let target = controls.duck.get();
+        let step = 1.0 / (controls.duck_time.get().max(0.001) * SAMPLE_RATE as f32);
+        self.duck = if self.duck < target { (self.duck + step).min(target) } else { (self.duck - step).max(target) };
+
+        let target = if controls.muted.load(Ordering::Relaxed) { 0.0 } else { controls.volume.get() * self.duck };
+        self.gain += (target - self.gain) * 0.005;
+        self.right = Some(self.gain);
+        self.mixer.next().map(|sample| sample * self.gain)
+    }
+}
+
+impl Source for BusSource {
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        2
+    }
+
+    fn sample_rate(&self) -> u32 {
+        SAMPLE_RATE
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
 }
 
-impl Sound {
-    pub fn from_bytes(bytes: &[u8]) -> Self {
+struct BusChannel {
+    controls: Arc<BusControls>,
+    effects: EffectChain,
+    input: MixerInput,
+}
+
+fn bus_channel() -> (BusChannel, BusSource) {
+    let controls = Arc::new(BusControls {
+        volume: AtomicF32::new(1.0),
+        muted: AtomicBool::new(false),
+        duck: AtomicF32::new(1.0),
+        duck_time: AtomicF32::new(0.0),
+    });
+    let effects = EffectChain::new();
+    let (input, mixer) = mixer();
+    let source = BusSource {
+        mixer: EffectSource::new(mixer, effects.clone()),
+        controls: Arc::clone(&controls),
+        gain: 1.0,
+        duck: 1.0,
+        right: None,
+    };
+    (BusChannel { controls, effects, input }, source)
+}
+
+// Streams ============================================================
+
+// Frames a stream takes from its queue at once, so the audio thread only
+// takes the lock every few milliseconds
+const STREAM_BLOCK: usize = 256;
+
+struct StreamQueue {
+    samples: Mutex<VecDeque<f32>>,
+    closed: AtomicBool,
+    // Samples taken from the queue that the audio thread hasn't played yet
+    taken: AtomicUsize,
+    // Set by `AudioStream::clear` for the audio thread to drop those too
+    cleared: AtomicBool,
+}
+
+/// Plays samples pushed while it runs, e.g. from an emulator or a synth
+/// updated every frame. Dropping the stream lets the queued samples finish.
+pub struct AudioStream {
+    queue: Arc<StreamQueue>,
+    channels: u16,
+    sample_rate: u32,
+    handle: SoundHandle,
+}
+
+impl AudioStream {
+
+    /// Queues interleaved samples, in whole frames. If the queue runs dry
+    /// the stream plays silence until more samples arrive.
+    pub fn push(&self, samples: &[f32]) {
+        self.queue.samples.lock().unwrap().extend(samples);
+    }
+
+    /// Frames waiting to be played; keep this above a frame's worth to
+    /// avoid gaps.
+    pub fn queued_frames(&self) -> usize {
+        let queued = self.queue.samples.lock().unwrap().len() + self.queue.taken.load(Ordering::Relaxed);
+        queued / self.channels as usize
+    }
+
+    pub fn clear(&self) {
+        self.queue.samples.lock().unwrap().clear();
+        self.queue.cleared.store(true, Ordering::Relaxed);
+    }
+
+    /// Ends the stream once the queued samples have played.
+    pub fn close(&self) {
+        self.queue.closed.store(true, Ordering::Release);
+    }
+
+    pub fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    pub fn handle(&self) -> &SoundHandle {
+        &self.handle
+    }
+}
+
+impl Drop for AudioStream {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+struct StreamSource {
+    queue: Arc<StreamQueue>,
+    channels: u16,
+    sample_rate: u32,
+    // Samples taken from the queue, up to a block
+    buffer: VecDeque<f32>,
+    // Sample index within the current frame
+    channel: usize,
+    // Silent samples left to finish the current frame
+    silence: usize,
+}
+
+impl StreamSource {
+    fn new(queue: Arc<StreamQueue>, channels: u16, sample_rate: u32) -> Self {
+        Self {
+            queue,
+            channels,
+            sample_rate,
+            buffer: VecDeque::with_capacity(STREAM_BLOCK * channels as usize),
+            channel: 0,
+            silence: 0,
+        }
+    }
+
+    // Moves the next block from the queue to the buffer. Returns false if
+    // the stream is closed and everything was played.
+    fn refill(&mut self) -> bool {
+        // Read before the queue, so samples pushed before closing are seen
+        let closed = self.queue.closed.load(Ordering::Acquire);
+        // Never block the audio thread; play silence if the game holds the lock
+        let Ok(mut samples) = self.queue.samples.try_lock() else { return true };
+        let count = samples.len().min(self.buffer.capacity());
+        self.buffer.extend(samples.drain(..count));
+        !(closed && self.buffer.is_empty())
+    }
+}
+
+impl Iterator for StreamSource {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        if self.queue.cleared.load(Ordering::Relaxed) {
+            self.queue.cleared.store(false, Ordering::Relaxed);
+            self.buffer.clear();
+        }
+        if self.silence == 0 && self.buffer.is_empty() {
+            if !self.refill() {
+                return None;
+            }
+            if self.buffer.is_empty() {
+                // Nothing queued, or the lock was busy; finish the frame
+                // silently so the channels stay in step
+                self.silence = self.channels as usize - self.channel;
+            }
+        }
+        let sample = if self.silence > 0 {
+            self.silence -= 1;
+            0.0
+        } else {
+            self.buffer.pop_front().unwrap_or(0.0)
+        };
+        self.queue.taken.store(self.buffer.len(), Ordering::Relaxed);
+        self.channel = (self.channel + 1) % self.channels as usize;
+        Some(sample)
+    }
+}
+
+impl Source for StreamSource {
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+// Playlists ============================================================
+
+pub struct Playlist {
+    pub tracks: Vec<Sound>,
+    pub shuffle: bool,
+    /// Start over after the last track; shuffled playlists are reshuffled.
+    pub repeat: bool,
+}
+
+impl Playlist {
+
+    pub fn new(tracks: Vec<Sound>) -> Self {
         Self {
-            data: Arc::from(bytes),
+            tracks,
+            shuffle: false,
+            repeat: true,
         }
     }
 
-    pub fn from_file(path: impl AsRef<Path>) -> Self {
+    pub fn shuffle(self, shuffle: bool) -> Self {
+        Self { shuffle, ..self }
+    }
+
+    pub fn repeat(self, repeat: bool) -> Self {
+        Self { repeat, ..self }
+    }
+}
+
+struct PlaylistState {
+    playlist: Playlist,
+    // Indices into the tracks, in play order
+    order: Vec<usize>,
+    index: usize,
+    rng: Rng,
+}
+
+// Positional audio ============================================================
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum AttenuationModel {
+    /// Fades linearly to silence at the max distance.
+    Linear,
+    /// Like sound in open air: `min / (min + rolloff * (distance - min))`.
+    Inverse,
+    /// `(distance / min) ^ -rolloff`
+    Exponential,
+}
+
+/// How positional sounds are heard from the listener. Distances are in the
+/// same units as positions, usually world pixels.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct SpatialConfig {
+    pub model: AttenuationModel,
+    /// Sounds closer than this play at full volume.
+    pub min_distance: f32,
+    /// Sounds are not attenuated further past this distance.
+    pub max_distance: f32,
+    pub rolloff: f32,
+    /// Horizontal offset at which a sound is panned fully to one side; 0
+    /// disables panning.
+    pub pan_distance: f32,
+}
+
+impl Default for SpatialConfig {
+    fn default() -> Self {
         Self {
-            data: Arc::from(std::fs::read(path).unwrap()),
+            model: AttenuationModel::Linear,
+            min_distance: 32.0,
+            max_distance: 800.0,
+            rolloff: 1.0,
+            pan_distance: 400.0,
         }
     }
 }
 
+impl SpatialConfig {
+    pub fn gain(&self, distance: f32) -> f32 {
+        let min = self.min_distance.max(0.001);
+        let max = self.max_distance.max(min);
+        let distance = distance.clamp(min, max);
+        let gain = match self.model {
+            AttenuationModel::Linear if max > min => 1.0 - self.rolloff * (distance - min) / (max - min),
+            AttenuationModel::Linear => 1.0,
+            AttenuationModel::Inverse => min / (min + self.rolloff * (distance - min)),
+            AttenuationModel::Exponential => (distance / min).powf(-self.rolloff),
+        };
+        gain.clamp(0.0, 1.0)
+    }
+}
+
+// Sound engine ============================================================
+
+struct VoiceSlot {
+    handle: SoundHandle,
+    priority: i32,
+    bus: Bus,
+    // Order in which voices were started, for stealing the oldest
+    started: u64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MusicEvent {
+    /// A marker set with `SoundEngine::set_music_markers` was reached.
+    Marker(String),
+    /// The music reached this beat, counting from 0, of the tempo set with
+    /// `SoundEngine::set_music_tempo`.
+    Beat(u64),
+}
+
+/// A sound started through the engine, recorded when the play log is on.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlayEvent {
+    /// The file the sound was loaded from, or how it was made
+    pub label: String,
+    pub bus: Bus,
+    /// Seconds of audio output before the sound started
+    pub time: f64,
+}
+
 pub struct SoundEngine {
-    _stream: OutputStream,
-    _stream_handle: OutputStreamHandle,
-    sinks: Vec<Sink>,
-    next_sink: usize,
+    output: AudioOutput,
+    backend: AudioBackend,
+    // Why the requested backend isn't the one in use
+    backend_error: Option<String>,
+    play_errors: Vec<String>,
+    play_log: Option<Vec<PlayEvent>>,
+    buses: Vec<BusChannel>,
+    music: Option<SoundHandle>,
+    playlist: Option<PlaylistState>,
+    listener: Point,
+    spatial: SpatialConfig,
+    ducking: Option<Ducking>,
+    music_markers: Vec<(f64, String)>,
+    // Beats per minute and the time of the first beat
+    music_tempo: Option<(f64, f64)>,
+    music_events: Vec<MusicEvent>,
+    last_music_position: Option<f64>,
+    // Seeks of the music seen by the last update
+    music_seeks: u64,
+    voices: Vec<VoiceSlot>,
+    // Streams and the bus each plays on
+    streams: Vec<(SoundHandle, Bus)>,
+    max_voices: usize,
+    voices_started: u64,
+}
+
+impl Default for SoundEngine {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl SoundEngine {
+    /// Opens the backend named by the PGFX_AUDIO environment variable
+    /// ("device", "null" or "wav:<path>"), or the default device. Without a
+    /// device, audio goes to the null backend; `backend_error` tells why.
     pub fn new() -> Self {
-        let (_stream, _stream_handle) = OutputStream::try_default().unwrap();
-        let mut sinks = Vec::new();
-        for _ in 0..8 {
-            sinks.push(Sink::try_new(&_stream_handle).unwrap());
+        let (backend, env_error) = match AudioBackend::from_env() {
+            Ok(backend) => (backend, None),
+            Err(e) => (None, Some(e)),
+        };
+        let backend = backend.unwrap_or(AudioBackend::Device);
+        let mut engine = Self::with_backend(backend).unwrap_or_else(|e| {
+            let mut engine = Self::open(AudioBackend::Null).unwrap();
+            engine.backend_error = Some(format!("Failed to open audio output, audio is disabled: {}", e));
+            engine
+        });
+        engine.backend_error = env_error.or(engine.backend_error);
+        engine
+    }
+
+    /// Opens `backend`. The null and WAV backends start with the play log
+    /// on, so headless tests can check what was played.
+    pub fn with_backend(backend: AudioBackend) -> Result<Self, String> {
+        let mut engine = Self::open(backend)?;
+        if engine.backend != AudioBackend::Device {
+            engine.set_play_log(true);
+        }
+        Ok(engine)
+    }
+
+    fn open(backend: AudioBackend) -> Result<Self, String> {
+        let (master, master_source) = bus_channel();
+        let mut buses = vec![master];
+        for _ in &Bus::ALL[1..] {
+            let (bus, source) = bus_channel();
+            buses[0].input.add(source);
+            buses.push(bus);
         }
+        let output = AudioOutput::open(&backend, master_source)?;
 
-        Self {
-            _stream,
-            _stream_handle,
-            sinks,
-            next_sink: 1,
+        Ok(Self {
+            output,
+            backend,
+            backend_error: None,
+            play_errors: Vec::new(),
+            play_log: None,
+            buses,
+            music: None,
+            playlist: None,
+            listener: Point::ZERO,
+            spatial: SpatialConfig::default(),
+            ducking: Some(Ducking::default()),
+            music_markers: Vec::new(),
+            music_tempo: None,
+            music_events: Vec::new(),
+            last_music_position: None,
+            music_seeks: 0,
+            voices: Vec::new(),
+            streams: Vec::new(),
+            max_voices: DEFAULT_MAX_VOICES,
+            voices_started: 0,
+        })
+    }
+
+    pub fn backend(&self) -> &AudioBackend {
+        &self.backend
+    }
+
+    /// Why audio isn't going where it was asked to: an unknown PGFX_AUDIO
+    /// value, an output device that failed to open, or a failed WAV write.
+    pub fn backend_error(&self) -> Option<String> {
+        self.output.error().or_else(|| self.backend_error.clone())
+    }
+
+    /// Why sounds failed to start since the last call. Sounds that fail
+    /// return a finished handle, and failed music leaves no music playing.
+    pub fn take_play_errors(&mut self) -> Vec<String> {
+        mem::take(&mut self.play_errors)
+    }
+
+    /// Seconds of audio the backend has consumed.
+    pub fn time(&self) -> f64 {
+        self.output.frames() as f64 / SAMPLE_RATE as f64
+    }
+
+    /// Estimated delay between mixing audio and hearing it.
+    pub fn latency(&self) -> Duration {
+        self.output.latency()
+    }
+
+    // Play log ========================================
+
+    pub fn set_play_log(&mut self, enabled: bool) {
+        if enabled != self.play_log.is_some() {
+            self.play_log = enabled.then(Vec::new);
+        }
+    }
+
+    /// Sounds started since the log was enabled or cleared, in order.
+    pub fn play_log(&self) -> &[PlayEvent] {
+        self.play_log.as_deref().unwrap_or_default()
+    }
+
+    pub fn clear_play_log(&mut self) {
+        if let Some(log) = &mut self.play_log {
+            log.clear();
+        }
+    }
+
+    fn log_play(&mut self, sound: Option<&Sound>, bus: Bus) {
+        let time = self.time();
+        if let Some(log) = &mut self.play_log {
+            let label = sound.map_or("stream".to_string(), |sound| sound.label());
+            log.push(PlayEvent { label, bus, time });
+        }
+    }
+
+    // Voices ========================================
+
+    /// Sets how many sounds can play at once; see `PlayOptions::priority`.
+    pub fn set_max_voices(&mut self, max_voices: usize) {
+        self.max_voices = max_voices.max(1);
+    }
+
+    pub fn max_voices(&self) -> usize {
+        self.max_voices
+    }
+
+    /// Number of sounds currently playing or paused.
+    pub fn active_voices(&self) -> usize {
+        self.voices.iter().filter(|voice| !voice.handle.is_finished()).count()
+    }
+
+    pub fn play(&mut self, sound: &Sound) -> SoundHandle {
+        self.play_with(sound, PlayOptions::default())
+    }
+
+    pub fn play_at(&mut self, sound: &Sound, position: Point) -> SoundHandle {
+        self.play_with(sound, PlayOptions::default().position(position))
+    }
+
+    pub fn play_with(&mut self, sound: &Sound, options: PlayOptions) -> SoundHandle {
+        self.play_with_effects(sound, options, &EffectChain::new())
+    }
+
+    /// Plays the sound through `effects`. The chain can be shared between
+    /// sounds, and changing it affects all of them.
+    pub fn play_with_effects(&mut self, sound: &Sound, options: PlayOptions, effects: &EffectChain) -> SoundHandle {
+        self.voices.retain(|voice| !voice.handle.is_finished());
+        if self.voices.len() >= self.max_voices && !self.steal_voice(options.priority) {
+            return SoundHandle::finished();
         }
+
+        let handle = match self.start_voice(sound, &options, effects) {
+            Ok(handle) => handle,
+            Err(e) => {
+                self.play_errors.push(format!("Failed to play sound: {}", e));
+                return SoundHandle::finished();
+            }
+        };
+        self.voices_started += 1;
+        self.voices.push(VoiceSlot {
+            handle: handle.clone(),
+            priority: options.priority,
+            bus: options.bus,
+            started: self.voices_started,
+        });
+        handle
+    }
+
+    /// Starts a stream of `channels` interleaved samples at `sample_rate`.
+    /// Streams don't count towards the voice limit and are never looped.
+    pub fn play_stream(&mut self, channels: u16, sample_rate: u32, options: PlayOptions) -> AudioStream {
+        let channels = channels.max(1);
+        let sample_rate = sample_rate.max(1);
+        let queue = Arc::new(StreamQueue {
+            samples: Mutex::new(VecDeque::new()),
+            closed: AtomicBool::new(false),
+            taken: AtomicUsize::new(0),
+            cleared: AtomicBool::new(false),
+        });
+        let source = StreamSource::new(Arc::clone(&queue), channels, sample_rate);
+        let handle = SoundHandle::new(&options, EffectChain::new(), None);
+        self.spatialize(&handle);
+        let voice = Voice::new(None, Box::new(source), Arc::clone(&handle.controls), false, None);
+        self.buses[options.bus.index()].input.add(EffectSource::new(voice, handle.effects.clone()));
+        self.log_play(None, options.bus);
+        self.streams.push((handle.clone(), options.bus));
+        AudioStream { queue, channels, sample_rate, handle }
     }
 
-    pub fn play(&mut self, sound: &Sound) {
-        // TODO detect free sinks
-        let sink_idx = self.next_sink;
-        self.next_sink += 1;
-        if self.next_sink == 8 {
-            self.next_sink = 1;
+    // Opens the sound and prepares its loop here rather than on the audio thread
+    fn start_voice(&mut self, sound: &Sound, options: &PlayOptions, effects: &EffectChain) -> Result<SoundHandle, String> {
+        let source = sound.open()?;
+        let loop_section = if options.looping { sound.loop_section()? } else { None };
+        let handle = SoundHandle::new(options, effects.clone(), Some(sound.clone()));
+        if options.looping && loop_section.is_none() {
+            handle.controls.wants_loop_source.store(true, Ordering::Relaxed);
+            handle.prepare_loop_source();
         }
+        self.spatialize(&handle);
+        let voice = Voice::new(Some(sound), source, Arc::clone(&handle.controls), options.looping, loop_section);
+        self.buses[options.bus.index()].input.add(EffectSource::new(voice, handle.effects.clone()));
+        self.log_play(Some(sound), options.bus);
+        Ok(handle)
+    }
 
-        let source = Decoder::new(Cursor::new(Arc::clone(&sound.data))).unwrap();
-        self.sinks[sink_idx].append(source);
-        self.sinks[sink_idx].play();
+    // Stops the lowest priority voice, preferring the quietest and then the
+    // oldest one. Returns false if every voice outranks `priority`.
+    fn steal_voice(&mut self, priority: i32) -> bool {
+        let victim = self.voices.iter().enumerate().min_by(|(_, a), (_, b)| {
+            a.priority
+                .cmp(&b.priority)
+                .then(a.handle.audible_volume().total_cmp(&b.handle.audible_volume()))
+                .then(a.started.cmp(&b.started))
+        });
+        match victim {
+            Some((i, voice)) if voice.priority <= priority => {
+                voice.handle.stop();
+                self.voices.remove(i);
+                true
+            }
+            _ => false,
+        }
     }
 
     pub fn play_music(&mut self, sound: &Sound) {
-        self.sinks[0].clear();
+        self.play_music_with(sound, 0.0, false);
+    }
+
+    /// Fades the music in over `fade_in` seconds. With `crossfade`, the
+    /// current music fades out over the same time instead of stopping.
+    pub fn play_music_with(&mut self, sound: &Sound, fade_in: f32, crossfade: bool) {
+        self.playlist = None;
+        self.start_music(sound, fade_in, crossfade, true);
+    }
 
-        let source = Decoder::new(Cursor::new(Arc::clone(&sound.data))).unwrap();
-        self.sinks[0].append(source.repeat_infinite());
-        self.sinks[0].play();
+    pub fn stop_music(&mut self, fade_out: f32) {
+        self.playlist = None;
+        if let Some(music) = self.music.take() {
+            music.fade_out(fade_out);
+        }
     }
 
     pub fn pause_music(&mut self) {
-        self.sinks[0].pause();
+        if let Some(music) = &self.music {
+            music.pause();
+        }
     }
 
     pub fn resume_music(&mut self) {
-        self.sinks[0].play();
+        if let Some(music) = &self.music {
+            music.resume();
+        }
+    }
+
+    /// Seconds into the music being heard, accounting for the latency.
+    pub fn music_position(&self) -> Option<f64> {
+        let latency = self.latency().as_secs_f64();
+        self.music.as_ref().map(|music| (music.playback_position() - latency).max(0.0))
+    }
+
+    pub fn seek_music(&mut self, seconds: f64) -> Result<(), String> {
+        let music = self.music.as_ref().ok_or("No music is playing")?;
+        music.seek(seconds)?;
+        // Events from the target on are reported as it is heard
+        let latency = self.latency().as_secs_f64();
+        self.music_seeks = music.controls.seeks.load(Ordering::Relaxed);
+        self.last_music_position = Some((seconds - latency).max(0.0) - f64::EPSILON);
+        Ok(())
+    }
+
+    /// Named times in seconds, reported by `music_events` when the music
+    /// passes them. Markers apply to whatever music plays until replaced.
+    pub fn set_music_markers(&mut self, markers: &[(f64, &str)]) {
+        self.music_markers = markers.iter().map(|&(time, name)| (time, name.to_string())).collect();
+        self.music_markers.sort_by(|a, b| a.0.total_cmp(&b.0));
+    }
+
+    /// Reports beats from `music_events`, with the first beat `offset`
+    /// seconds into the music. A `bpm` of 0 turns beats off.
+    pub fn set_music_tempo(&mut self, bpm: f64, offset: f64) {
+        self.music_tempo = (bpm > 0.0).then_some((bpm, offset));
+    }
+
+    /// Fractional beat of the music being heard.
+    pub fn music_beat(&self) -> Option<f64> {
+        let (bpm, offset) = self.music_tempo?;
+        Some((self.music_position()? - offset) * bpm / 60.0)
+    }
+
+    /// Markers and beats the music passed during the last `update`.
+    pub fn music_events(&self) -> &[MusicEvent] {
+        &self.music_events
+    }
+
+    // Collects the events after `from` and up to `to`
+    fn collect_music_events(&mut self, from: f64, to: f64) {
+        for (time, name) in &self.music_markers {
+            if *time > from && *time <= to {
+                self.music_events.push(MusicEvent::Marker(name.clone()));
+            }
+        }
+        if let Some((bpm, offset)) = self.music_tempo {
+            let beat = |time: f64| ((time - offset) * bpm / 60.0).floor();
+            let first = (beat(from) + 1.0).max(0.0);
+            let last = beat(to);
+            let mut n = first;
+            while n <= last {
+                self.music_events.push(MusicEvent::Beat(n as u64));
+                n += 1.0;
+            }
+        }
+    }
+
+    fn update_music_events(&mut self) {
+        self.music_events.clear();
+        let position = self.music_position();
+        let seeks = self.music.as_ref().map_or(0, |music| music.controls.seeks.load(Ordering::Relaxed));
+        if let (Some(from), Some(to)) = (self.last_music_position, position) {
+            if seeks != self.music_seeks {
+                // Seeked through the handle; nothing was passed
+            } else if to >= from {
+                self.collect_music_events(from, to);
+            } else {
+                // Looped back to the loop start
+                let sound = self.music.as_ref().and_then(|music| music.sound.as_ref());
+                let start = sound.map_or(0.0, |sound| sound.loop_start());
+                self.collect_music_events(start - f64::EPSILON, to);
+            }
+        }
+        self.music_seeks = seeks;
+        self.last_music_position = position;
+    }
+
+    pub fn is_music_playing(&self) -> bool {
+        self.music.as_ref().is_some_and(|music| music.is_playing())
+    }
+
+    /// Plays the tracks one after another as music. The playlist advances
+    /// in `update`.
+    pub fn play_playlist(&mut self, playlist: Playlist) {
+        let mut state = PlaylistState {
+            order: (0..playlist.tracks.len()).collect(),
+            playlist,
+            index: 0,
+            rng: Rng::from_time(),
+        };
+        if state.playlist.shuffle {
+            state.rng.shuffle(&mut state.order);
+        }
+        self.playlist = Some(state);
+        self.start_track(0.0);
+    }
+
+    /// Skips to the next track of the playlist, crossfading over `crossfade` seconds.
+    pub fn next_track(&mut self, crossfade: f32) {
+        if let Some(state) = &mut self.playlist {
+            state.index += 1;
+            self.start_track(crossfade);
+        }
+    }
+
+    fn start_track(&mut self, crossfade: f32) {
+        let Some(state) = &mut self.playlist else { return };
+        if state.index >= state.order.len() {
+            if !state.playlist.repeat || state.order.is_empty() {
+                self.stop_music(crossfade);
+                return;
+            }
+            state.index = 0;
+            if state.playlist.shuffle {
+                state.rng.shuffle(&mut state.order);
+            }
+        }
+        let sound = state.playlist.tracks[state.order[state.index]].clone();
+        self.start_music(&sound, crossfade, true, false);
+    }
+
+    fn start_music(&mut self, sound: &Sound, fade_in: f32, crossfade: bool, looping: bool) {
+        if let Some(music) = self.music.take() {
+            if crossfade {
+                music.fade_out(fade_in);
+            } else {
+                music.stop();
+            }
+        }
+
+        let options = PlayOptions::default().looping(looping).bus(Bus::Music).fade_in(fade_in);
+        match self.start_voice(sound, &options, &EffectChain::new()) {
+            Ok(handle) => self.music = Some(handle),
+            Err(e) => {
+                self.play_errors.push(format!("Failed to play music: {}", e));
+                return;
+            }
+        }
+        self.last_music_position = Some(-f64::EPSILON);
+        self.music_seeks = 0;
+    }
+
+    /// Lists the sounds that are alive and the memory they hold.
+    pub fn memory_report(&self) -> SoundReport {
+        SoundReport {
+            sounds: LIVE_SOUNDS.lock().unwrap().values().cloned().collect(),
+        }
+    }
+
+    // Buses ========================================
+
+    pub fn set_bus_volume(&mut self, bus: Bus, volume: f32) {
+        self.buses[bus.index()].controls.volume.set(volume.max(0.0));
+    }
+
+    pub fn bus_volume(&self, bus: Bus) -> f32 {
+        self.buses[bus.index()].controls.volume.get()
+    }
+
+    pub fn set_bus_muted(&mut self, bus: Bus, muted: bool) {
+        self.buses[bus.index()].controls.muted.store(muted, Ordering::Relaxed);
+    }
+
+    pub fn is_bus_muted(&self, bus: Bus) -> bool {
+        self.buses[bus.index()].controls.muted.load(Ordering::Relaxed)
+    }
+
+    /// The effects applied to everything played on the bus.
+    pub fn bus_effects(&self, bus: Bus) -> &EffectChain {
+        &self.buses[bus.index()].effects
+    }
+
+    /// Sets how the music bus is ducked under the voice bus, or disables
+    /// ducking with `None`.
+    pub fn set_ducking(&mut self, ducking: Option<Ducking>) {
+        self.ducking = ducking;
+    }
+
+    pub fn ducking(&self) -> Option<Ducking> {
+        self.ducking
+    }
+
+    // Positional audio ========================================
+
+    pub fn set_listener(&mut self, listener: Point) {
+        self.listener = listener;
+    }
+
+    pub fn listener(&self) -> Point {
+        self.listener
+    }
+
+    pub fn set_spatial_config(&mut self, config: SpatialConfig) {
+        self.spatial = config;
+    }
+
+    pub fn spatial_config(&self) -> SpatialConfig {
+        self.spatial
+    }
+
+    fn spatialize(&self, handle: &SoundHandle) {
+        let Some(position) = handle.position() else { return };
+        let offset = position - self.listener;
+        let distance = (offset.x * offset.x + offset.y * offset.y).sqrt();
+        let config = &self.spatial;
+        handle.controls.spatial_gain.set(config.gain(distance));
+        let pan = if config.pan_distance > 0.0 { (offset.x / config.pan_distance).clamp(-1.0, 1.0) } else { 0.0 };
+        handle.controls.spatial_pan.set(pan);
+    }
+
+    /// Called once per frame by the engine.
+    pub fn update(&mut self) {
+        if self.playlist.is_some() && self.music.as_ref().is_none_or(|music| music.is_finished()) {
+            self.next_track(0.0);
+        }
+        self.update_music_events();
+
+        self.voices.retain(|voice| !voice.handle.is_finished());
+        self.streams.retain(|(stream, _)| !stream.is_finished());
+        for handle in self.voices.iter().map(|voice| &voice.handle).chain(self.streams.iter().map(|(stream, _)| stream)) {
+            self.spatialize(handle);
+        }
+        for handle in self.voices.iter().map(|voice| &voice.handle).chain(&self.music) {
+            handle.prepare_loop_source();
+        }
+        let talking = self.voices
+            .iter()
+            .map(|voice| (&voice.handle, voice.bus))
+            .chain(self.streams.iter().map(|(stream, bus)| (stream, *bus)))
+            .any(|(handle, bus)| bus == Bus::Voice && handle.is_playing());
+
+        let music = &self.buses[Bus::Music.index()].controls;
+        match self.ducking {
+            Some(ducking) => {
+                let volume = ducking.volume.clamp(0.0, 1.0);
+                let time = if talking { ducking.attack } else { ducking.release };
+                music.duck_time.set(time / (1.0 - volume).max(0.001));
+                music.duck.set(if talking { volume } else { 1.0 });
+            }
+            None => music.duck.set(1.0),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 16-bit mono WAV at the mixer rate whose nth frame is n / 100
+    fn wav(frames: usize) -> Vec<u8> {
+        let spec = hound::WavSpec {
+            channels: 1,
+            sample_rate: SAMPLE_RATE,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut bytes = Cursor::new(Vec::new());
+        let mut writer = hound::WavWriter::new(&mut bytes, spec).unwrap();
+        for n in 0..frames {
+            writer.write_sample((n as f32 / 100.0 * 32768.0) as i16).unwrap();
+        }
+        writer.finalize().unwrap();
+        bytes.into_inner()
+    }
+
+    // Starts a voice without an output, returning it and its handle
+    fn voice(sound: &Sound, looping: bool) -> (Voice, SoundHandle) {
+        let handle = SoundHandle::new(&PlayOptions::default(), EffectChain::new(), Some(sound.clone()));
+        let loop_section = if looping { sound.loop_section().unwrap() } else { None };
+        if looping && loop_section.is_none() {
+            handle.controls.wants_loop_source.store(true, Ordering::Relaxed);
+            handle.prepare_loop_source();
+        }
+        let voice = Voice::new(Some(sound), sound.open().unwrap(), Arc::clone(&handle.controls), looping, loop_section);
+        (voice, handle)
+    }
+
+    // The left channel of the next `frames` frames, as frame numbers of the test sound
+    fn frames(voice: &mut Voice, frames: usize) -> Vec<i32> {
+        (0..frames)
+            .map_while(|_| {
+                let left = voice.next()?;
+                voice.next();
+                Some((left * 100.0).round() as i32)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn plays_once_without_looping() {
+        let sound = Sound::from_bytes(&wav(5)).unwrap();
+        let (mut voice, handle) = voice(&sound, false);
+        assert_eq!(frames(&mut voice, 10), [0, 1, 2, 3, 4]);
+        assert!(handle.is_finished());
+    }
+
+    #[test]
+    fn loops_between_loop_points() {
+        let bytes = wav(10);
+        let compressed = Sound::from_bytes(&bytes).unwrap().with_loop_points(LoopPoints::samples(4, Some(8)));
+        for sound in [compressed.clone(), compressed.decode().unwrap()] {
+            let (mut voice, _) = voice(&sound, true);
+            assert_eq!(frames(&mut voice, 14), [0, 1, 2, 3, 4, 5, 6, 7, 4, 5, 6, 7, 4, 5]);
+        }
+        let to_end = Sound::from_bytes(&bytes).unwrap().with_loop_points(LoopPoints::samples(7, None));
+        assert_eq!(frames(&mut voice(&to_end, true).0, 14), [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 7, 8, 9, 7]);
+    }
+
+    #[test]
+    fn streamed_loops_wait_for_the_game_thread() {
+        let path = std::env::temp_dir().join(format!("pgfx-loop-{}.wav", std::process::id()));
+        std::fs::write(&path, wav(6)).unwrap();
+        let sound = Sound::from_file_with(&path, SoundMode::Streamed).unwrap().with_loop_points(LoopPoints::samples(2, None));
+        let (mut voice, handle) = voice(&sound, true);
+        // The first restart uses the source prepared when the voice started
+        assert_eq!(frames(&mut voice, 10), [0, 1, 2, 3, 4, 5, 2, 3, 4, 5]);
+        // Then it plays silence until the engine reopens the file
+        assert_eq!(frames(&mut voice, 3), [0, 0, 0]);
+        assert!(handle.controls.wants_loop_source.load(Ordering::Relaxed));
+        handle.prepare_loop_source();
+        // Two silent frames were already read ahead for interpolation
+        assert_eq!(frames(&mut voice, 6), [0, 0, 2, 3, 4, 5]);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    fn stream(channels: u16) -> (Arc<StreamQueue>, StreamSource) {
+        let queue = Arc::new(StreamQueue {
+            samples: Mutex::new(VecDeque::new()),
+            closed: AtomicBool::new(false),
+            taken: AtomicUsize::new(0),
+            cleared: AtomicBool::new(false),
+        });
+        (Arc::clone(&queue), StreamSource::new(queue, channels, SAMPLE_RATE))
+    }
+
+    #[test]
+    fn streams_play_silence_while_empty_or_locked() {
+        let (queue, mut source) = stream(2);
+        assert_eq!(source.by_ref().take(2).collect::<Vec<_>>(), [0.0, 0.0]);
+        queue.samples.lock().unwrap().extend([0.1, 0.2, 0.3, 0.4]);
+        {
+            let _game = queue.samples.lock().unwrap();
+            assert_eq!(source.by_ref().take(4).collect::<Vec<_>>(), [0.0; 4]);
+        }
+        assert_eq!(source.by_ref().take(6).collect::<Vec<_>>(), [0.1, 0.2, 0.3, 0.4, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn stream_silence_keeps_channels_in_step() {
+        let (queue, mut source) = stream(2);
+        // Half a frame, then the queue runs dry
+        queue.samples.lock().unwrap().extend([0.5]);
+        assert_eq!(source.by_ref().take(2).collect::<Vec<_>>(), [0.5, 0.0]);
+        queue.samples.lock().unwrap().extend([0.1, 0.2]);
+        assert_eq!(source.by_ref().take(2).collect::<Vec<_>>(), [0.1, 0.2]);
+    }
+
+    #[test]
+    fn streams_take_blocks_and_end_when_closed() {
+        let (queue, mut source) = stream(1);
+        queue.samples.lock().unwrap().extend(vec![0.25; STREAM_BLOCK + 10]);
+        source.next();
+        assert_eq!(queue.samples.lock().unwrap().len(), 10);
+        assert_eq!(queue.taken.load(Ordering::Relaxed), STREAM_BLOCK - 1);
+
+        queue.closed.store(true, Ordering::Release);
+        assert_eq!(source.by_ref().count(), STREAM_BLOCK + 9);
+    }
+
+    #[test]
+    fn clearing_a_stream_drops_taken_samples() {
+        let (queue, mut source) = stream(1);
+        queue.samples.lock().unwrap().extend([0.5; 8]);
+        source.next();
+        queue.cleared.store(true, Ordering::Relaxed);
+        assert_eq!(source.next(), Some(0.0));
+        assert_eq!(queue.taken.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn play_log_is_on_only_when_headless_is_asked_for() {
+        let sound = Sound::from_samples(1, SAMPLE_RATE, &[0.0; 10]);
+        let mut engine = SoundEngine::with_backend(AudioBackend::Null).unwrap();
+        engine.play(&sound);
+        assert_eq!(engine.play_log().len(), 1);
+        assert_eq!(engine.play_log()[0].label, "samples");
+        assert_eq!(engine.backend_error(), None);
+
+        // As when falling back from a missing device
+        let mut engine = SoundEngine::open(AudioBackend::Null).unwrap();
+        engine.play(&sound);
+        assert!(engine.play_log().is_empty());
+    }
+
+    #[test]
+    fn duration_comes_from_the_header_or_decoding() {
+        let bytes = wav(4410);
+        assert_eq!(Sound::from_bytes(&bytes).unwrap().duration(), Some(Duration::from_millis(100)));
+        let path = std::env::temp_dir().join(format!("pgfx-decoded-{}.wav", std::process::id()));
+        std::fs::write(&path, &bytes).unwrap();
+        let sound = Sound::from_file_with(&path, SoundMode::Decoded).unwrap();
+        assert_eq!(sound.mode(), SoundMode::Decoded);
+        assert_eq!(sound.duration(), Some(Duration::from_millis(100)));
+        assert_eq!(LIVE_SOUNDS.lock().unwrap()[&sound.data.id].bytes, 4410 * 4);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn seeking_music_reports_no_skipped_events() {
+        let sound = Sound::from_samples(1, SAMPLE_RATE, &vec![0.0; SAMPLE_RATE as usize * 10]);
+        let mut engine = SoundEngine::with_backend(AudioBackend::Null).unwrap();
+        // Mix the music here rather than on the backend thread
+        let (mut voice, handle) = voice(&sound, true);
+        engine.music = Some(handle);
+        engine.last_music_position = Some(-f64::EPSILON);
+        engine.set_music_markers(&[(1.0, "one"), (5.0, "five"), (9.0, "nine")]);
+        engine.set_music_tempo(60.0, 0.0);
+        frames(&mut voice, 100);
+        engine.update();
+
+        engine.seek_music(5.5).unwrap();
+        engine.update();
+        assert_eq!(engine.music_events(), []);
+        engine.seek_music(2.5).unwrap();
+        frames(&mut voice, 100);
+        engine.update();
+        assert_eq!(engine.music_events(), []);
+
+        // Seeking through the handle skips ahead without events too
+        engine.music.as_ref().unwrap().seek(7.5).unwrap();
+        frames(&mut voice, 100);
+        engine.update();
+        assert_eq!(engine.music_events(), []);
+
+        // Playing on from the target reports what is passed
+        engine.seek_music(8.9).unwrap();
+        frames(&mut voice, SAMPLE_RATE as usize / 5);
+        engine.update();
+        assert_eq!(engine.music_events(), [MusicEvent::Marker("nine".into()), MusicEvent::Beat(9)]);
+    }
+
+    #[test]
+    fn voice_streams_duck_the_music() {
+        let mut engine = SoundEngine::with_backend(AudioBackend::Null).unwrap();
+        let stream = engine.play_stream(1, SAMPLE_RATE, PlayOptions::default().bus(Bus::Voice));
+        engine.update();
+        assert_eq!(engine.buses[Bus::Music.index()].controls.duck.get(), Ducking::default().volume);
+        stream.handle().stop();
+        engine.update();
+        assert_eq!(engine.buses[Bus::Music.index()].controls.duck.get(), 1.0);
     }
 }
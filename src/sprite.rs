@@ -0,0 +1,380 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+use serde_json::Value;
+
+use super::engine::Texture;
+use super::types::Rect;
+
+// Sprite sheets ============================================================
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PlayMode {
+    Loop,
+    PingPong,
+    Once,
+}
+
+struct Tag {
+    from: usize,
+    to: usize,
+    reverse: bool,
+    mode: PlayMode,
+}
+
+pub struct SpriteSheet {
+    texture: Texture,
+    frames: Vec<Rect>,
+    // Seconds, if the sheet specified per frame durations
+    durations: Vec<Option<f32>>,
+    names: HashMap<String, usize>,
+    tags: HashMap<String, Tag>,
+}
+
+impl SpriteSheet {
+
+    /// Slices the texture into a grid of `frame_width` x `frame_height`
+    /// frames, left to right and top to bottom. `margin` is the border around
+    /// the whole grid and `spacing` the gap between frames, both in pixels.
+    pub fn from_grid(texture: Texture, frame_width: f32, frame_height: f32, margin: f32, spacing: f32) -> Self {
+        let mut frames = Vec::new();
+        let mut y = margin;
+        while y + frame_height <= texture.height - margin {
+            let mut x = margin;
+            while x + frame_width <= texture.width - margin {
+                frames.push(Rect::new(x, y, frame_width, frame_height));
+                x += frame_width + spacing;
+            }
+            y += frame_height + spacing;
+        }
+        Self {
+            texture,
+            durations: vec![None; frames.len()],
+            frames,
+            names: HashMap::new(),
+            tags: HashMap::new(),
+        }
+    }
+
+    /// Loads named frames from an Aseprite or TexturePacker JSON export, in
+    /// either the "hash" or "array" layout. Aseprite frame tags become
+    /// animations available through `Animation::from_tag`.
+    pub fn from_json(texture: Texture, json: &str) -> Result<Self, String> {
+        let root: Value = serde_json::from_str(json).map_err(|e| e.to_string())?;
+
+        let entries: Vec<(String, &Value)> = match &root["frames"] {
+            Value::Object(map) => map.iter().map(|(name, frame)| (name.clone(), frame)).collect(),
+            Value::Array(list) => list
+                .iter()
+                .map(|frame| (frame["filename"].as_str().unwrap_or_default().to_string(), frame))
+                .collect(),
+            _ => return Err("Sprite sheet JSON has no \"frames\"".to_string()),
+        };
+
+        let mut sheet = Self {
+            texture,
+            frames: Vec::new(),
+            durations: Vec::new(),
+            names: HashMap::new(),
+            tags: HashMap::new(),
+        };
+        for (name, frame) in entries {
+            if frame["rotated"].as_bool() == Some(true) {
+                return Err(format!("Rotated frame \"{}\" is not supported", name));
+            }
+            let rect = &frame["frame"];
+            let field = |key: &str| {
+                rect[key].as_f64().map(|v| v as f32).ok_or_else(|| format!("Frame \"{}\" is missing \"{}\"", name, key))
+            };
+            sheet.frames.push(Rect::new(field("x")?, field("y")?, field("w")?, field("h")?));
+            sheet.durations.push(frame["duration"].as_f64().map(|ms| ms as f32 / 1000.0));
+            sheet.names.insert(name, sheet.frames.len() - 1);
+        }
+
+        if let Some(tags) = root["meta"]["frameTags"].as_array() {
+            for tag in tags {
+                let name = tag["name"].as_str().unwrap_or_default().to_string();
+                let from = tag["from"].as_u64().unwrap_or(0) as usize;
+                let to = tag["to"].as_u64().unwrap_or(0) as usize;
+                if from > to || to >= sheet.frames.len() {
+                    return Err(format!("Frame tag \"{}\" is out of range", name));
+                }
+                let direction = tag["direction"].as_str().unwrap_or("forward");
+                sheet.tags.insert(name, Tag {
+                    from,
+                    to,
+                    reverse: direction == "reverse",
+                    mode: if direction.starts_with("pingpong") { PlayMode::PingPong } else { PlayMode::Loop },
+                });
+            }
+        }
+
+        Ok(sheet)
+    }
+
+    pub fn texture(&self) -> &Texture {
+        &self.texture
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    pub fn frame(&self, index: usize) -> Option<Rect> {
+        self.frames.get(index).copied()
+    }
+
+    pub fn frame_index(&self, name: &str) -> Option<usize> {
+        self.names.get(name).copied()
+    }
+
+    pub fn frame_by_name(&self, name: &str) -> Option<Rect> {
+        self.frame_index(name).and_then(|i| self.frame(i))
+    }
+}
+
+// Animation ============================================================
+
+#[derive(Debug, Clone)]
+pub struct AnimationFrame {
+    pub index: usize,
+    /// Seconds
+    pub duration: f32,
+    /// Reported by `Animation::update` when this frame is reached.
+    pub event: Option<String>,
+}
+
+pub struct Animation {
+    sheet: Rc<SpriteSheet>,
+    frames: Vec<AnimationFrame>,
+    pub mode: PlayMode,
+    pub speed: f32,
+    pub paused: bool,
+    current: usize,
+    elapsed: f32,
+    forward: bool,
+    finished: bool,
+    // Whether the first frame's event has been reported
+    started: bool,
+}
+
+impl Animation {
+
+    /// Plays the given sheet frames in order, each for `frame_duration` seconds.
+    pub fn new(sheet: Rc<SpriteSheet>, frames: &[usize], frame_duration: f32, mode: PlayMode) -> Self {
+        let frames = frames
+            .iter()
+            .map(|&index| AnimationFrame { index, duration: frame_duration, event: None })
+            .collect();
+        Self::from_frames(sheet, frames, mode)
+    }
+
+    pub fn from_frames(sheet: Rc<SpriteSheet>, frames: Vec<AnimationFrame>, mode: PlayMode) -> Self {
+        Self {
+            sheet,
+            frames,
+            mode,
+            speed: 1.0,
+            paused: false,
+            current: 0,
+            elapsed: 0.0,
+            forward: true,
+            finished: false,
+            started: false,
+        }
+    }
+
+    /// Plays a frame tag from an Aseprite export, using its durations and direction.
+    pub fn from_tag(sheet: Rc<SpriteSheet>, tag: &str) -> Option<Self> {
+        let tag = sheet.tags.get(tag)?;
+        let mut indices: Vec<usize> = (tag.from..=tag.to).collect();
+        if tag.reverse {
+            indices.reverse();
+        }
+        let frames = indices
+            .into_iter()
+            .map(|index| AnimationFrame { index, duration: sheet.durations[index].unwrap_or(0.1), event: None })
+            .collect();
+        let mode = tag.mode;
+        Some(Self::from_frames(sheet, frames, mode))
+    }
+
+    pub fn sheet(&self) -> &SpriteSheet {
+        &self.sheet
+    }
+
+    pub fn frames_mut(&mut self) -> &mut [AnimationFrame] {
+        &mut self.frames
+    }
+
+    /// Reports `event` from `update` whenever the animation reaches its `frame`th frame.
+    pub fn set_event(&mut self, frame: usize, event: &str) {
+        if let Some(frame) = self.frames.get_mut(frame) {
+            frame.event = Some(event.to_string());
+        }
+    }
+
+    /// Position in the animation's frame list (not the sheet).
+    pub fn current_frame(&self) -> usize {
+        self.current
+    }
+
+    pub fn current_rect(&self) -> Rect {
+        self.frames
+            .get(self.current)
+            .and_then(|frame| self.sheet.frame(frame.index))
+            .unwrap_or(Rect::new(0.0, 0.0, 0.0, 0.0))
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    pub fn reset(&mut self) {
+        self.current = 0;
+        self.elapsed = 0.0;
+        self.forward = true;
+        self.finished = false;
+        self.started = false;
+    }
+
+    /// Advances the animation by `dt` seconds and returns the events of the
+    /// frames that were reached, including the first frame when it starts.
+    pub fn update(&mut self, dt: f32) -> Vec<String> {
+        let mut events = Vec::new();
+        if self.paused || self.finished || self.frames.is_empty() {
+            return events;
+        }
+        if !self.started {
+            self.started = true;
+            events.extend(self.frames[self.current].event.clone());
+        }
+        self.elapsed += dt * self.speed;
+        while self.elapsed >= self.frames[self.current].duration && !self.finished {
+            let duration = self.frames[self.current].duration;
+            if duration <= 0.0 && self.frames.iter().all(|f| f.duration <= 0.0) {
+                break;
+            }
+            self.elapsed -= duration;
+            if self.advance() {
+                events.extend(self.frames[self.current].event.clone());
+            }
+        }
+        events
+    }
+
+    // Returns whether a frame was reached; looping a single frame reaches it again
+    fn advance(&mut self) -> bool {
+        let last = self.frames.len() - 1;
+        match self.mode {
+            PlayMode::Loop => self.current = if self.current == last { 0 } else { self.current + 1 },
+            PlayMode::Once => {
+                if self.current == last {
+                    self.finished = true;
+                    return false;
+                }
+                self.current += 1;
+            }
+            PlayMode::PingPong => {
+                if last == 0 {
+                    return false;
+                }
+                if self.forward && self.current == last {
+                    self.forward = false;
+                } else if !self.forward && self.current == 0 {
+                    self.forward = true;
+                }
+                if self.forward {
+                    self.current += 1;
+                } else {
+                    self.current -= 1;
+                }
+            }
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn animation(frames: usize, mode: PlayMode) -> Animation {
+        let sheet = SpriteSheet::from_grid(Texture::headless(frames as f32 * 8.0, 8.0), 8.0, 8.0, 0.0, 0.0);
+        let indices: Vec<usize> = (0..frames).collect();
+        Animation::new(Rc::new(sheet), &indices, 0.1, mode)
+    }
+
+    fn frames_visited(animation: &mut Animation, steps: usize) -> Vec<usize> {
+        (0..steps)
+            .map(|_| {
+                animation.update(0.1);
+                animation.current_frame()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn loop_wraps_around() {
+        let mut animation = animation(3, PlayMode::Loop);
+        assert_eq!(frames_visited(&mut animation, 5), [1, 2, 0, 1, 2]);
+        assert!(!animation.is_finished());
+    }
+
+    #[test]
+    fn ping_pong_reverses_at_the_ends() {
+        let mut animation = animation(3, PlayMode::PingPong);
+        assert_eq!(frames_visited(&mut animation, 6), [1, 2, 1, 0, 1, 2]);
+    }
+
+    #[test]
+    fn once_stops_on_the_last_frame() {
+        let mut animation = animation(3, PlayMode::Once);
+        assert_eq!(frames_visited(&mut animation, 4), [1, 2, 2, 2]);
+        assert!(animation.is_finished());
+        assert_eq!(animation.current_rect().x, 16.0);
+    }
+
+    #[test]
+    fn speed_and_pause() {
+        let mut animation = animation(4, PlayMode::Loop);
+        animation.speed = 2.0;
+        animation.update(0.1);
+        assert_eq!(animation.current_frame(), 2);
+        animation.paused = true;
+        animation.update(1.0);
+        assert_eq!(animation.current_frame(), 2);
+    }
+
+    #[test]
+    fn frame_events() {
+        let mut animation = animation(3, PlayMode::Loop);
+        animation.set_event(0, "start");
+        animation.set_event(2, "step");
+        assert_eq!(animation.update(0.0), ["start"]);
+        assert!(animation.update(0.1).is_empty());
+        assert_eq!(animation.update(0.1), ["step"]);
+        assert_eq!(animation.update(0.1), ["start"]);
+        // Several frames in one update report every event on the way
+        assert_eq!(animation.update(0.3), ["step", "start"]);
+    }
+
+    #[test]
+    fn first_frame_event_after_reset() {
+        let mut animation = animation(2, PlayMode::Once);
+        animation.set_event(0, "start");
+        assert_eq!(animation.update(0.05), ["start"]);
+        animation.reset();
+        assert_eq!(animation.update(0.05), ["start"]);
+    }
+
+    #[test]
+    fn single_frame_loop_repeats_its_event() {
+        let mut animation = animation(1, PlayMode::Loop);
+        animation.set_event(0, "tick");
+        assert_eq!(animation.update(0.25), ["tick", "tick", "tick"]);
+    }
+}
@@ -0,0 +1,273 @@
+use std::f32::consts::TAU;
+
+use super::rng::Rng;
+use super::sound::{Sound, SAMPLE_RATE};
+
+// Synth ============================================================
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Waveform {
+    Sine,
+    /// `duty` is the fraction of each period spent high, 0.5 for a plain square.
+    Square { duty: f32 },
+    Triangle,
+    Sawtooth,
+    /// A new random value every half period, so the frequency sets the pitch of the noise.
+    Noise,
+}
+
+/// Attack, decay and release in seconds; `sustain` is the level held
+/// between the decay and the release.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Envelope {
+    pub attack: f32,
+    pub decay: f32,
+    pub sustain: f32,
+    pub release: f32,
+}
+
+impl Default for Envelope {
+    fn default() -> Self {
+        Self {
+            attack: 0.01,
+            decay: 0.05,
+            sustain: 0.7,
+            release: 0.1,
+        }
+    }
+}
+
+impl Envelope {
+    pub fn new(attack: f32, decay: f32, sustain: f32, release: f32) -> Self {
+        Self { attack, decay, sustain, release }
+    }
+
+    /// Level `time` seconds into a note that is released after `held` seconds.
+    pub fn level(&self, time: f32, held: f32) -> f32 {
+        if time < held {
+            return self.held_level(time);
+        }
+        if self.release <= 0.0 {
+            return 0.0;
+        }
+        let released = (time - held) / self.release;
+        (self.held_level(held) * (1.0 - released)).max(0.0)
+    }
+
+    fn held_level(&self, time: f32) -> f32 {
+        if time < self.attack {
+            time / self.attack
+        } else if time < self.attack + self.decay {
+            1.0 - (1.0 - self.sustain) * (time - self.attack) / self.decay
+        } else {
+            self.sustain
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SfxPreset {
+    Blip,
+    Pickup,
+    Laser,
+    Explosion,
+    Jump,
+    Hit,
+}
+
+/// A single synthesized note, rendered into a `Sound` with `to_sound`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Tone {
+    pub waveform: Waveform,
+    /// Hz at the start of the note
+    pub frequency: f32,
+    /// Frequency change in Hz per second, for laser and jump sweeps
+    pub slide: f32,
+    /// Depth as a fraction of the frequency, and rate in Hz
+    pub vibrato_depth: f32,
+    pub vibrato_rate: f32,
+    pub envelope: Envelope,
+    /// Seconds before the release starts
+    pub duration: f32,
+    pub volume: f32,
+    /// Seed for the noise waveform
+    pub seed: u64,
+}
+
+impl Default for Tone {
+    fn default() -> Self {
+        Self {
+            waveform: Waveform::Square { duty: 0.5 },
+            frequency: 440.0,
+            slide: 0.0,
+            vibrato_depth: 0.0,
+            vibrato_rate: 0.0,
+            envelope: Envelope::default(),
+            duration: 0.2,
+            volume: 0.5,
+            seed: 0,
+        }
+    }
+}
+
+impl Tone {
+    pub fn new(waveform: Waveform, frequency: f32, duration: f32) -> Self {
+        Self { waveform, frequency, duration, ..Self::default() }
+    }
+
+    /// A randomized sound effect in the style of sfxr. The same seed always
+    /// gives the same sound.
+    pub fn preset(preset: SfxPreset, seed: u64) -> Self {
+        let mut rng = Rng::new(seed);
+        let square = Waveform::Square { duty: rng.range(0.25, 0.5) };
+        let tone = Self { seed, ..Self::default() };
+        match preset {
+            SfxPreset::Blip => Self {
+                waveform: square,
+                frequency: rng.range(400.0, 1200.0),
+                envelope: Envelope::new(0.0, 0.0, 1.0, 0.02),
+                duration: rng.range(0.04, 0.1),
+                ..tone
+            },
+            SfxPreset::Pickup => Self {
+                waveform: square,
+                frequency: rng.range(600.0, 1200.0),
+                slide: rng.range(1500.0, 4000.0),
+                envelope: Envelope::new(0.0, 0.05, 0.6, rng.range(0.1, 0.25)),
+                duration: rng.range(0.05, 0.12),
+                ..tone
+            },
+            SfxPreset::Laser => Self {
+                waveform: if rng.next_f32() < 0.5 { Waveform::Sawtooth } else { square },
+                frequency: rng.range(800.0, 2000.0),
+                slide: -rng.range(3000.0, 8000.0),
+                envelope: Envelope::new(0.0, 0.0, 1.0, rng.range(0.05, 0.15)),
+                duration: rng.range(0.08, 0.2),
+                ..tone
+            },
+            SfxPreset::Explosion => Self {
+                waveform: Waveform::Noise,
+                frequency: rng.range(200.0, 1200.0),
+                slide: -rng.range(100.0, 800.0),
+                envelope: Envelope::new(0.0, 0.1, 0.5, rng.range(0.3, 0.7)),
+                duration: rng.range(0.05, 0.2),
+                volume: 0.7,
+                ..tone
+            },
+            SfxPreset::Jump => Self {
+                waveform: square,
+                frequency: rng.range(250.0, 500.0),
+                slide: rng.range(800.0, 2000.0),
+                envelope: Envelope::new(0.0, 0.05, 0.7, rng.range(0.05, 0.15)),
+                duration: rng.range(0.1, 0.2),
+                ..tone
+            },
+            SfxPreset::Hit => Self {
+                waveform: if rng.next_f32() < 0.5 { Waveform::Noise } else { Waveform::Sawtooth },
+                frequency: rng.range(300.0, 900.0),
+                slide: -rng.range(1000.0, 3000.0),
+                envelope: Envelope::new(0.0, 0.0, 1.0, rng.range(0.05, 0.15)),
+                duration: rng.range(0.02, 0.08),
+                ..tone
+            },
+        }
+    }
+
+    /// Length of the rendered note, including the release.
+    pub fn length(&self) -> f32 {
+        self.duration.max(0.0) + self.envelope.release.max(0.0)
+    }
+
+    /// Mono samples at `sample_rate`.
+    pub fn render(&self, sample_rate: u32) -> Vec<f32> {
+        let count = (self.length() * sample_rate as f32).ceil() as usize;
+        let mut rng = Rng::new(self.seed);
+        let mut phase = 0.0;
+        let mut noise = rng.range(-1.0, 1.0);
+        let mut samples = Vec::with_capacity(count);
+        for i in 0..count {
+            let time = i as f32 / sample_rate as f32;
+            let vibrato = 1.0 + self.vibrato_depth * (TAU * self.vibrato_rate * time).sin();
+            let frequency = ((self.frequency + self.slide * time) * vibrato).max(0.0);
+
+            let value = match self.waveform {
+                Waveform::Sine => (TAU * phase).sin(),
+                Waveform::Square { duty } => if phase < duty { 1.0 } else { -1.0 },
+                Waveform::Triangle => 1.0 - 4.0 * (phase - 0.5).abs(),
+                Waveform::Sawtooth => 2.0 * phase - 1.0,
+                Waveform::Noise => noise,
+            };
+            samples.push(value * self.envelope.level(time, self.duration) * self.volume);
+
+            let previous = phase;
+            phase = (phase + frequency / sample_rate as f32).fract();
+            // Crossing the start or the middle of the period
+            if phase < previous || (previous < 0.5 && phase >= 0.5) {
+                noise = rng.range(-1.0, 1.0);
+            }
+        }
+        samples
+    }
+
+    pub fn to_sound(&self) -> Sound {
+        Sound::from_samples(1, SAMPLE_RATE, &self.render(SAMPLE_RATE))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-5
+    }
+
+    #[test]
+    fn envelope_boundaries() {
+        let envelope = Envelope::new(0.1, 0.2, 0.5, 0.4);
+        let held = 1.0;
+        assert_eq!(envelope.level(0.0, held), 0.0);
+        assert!(close(envelope.level(0.05, held), 0.5));
+        // Peak at the end of the attack, then down to the sustain level
+        assert!(close(envelope.level(0.1, held), 1.0));
+        assert!(close(envelope.level(0.2, held), 0.75));
+        assert!(close(envelope.level(0.3, held), 0.5));
+        assert!(close(envelope.level(0.9, held), 0.5));
+        // Release from the sustain level down to silence
+        assert!(close(envelope.level(1.0, held), 0.5));
+        assert!(close(envelope.level(1.2, held), 0.25));
+        assert!(close(envelope.level(1.4, held), 0.0));
+        assert_eq!(envelope.level(2.0, held), 0.0);
+    }
+
+    #[test]
+    fn release_starts_from_the_current_level() {
+        let envelope = Envelope::new(0.2, 0.0, 1.0, 0.1);
+        // Released halfway through the attack
+        assert!(close(envelope.level(0.1, 0.1), 0.5));
+        assert!(close(envelope.level(0.15, 0.1), 0.25));
+        assert_eq!(Envelope::new(0.0, 0.0, 1.0, 0.0).level(0.1, 0.1), 0.0);
+        assert_eq!(Envelope::new(0.0, 0.0, 0.8, 0.1).level(0.0, 0.1), 0.8);
+    }
+
+    #[test]
+    fn rendered_length() {
+        let tone = Tone { envelope: Envelope::new(0.0, 0.0, 1.0, 0.05), ..Tone::new(Waveform::Sine, 440.0, 0.1) };
+        assert!(close(tone.length(), 0.15));
+        assert_eq!(tone.render(1000).len(), 150);
+        assert_eq!(tone.render(SAMPLE_RATE).len(), (0.15 * SAMPLE_RATE as f32).ceil() as usize);
+        assert!(Tone::new(Waveform::Sine, 440.0, -1.0).render(1000).len() <= 100);
+    }
+
+    #[test]
+    fn same_seed_same_sound() {
+        for preset in [SfxPreset::Blip, SfxPreset::Pickup, SfxPreset::Laser, SfxPreset::Explosion, SfxPreset::Jump, SfxPreset::Hit] {
+            let tone = Tone::preset(preset, 42);
+            assert_eq!(tone, Tone::preset(preset, 42));
+            assert_eq!(tone.render(SAMPLE_RATE), Tone::preset(preset, 42).render(SAMPLE_RATE));
+            let samples = tone.render(SAMPLE_RATE);
+            assert!(samples.iter().all(|s| s.abs() <= 1.0));
+        }
+        assert_ne!(Tone::preset(SfxPreset::Explosion, 1).render(SAMPLE_RATE), Tone::preset(SfxPreset::Explosion, 2).render(SAMPLE_RATE));
+    }
+}
@@ -0,0 +1,1098 @@
+use std::collections::HashMap;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use base64::Engine as _;
+use flate2::read::{GzDecoder, ZlibDecoder};
+use roxmltree::Node;
+use serde_json::Value;
+
+use super::engine::Texture;
+use super::tilemap::{tile_id, Orientation, TileMap, Tileset};
+use super::types::{Color, Point};
+
+// Tiled maps ============================================================
+//
+// Both the XML (.tmx/.tsx) and JSON (.tmj/.tsj) formats are parsed into the
+// same intermediate data, which is then turned into a `TileMap`. Tile ids are
+// renumbered on the way since `TileMap` assigns tileset ids itself.
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PropertyValue {
+    String(String),
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    Color(Color),
+    /// Path relative to the file that declared the property
+    File(String),
+    /// Id of an object in the map
+    Object(u32),
+    Class(Properties),
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Properties {
+    values: HashMap<String, PropertyValue>,
+}
+
+impl Properties {
+
+    pub fn get(&self, name: &str) -> Option<&PropertyValue> {
+        self.values.get(name)
+    }
+
+    pub fn get_str(&self, name: &str) -> Option<&str> {
+        match self.get(name)? {
+            PropertyValue::String(s) | PropertyValue::File(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn get_int(&self, name: &str) -> Option<i64> {
+        match *self.get(name)? {
+            PropertyValue::Int(v) => Some(v),
+            PropertyValue::Object(id) => Some(id as i64),
+            _ => None,
+        }
+    }
+
+    /// Returns float properties, and int properties converted to floats.
+    pub fn get_float(&self, name: &str) -> Option<f64> {
+        match *self.get(name)? {
+            PropertyValue::Float(v) => Some(v),
+            PropertyValue::Int(v) => Some(v as f64),
+            _ => None,
+        }
+    }
+
+    pub fn get_bool(&self, name: &str) -> Option<bool> {
+        match *self.get(name)? {
+            PropertyValue::Bool(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    pub fn get_color(&self, name: &str) -> Option<Color> {
+        match *self.get(name)? {
+            PropertyValue::Color(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &PropertyValue)> {
+        self.values.iter().map(|(name, value)| (name.as_str(), value))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum ObjectShape {
+    Rect,
+    Ellipse,
+    Point,
+    /// Points relative to the object position
+    Polygon(Vec<Point>),
+    Polyline(Vec<Point>),
+    Text(String),
+    /// A tile id of the map's `TileMap`, with flip flags. Tile objects are
+    /// anchored at their bottom left corner.
+    Tile(u32),
+}
+
+/// An object from an object layer. Positions are in map pixels as stored by
+/// Tiled; on isometric maps both axes are measured along the tile rows in
+/// units of the tile height, like Tiled does.
+#[derive(Debug, Clone)]
+pub struct TiledObject {
+    pub id: u32,
+    pub name: String,
+    pub class: String,
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+    /// Degrees, clockwise
+    pub rotation: f32,
+    pub visible: bool,
+    pub shape: ObjectShape,
+    pub properties: Properties,
+}
+
+#[derive(Debug, Clone)]
+pub struct ObjectLayer {
+    pub name: String,
+    pub visible: bool,
+    pub opacity: f32,
+    pub offset: Point,
+    /// Multiplied with the colors of the layer's tiles and images.
+    pub tint: Color,
+    pub objects: Vec<TiledObject>,
+    pub properties: Properties,
+}
+
+/// A layer of a Tiled map, in drawing order. Tile layers index into the
+/// layers of `TiledMap::tilemap`, object layers into `TiledMap::object_layers`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MapLayer {
+    Tiles(usize),
+    Objects(usize),
+}
+
+/// A map made with the Tiled editor, loaded from a .tmx or .tmj file.
+///
+/// Tile layers are loaded into `tilemap`, ready for `Engine::draw_tilemap`.
+/// Layers inside groups are flattened, with the group's offset, opacity, tint
+/// and visibility applied to them. Image layers and infinite maps are not
+/// supported.
+pub struct TiledMap {
+    pub tilemap: TileMap,
+    pub properties: Properties,
+    pub background: Option<Color>,
+    /// Properties of the tile layers, by `tilemap` layer index
+    pub tile_layer_properties: Vec<Properties>,
+    pub object_layers: Vec<ObjectLayer>,
+    pub layer_order: Vec<MapLayer>,
+    tile_properties: HashMap<u32, Properties>,
+}
+
+impl TiledMap {
+
+    /// Loads a map and the tilesets and images it references, relative to
+    /// the map file. The format is chosen from the file extension.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, String> {
+        let path = path.as_ref();
+        let text = read_file(path)?;
+        let dir = path.parent().unwrap_or(Path::new(""));
+        let data = if is_json(path, &text) { parse_json_map(&text, dir) } else { parse_xml_map(&text, dir) };
+        build(data.map_err(|e| format!("{}: {}", path.display(), e))?)
+    }
+
+    pub fn object_layer(&self, name: &str) -> Option<&ObjectLayer> {
+        self.object_layers.iter().find(|layer| layer.name == name)
+    }
+
+    /// Iterates over the objects of all object layers.
+    pub fn objects(&self) -> impl Iterator<Item = &TiledObject> {
+        self.object_layers.iter().flat_map(|layer| layer.objects.iter())
+    }
+
+    pub fn object_by_name(&self, name: &str) -> Option<&TiledObject> {
+        self.objects().find(|object| object.name == name)
+    }
+
+    pub fn object_by_id(&self, id: u32) -> Option<&TiledObject> {
+        self.objects().find(|object| object.id == id)
+    }
+
+    /// Returns the properties set on a tile in its tileset. Flip flags are ignored.
+    pub fn tile_properties(&self, tile: u32) -> Option<&Properties> {
+        self.tile_properties.get(&tile_id(tile))
+    }
+}
+
+// Intermediate data ============================================================
+
+struct MapData {
+    orientation: Orientation,
+    width: usize,
+    height: usize,
+    tile_width: f32,
+    tile_height: f32,
+    background: Option<Color>,
+    properties: Properties,
+    tilesets: Vec<TilesetData>,
+    layers: Vec<LayerData>,
+}
+
+struct TilesetData {
+    first_gid: u32,
+    name: String,
+    image: PathBuf,
+    tile_width: f32,
+    tile_height: f32,
+    margin: f32,
+    spacing: f32,
+    offset: Point,
+    tile_properties: Vec<(u32, Properties)>,
+    // Frames are tile indices and durations in seconds
+    animations: Vec<(u32, Vec<(u32, f32)>)>,
+}
+
+#[derive(Clone)]
+struct LayerInfo {
+    name: String,
+    visible: bool,
+    opacity: f32,
+    offset: Point,
+    tint: Color,
+    properties: Properties,
+}
+
+impl LayerInfo {
+    fn root() -> Self {
+        Self {
+            name: String::new(),
+            visible: true,
+            opacity: 1.0,
+            offset: Point::ZERO,
+            tint: Color::WHITE,
+            properties: Properties::default(),
+        }
+    }
+
+    // Applies the settings of the enclosing group
+    fn inherit(mut self, group: &LayerInfo) -> Self {
+        self.visible &= group.visible;
+        self.opacity *= group.opacity;
+        self.offset = self.offset + group.offset;
+        self.tint = multiply_colors(self.tint, group.tint);
+        self
+    }
+}
+
+fn multiply_colors(a: Color, b: Color) -> Color {
+    let channel = |x: u8, y: u8| ((x as u32 * y as u32 + 127) / 255) as u8;
+    Color::rgba(channel(a.r, b.r), channel(a.g, b.g), channel(a.b, b.b), channel(a.a, b.a))
+}
+
+enum LayerData {
+    Tiles(LayerInfo, Vec<u32>),
+    Objects(LayerInfo, Vec<TiledObject>),
+}
+
+fn build(data: MapData) -> Result<TiledMap, String> {
+    let mut tilemap = TileMap::new(data.width, data.height, data.tile_width, data.tile_height);
+    tilemap.set_orientation(data.orientation);
+
+    // (Tiled first gid, tile count, TileMap first id)
+    let mut ranges = Vec::new();
+    let mut tile_properties = HashMap::new();
+    let mut tilesets = data.tilesets;
+    tilesets.sort_by_key(|tileset| tileset.first_gid);
+    for data in tilesets {
+        let texture = Texture::from_file(&data.image)
+            .map_err(|e| format!("Tileset \"{}\": {}: {}", data.name, data.image.display(), e))?;
+        let mut tileset = Tileset::new(texture, data.tile_width, data.tile_height, data.margin, data.spacing)
+            .with_offset(data.offset);
+        for (tile, frames) in &data.animations {
+            tileset.set_animation(*tile, frames);
+        }
+        let tile_count = tileset.tile_count();
+        let first_id = tilemap.add_tileset(tileset);
+        for (tile, properties) in data.tile_properties {
+            tile_properties.insert(first_id + tile, properties);
+        }
+        ranges.push((data.first_gid, tile_count, first_id));
+    }
+    let remap = |gid: u32| {
+        let id = tile_id(gid);
+        ranges
+            .iter()
+            .rev()
+            .find(|(first_gid, _, _)| *first_gid <= id)
+            .filter(|(first_gid, count, _)| id - first_gid < *count)
+            .map_or(0, |(first_gid, _, first_id)| (gid - id) | (first_id + id - first_gid))
+    };
+
+    let mut map = TiledMap {
+        tilemap,
+        properties: data.properties,
+        background: data.background,
+        tile_layer_properties: Vec::new(),
+        object_layers: Vec::new(),
+        layer_order: Vec::new(),
+        tile_properties,
+    };
+    for layer in data.layers {
+        match layer {
+            LayerData::Tiles(info, tiles) => {
+                let index = map.tilemap.add_layer(&info.name);
+                let layer = map.tilemap.layer_mut(index).unwrap();
+                layer.visible = info.visible;
+                layer.opacity = info.opacity;
+                layer.tint = info.tint;
+                layer.offset = info.offset;
+                let tiles: Vec<u32> = tiles.into_iter().map(remap).collect();
+                layer.set_tiles(&tiles).map_err(|e| format!("Layer \"{}\": {}", info.name, e))?;
+                map.tile_layer_properties.push(info.properties);
+                map.layer_order.push(MapLayer::Tiles(index));
+            }
+            LayerData::Objects(info, mut objects) => {
+                for object in &mut objects {
+                    if let ObjectShape::Tile(gid) = &mut object.shape {
+                        *gid = remap(*gid);
+                    }
+                }
+                map.object_layers.push(ObjectLayer {
+                    name: info.name,
+                    visible: info.visible,
+                    opacity: info.opacity,
+                    offset: info.offset,
+                    tint: info.tint,
+                    objects,
+                    properties: info.properties,
+                });
+                map.layer_order.push(MapLayer::Objects(map.object_layers.len() - 1));
+            }
+        }
+    }
+    Ok(map)
+}
+
+fn read_file(path: &Path) -> Result<String, String> {
+    std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+fn is_json(path: &Path, text: &str) -> bool {
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("tmx") | Some("tsx") | Some("xml") => false,
+        Some("tmj") | Some("tsj") | Some("json") => true,
+        _ => text.trim_start().starts_with('{'),
+    }
+}
+
+fn parse_orientation(orientation: &str) -> Result<Orientation, String> {
+    match orientation {
+        "orthogonal" => Ok(Orientation::Orthogonal),
+        "isometric" => Ok(Orientation::Isometric),
+        _ => Err(format!("Unsupported map orientation \"{}\"", orientation)),
+    }
+}
+
+// Tiled colors are "#RRGGBB" or "#AARRGGBB"
+fn parse_color(color: &str) -> Result<Color, String> {
+    let hex = color.trim_start_matches('#');
+    let value = u32::from_str_radix(hex, 16).map_err(|_| format!("Invalid color \"{}\"", color))?;
+    let [a, r, g, b] = value.to_be_bytes();
+    match hex.len() {
+        6 => Ok(Color::rgba(r, g, b, 255)),
+        8 => Ok(Color::rgba(r, g, b, a)),
+        _ => Err(format!("Invalid color \"{}\"", color)),
+    }
+}
+
+fn parse_property(kind: &str, value: &str) -> Result<PropertyValue, String> {
+    let invalid = || format!("Invalid {} property value \"{}\"", kind, value);
+    Ok(match kind {
+        "int" => PropertyValue::Int(value.parse().map_err(|_| invalid())?),
+        "float" => PropertyValue::Float(value.parse().map_err(|_| invalid())?),
+        "bool" => PropertyValue::Bool(value == "true"),
+        "color" if value.is_empty() => PropertyValue::Color(Color::rgba(0, 0, 0, 0)),
+        "color" => PropertyValue::Color(parse_color(value)?),
+        "file" => PropertyValue::File(value.to_string()),
+        "object" => PropertyValue::Object(value.parse().map_err(|_| invalid())?),
+        _ => PropertyValue::String(value.to_string()),
+    })
+}
+
+// Base64 tile data, optionally compressed, holds little endian u32 ids.
+fn decode_tiles(text: &str, compression: &str) -> Result<Vec<u32>, String> {
+    let bytes = base64::engine::general_purpose::STANDARD
+        .decode(text.trim())
+        .map_err(|e| format!("Invalid base64 tile data: {}", e))?;
+    let mut decompressed = Vec::new();
+    let bytes = match compression {
+        "" => bytes,
+        "zlib" => {
+            ZlibDecoder::new(&bytes[..]).read_to_end(&mut decompressed).map_err(|e| e.to_string())?;
+            decompressed
+        }
+        "gzip" => {
+            GzDecoder::new(&bytes[..]).read_to_end(&mut decompressed).map_err(|e| e.to_string())?;
+            decompressed
+        }
+        _ => return Err(format!("Unsupported tile data compression \"{}\"", compression)),
+    };
+    Ok(bytes.chunks_exact(4).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]])).collect())
+}
+
+fn check_tile_count(tiles: Vec<u32>, width: usize, height: usize) -> Result<Vec<u32>, String> {
+    if tiles.len() != width * height {
+        return Err(format!("Expected {} tiles for a {}x{} layer, got {}", width * height, width, height, tiles.len()));
+    }
+    Ok(tiles)
+}
+
+// XML ============================================================
+
+fn attr<T: FromStr>(node: Node, name: &str) -> Result<T, String> {
+    let value = node
+        .attribute(name)
+        .ok_or_else(|| format!("<{}> is missing \"{}\"", node.tag_name().name(), name))?;
+    value
+        .parse()
+        .map_err(|_| format!("<{}> has an invalid \"{}\": \"{}\"", node.tag_name().name(), name, value))
+}
+
+fn attr_or<T: FromStr>(node: Node, name: &str, default: T) -> Result<T, String> {
+    match node.attribute(name) {
+        Some(_) => attr(node, name),
+        None => Ok(default),
+    }
+}
+
+fn child<'a, 'input>(node: Node<'a, 'input>, name: &str) -> Option<Node<'a, 'input>> {
+    node.children().find(|n| n.has_tag_name(name))
+}
+
+fn parse_xml_map(text: &str, dir: &Path) -> Result<MapData, String> {
+    let doc = roxmltree::Document::parse(text).map_err(|e| e.to_string())?;
+    let map = doc.root_element();
+    if !map.has_tag_name("map") {
+        return Err("Expected a <map> element".to_string());
+    }
+    if map.attribute("infinite") == Some("1") {
+        return Err("Infinite maps are not supported".to_string());
+    }
+
+    let mut data = MapData {
+        orientation: parse_orientation(map.attribute("orientation").unwrap_or("orthogonal"))?,
+        width: attr(map, "width")?,
+        height: attr(map, "height")?,
+        tile_width: attr(map, "tilewidth")?,
+        tile_height: attr(map, "tileheight")?,
+        background: map.attribute("backgroundcolor").map(parse_color).transpose()?,
+        properties: Properties::default(),
+        tilesets: Vec::new(),
+        layers: Vec::new(),
+    };
+    for node in map.children().filter(Node::is_element) {
+        match node.tag_name().name() {
+            "properties" => data.properties = parse_xml_properties(node)?,
+            "tileset" => {
+                let first_gid = attr(node, "firstgid")?;
+                let tileset = match node.attribute("source") {
+                    Some(source) => load_external_tileset(&dir.join(source), first_gid)?,
+                    None => parse_xml_tileset(node, first_gid, dir)?,
+                };
+                data.tilesets.push(tileset);
+            }
+            _ => parse_xml_layer(node, &LayerInfo::root(), data.width, data.height, &mut data.layers)?,
+        }
+    }
+    Ok(data)
+}
+
+fn parse_xml_properties(node: Node) -> Result<Properties, String> {
+    let mut properties = Properties::default();
+    for property in node.children().filter(|n| n.has_tag_name("property")) {
+        let name: String = attr(property, "name")?;
+        let kind = property.attribute("type").unwrap_or("string");
+        let value = if kind == "class" {
+            PropertyValue::Class(child(property, "properties").map(parse_xml_properties).transpose()?.unwrap_or_default())
+        } else {
+            // Multiline strings are stored as the element's text
+            let value = property.attribute("value").or(property.text()).unwrap_or("");
+            parse_property(kind, value)?
+        };
+        properties.values.insert(name, value);
+    }
+    Ok(properties)
+}
+
+fn load_external_tileset(path: &Path, first_gid: u32) -> Result<TilesetData, String> {
+    let text = read_file(path)?;
+    let dir = path.parent().unwrap_or(Path::new(""));
+    let tileset = if is_json(path, &text) {
+        let root: Value = serde_json::from_str(&text).map_err(|e| e.to_string())?;
+        parse_json_tileset(&root, first_gid, dir)
+    } else {
+        let doc = roxmltree::Document::parse(&text).map_err(|e| e.to_string())?;
+        parse_xml_tileset(doc.root_element(), first_gid, dir)
+    };
+    tileset.map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+fn parse_xml_tileset(node: Node, first_gid: u32, dir: &Path) -> Result<TilesetData, String> {
+    let name = node.attribute("name").unwrap_or_default().to_string();
+    let image = child(node, "image")
+        .ok_or_else(|| format!("Tileset \"{}\" has no image; image collection tilesets are not supported", name))?;
+    let offset = match child(node, "tileoffset") {
+        Some(offset) => Point::new(attr_or(offset, "x", 0.0)?, attr_or(offset, "y", 0.0)?),
+        None => Point::ZERO,
+    };
+
+    let mut tileset = TilesetData {
+        first_gid,
+        image: dir.join(attr::<String>(image, "source")?),
+        tile_width: attr(node, "tilewidth")?,
+        tile_height: attr(node, "tileheight")?,
+        margin: attr_or(node, "margin", 0.0)?,
+        spacing: attr_or(node, "spacing", 0.0)?,
+        offset,
+        tile_properties: Vec::new(),
+        animations: Vec::new(),
+        name,
+    };
+    for tile in node.children().filter(|n| n.has_tag_name("tile")) {
+        let id = attr(tile, "id")?;
+        if let Some(properties) = child(tile, "properties") {
+            tileset.tile_properties.push((id, parse_xml_properties(properties)?));
+        }
+        if let Some(animation) = child(tile, "animation") {
+            let frames = animation
+                .children()
+                .filter(|n| n.has_tag_name("frame"))
+                .map(|frame| Ok((attr(frame, "tileid")?, attr::<f32>(frame, "duration")? / 1000.0)))
+                .collect::<Result<Vec<_>, String>>()?;
+            tileset.animations.push((id, frames));
+        }
+    }
+    Ok(tileset)
+}
+
+fn parse_xml_layer_info(node: Node) -> Result<LayerInfo, String> {
+    Ok(LayerInfo {
+        name: node.attribute("name").unwrap_or_default().to_string(),
+        visible: node.attribute("visible") != Some("0"),
+        opacity: attr_or(node, "opacity", 1.0)?,
+        offset: Point::new(attr_or(node, "offsetx", 0.0)?, attr_or(node, "offsety", 0.0)?),
+        tint: node.attribute("tintcolor").map(parse_color).transpose()?.unwrap_or(Color::WHITE),
+        properties: child(node, "properties").map(parse_xml_properties).transpose()?.unwrap_or_default(),
+    })
+}
+
+fn parse_xml_layer(node: Node, group: &LayerInfo, width: usize, height: usize, layers: &mut Vec<LayerData>) -> Result<(), String> {
+    let kind = node.tag_name().name();
+    if !matches!(kind, "layer" | "objectgroup" | "group") {
+        return Ok(());
+    }
+    let info = parse_xml_layer_info(node)?.inherit(group);
+    let context = |e: String| format!("Layer \"{}\": {}", info.name, e);
+    match kind {
+        "layer" => {
+            let data = child(node, "data").ok_or_else(|| context("no tile data".to_string()))?;
+            let tiles = parse_xml_tile_data(data).and_then(|tiles| check_tile_count(tiles, width, height)).map_err(context)?;
+            layers.push(LayerData::Tiles(info, tiles));
+        }
+        "objectgroup" => {
+            let objects = node
+                .children()
+                .filter(|n| n.has_tag_name("object"))
+                .map(parse_xml_object)
+                .collect::<Result<Vec<_>, String>>()
+                .map_err(context)?;
+            layers.push(LayerData::Objects(info, objects));
+        }
+        _ => {
+            for layer in node.children().filter(Node::is_element) {
+                parse_xml_layer(layer, &info, width, height, layers)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn parse_xml_tile_data(data: Node) -> Result<Vec<u32>, String> {
+    if child(data, "chunk").is_some() {
+        return Err("Infinite maps are not supported".to_string());
+    }
+    match data.attribute("encoding") {
+        None => data
+            .children()
+            .filter(|n| n.has_tag_name("tile"))
+            .map(|tile| attr_or(tile, "gid", 0))
+            .collect(),
+        Some("csv") => data
+            .text()
+            .unwrap_or_default()
+            .split(',')
+            .map(|gid| gid.trim().parse().map_err(|_| format!("Invalid tile id \"{}\"", gid.trim())))
+            .collect(),
+        Some("base64") => decode_tiles(data.text().unwrap_or_default(), data.attribute("compression").unwrap_or_default()),
+        Some(encoding) => Err(format!("Unsupported tile data encoding \"{}\"", encoding)),
+    }
+}
+
+fn parse_points(points: &str) -> Result<Vec<Point>, String> {
+    points
+        .split_whitespace()
+        .map(|point| {
+            let (x, y) = point.split_once(',').ok_or_else(|| format!("Invalid point \"{}\"", point))?;
+            let parse = |v: &str| v.parse::<f32>().map_err(|_| format!("Invalid point \"{}\"", point));
+            Ok(Point::new(parse(x)?, parse(y)?))
+        })
+        .collect()
+}
+
+fn parse_xml_object(node: Node) -> Result<TiledObject, String> {
+    let shape = if let Some(gid) = node.attribute("gid") {
+        ObjectShape::Tile(gid.parse().map_err(|_| format!("Invalid tile id \"{}\"", gid))?)
+    } else if child(node, "ellipse").is_some() {
+        ObjectShape::Ellipse
+    } else if child(node, "point").is_some() {
+        ObjectShape::Point
+    } else if let Some(polygon) = child(node, "polygon") {
+        ObjectShape::Polygon(parse_points(polygon.attribute("points").unwrap_or_default())?)
+    } else if let Some(polyline) = child(node, "polyline") {
+        ObjectShape::Polyline(parse_points(polyline.attribute("points").unwrap_or_default())?)
+    } else if let Some(text) = child(node, "text") {
+        ObjectShape::Text(text.text().unwrap_or_default().to_string())
+    } else {
+        ObjectShape::Rect
+    };
+    Ok(TiledObject {
+        id: attr_or(node, "id", 0)?,
+        name: node.attribute("name").unwrap_or_default().to_string(),
+        // Called "type" before Tiled 1.9
+        class: node.attribute("class").or(node.attribute("type")).unwrap_or_default().to_string(),
+        x: attr_or(node, "x", 0.0)?,
+        y: attr_or(node, "y", 0.0)?,
+        width: attr_or(node, "width", 0.0)?,
+        height: attr_or(node, "height", 0.0)?,
+        rotation: attr_or(node, "rotation", 0.0)?,
+        visible: node.attribute("visible") != Some("0"),
+        shape,
+        properties: child(node, "properties").map(parse_xml_properties).transpose()?.unwrap_or_default(),
+    })
+}
+
+// JSON ============================================================
+
+fn field<T: TryFrom<u64>>(value: &Value, key: &str) -> Result<T, String> {
+    value[key]
+        .as_u64()
+        .and_then(|v| T::try_from(v).ok())
+        .ok_or_else(|| format!("Missing or invalid \"{}\"", key))
+}
+
+fn number_or(value: &Value, key: &str, default: f32) -> f32 {
+    value[key].as_f64().map_or(default, |v| v as f32)
+}
+
+fn str_or<'a>(value: &'a Value, key: &str) -> &'a str {
+    value[key].as_str().unwrap_or_default()
+}
+
+fn parse_json_map(text: &str, dir: &Path) -> Result<MapData, String> {
+    let root: Value = serde_json::from_str(text).map_err(|e| e.to_string())?;
+    if root["infinite"].as_bool() == Some(true) {
+        return Err("Infinite maps are not supported".to_string());
+    }
+
+    let mut data = MapData {
+        orientation: parse_orientation(root["orientation"].as_str().unwrap_or("orthogonal"))?,
+        width: field(&root, "width")?,
+        height: field(&root, "height")?,
+        tile_width: field::<u32>(&root, "tilewidth")? as f32,
+        tile_height: field::<u32>(&root, "tileheight")? as f32,
+        background: root["backgroundcolor"].as_str().map(parse_color).transpose()?,
+        properties: parse_json_properties(&root["properties"])?,
+        tilesets: Vec::new(),
+        layers: Vec::new(),
+    };
+    for tileset in root["tilesets"].as_array().into_iter().flatten() {
+        let first_gid = field(tileset, "firstgid")?;
+        let tileset = match tileset["source"].as_str() {
+            Some(source) => load_external_tileset(&dir.join(source), first_gid)?,
+            None => parse_json_tileset(tileset, first_gid, dir)?,
+        };
+        data.tilesets.push(tileset);
+    }
+    for layer in root["layers"].as_array().into_iter().flatten() {
+        parse_json_layer(layer, &LayerInfo::root(), data.width, data.height, &mut data.layers)?;
+    }
+    Ok(data)
+}
+
+fn parse_json_properties(value: &Value) -> Result<Properties, String> {
+    let mut properties = Properties::default();
+    for property in value.as_array().into_iter().flatten() {
+        let name = str_or(property, "name").to_string();
+        let value = match (str_or(property, "type"), &property["value"]) {
+            ("class", Value::Object(members)) => PropertyValue::Class(json_class_members(members)),
+            (kind, Value::String(s)) => parse_property(kind, s)?,
+            (kind, value) => parse_property(kind, &value.to_string())?,
+        };
+        properties.values.insert(name, value);
+    }
+    Ok(properties)
+}
+
+// Class members only store their values, so their types are guessed from the JSON.
+fn json_class_members(members: &serde_json::Map<String, Value>) -> Properties {
+    let mut properties = Properties::default();
+    for (name, value) in members {
+        let value = match value {
+            Value::Bool(v) => PropertyValue::Bool(*v),
+            Value::Number(n) => match n.as_i64() {
+                Some(v) => PropertyValue::Int(v),
+                None => PropertyValue::Float(n.as_f64().unwrap_or_default()),
+            },
+            Value::Object(members) => PropertyValue::Class(json_class_members(members)),
+            Value::String(s) => PropertyValue::String(s.clone()),
+            other => PropertyValue::String(other.to_string()),
+        };
+        properties.values.insert(name.clone(), value);
+    }
+    properties
+}
+
+fn parse_json_tileset(value: &Value, first_gid: u32, dir: &Path) -> Result<TilesetData, String> {
+    let name = str_or(value, "name").to_string();
+    let image = value["image"]
+        .as_str()
+        .ok_or_else(|| format!("Tileset \"{}\" has no image; image collection tilesets are not supported", name))?;
+    let offset = &value["tileoffset"];
+
+    let mut tileset = TilesetData {
+        first_gid,
+        image: dir.join(image),
+        tile_width: field::<u32>(value, "tilewidth")? as f32,
+        tile_height: field::<u32>(value, "tileheight")? as f32,
+        margin: number_or(value, "margin", 0.0),
+        spacing: number_or(value, "spacing", 0.0),
+        offset: Point::new(number_or(offset, "x", 0.0), number_or(offset, "y", 0.0)),
+        tile_properties: Vec::new(),
+        animations: Vec::new(),
+        name,
+    };
+    for tile in value["tiles"].as_array().into_iter().flatten() {
+        let id = field(tile, "id")?;
+        if tile["properties"].is_array() {
+            tileset.tile_properties.push((id, parse_json_properties(&tile["properties"])?));
+        }
+        if let Some(animation) = tile["animation"].as_array() {
+            let frames = animation
+                .iter()
+                .map(|frame| Ok((field(frame, "tileid")?, number_or(frame, "duration", 0.0) / 1000.0)))
+                .collect::<Result<Vec<_>, String>>()?;
+            tileset.animations.push((id, frames));
+        }
+    }
+    Ok(tileset)
+}
+
+fn parse_json_layer(value: &Value, group: &LayerInfo, width: usize, height: usize, layers: &mut Vec<LayerData>) -> Result<(), String> {
+    let info = LayerInfo {
+        name: str_or(value, "name").to_string(),
+        visible: value["visible"].as_bool().unwrap_or(true),
+        opacity: number_or(value, "opacity", 1.0),
+        offset: Point::new(number_or(value, "offsetx", 0.0), number_or(value, "offsety", 0.0)),
+        tint: value["tintcolor"].as_str().map(parse_color).transpose()?.unwrap_or(Color::WHITE),
+        properties: parse_json_properties(&value["properties"])?,
+    }
+    .inherit(group);
+    let context = |e: String| format!("Layer \"{}\": {}", info.name, e);
+
+    match str_or(value, "type") {
+        "tilelayer" => {
+            if value["chunks"].is_array() {
+                return Err(context("Infinite maps are not supported".to_string()));
+            }
+            let tiles = match &value["data"] {
+                Value::Array(ids) => ids
+                    .iter()
+                    .map(|id| id.as_u64().map(|id| id as u32).ok_or_else(|| format!("Invalid tile id {}", id)))
+                    .collect(),
+                Value::String(text) if str_or(value, "encoding") == "base64" => decode_tiles(text, str_or(value, "compression")),
+                _ => Err("Missing or invalid tile data".to_string()),
+            };
+            let tiles = tiles.and_then(|tiles| check_tile_count(tiles, width, height)).map_err(context)?;
+            layers.push(LayerData::Tiles(info, tiles));
+        }
+        "objectgroup" => {
+            let objects = value["objects"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .map(parse_json_object)
+                .collect::<Result<Vec<_>, String>>()
+                .map_err(context)?;
+            layers.push(LayerData::Objects(info, objects));
+        }
+        "group" => {
+            for layer in value["layers"].as_array().into_iter().flatten() {
+                parse_json_layer(layer, &info, width, height, layers)?;
+            }
+        }
+        _ => (),
+    }
+    Ok(())
+}
+
+fn parse_json_points(value: &Value) -> Vec<Point> {
+    value
+        .as_array()
+        .into_iter()
+        .flatten()
+        .map(|point| Point::new(number_or(point, "x", 0.0), number_or(point, "y", 0.0)))
+        .collect()
+}
+
+fn parse_json_object(value: &Value) -> Result<TiledObject, String> {
+    let shape = if let Some(gid) = value["gid"].as_u64() {
+        ObjectShape::Tile(gid as u32)
+    } else if value["ellipse"].as_bool() == Some(true) {
+        ObjectShape::Ellipse
+    } else if value["point"].as_bool() == Some(true) {
+        ObjectShape::Point
+    } else if value["polygon"].is_array() {
+        ObjectShape::Polygon(parse_json_points(&value["polygon"]))
+    } else if value["polyline"].is_array() {
+        ObjectShape::Polyline(parse_json_points(&value["polyline"]))
+    } else if value["text"].is_object() {
+        ObjectShape::Text(str_or(&value["text"], "text").to_string())
+    } else {
+        ObjectShape::Rect
+    };
+    let class = value["class"].as_str().or(value["type"].as_str()).unwrap_or_default();
+    Ok(TiledObject {
+        id: value["id"].as_u64().unwrap_or(0) as u32,
+        name: str_or(value, "name").to_string(),
+        class: class.to_string(),
+        x: number_or(value, "x", 0.0),
+        y: number_or(value, "y", 0.0),
+        width: number_or(value, "width", 0.0),
+        height: number_or(value, "height", 0.0),
+        rotation: number_or(value, "rotation", 0.0),
+        visible: value["visible"].as_bool().unwrap_or(true),
+        shape,
+        properties: parse_json_properties(&value["properties"])?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use flate2::Compression;
+    use flate2::write::{GzEncoder, ZlibEncoder};
+    use super::*;
+    use super::super::tilemap::{TILE_FLIP_DIAGONAL, TILE_FLIP_HORIZONTAL, TILE_FLIP_VERTICAL};
+
+    const TILES: [u32; 4] = [1, 2, 0, 3 | TILE_FLIP_HORIZONTAL | TILE_FLIP_DIAGONAL];
+
+    fn tile_bytes() -> Vec<u8> {
+        TILES.iter().flat_map(|tile| tile.to_le_bytes()).collect()
+    }
+
+    fn base64(bytes: &[u8]) -> String {
+        base64::engine::general_purpose::STANDARD.encode(bytes)
+    }
+
+    fn xml_map(layers: &str) -> String {
+        format!(
+            r#"<map orientation="orthogonal" width="2" height="2" tilewidth="16" tileheight="16">{}</map>"#,
+            layers,
+        )
+    }
+
+    fn tiles(data: &MapData) -> Vec<&Vec<u32>> {
+        data.layers
+            .iter()
+            .filter_map(|layer| match layer {
+                LayerData::Tiles(_, tiles) => Some(tiles),
+                LayerData::Objects(..) => None,
+            })
+            .collect()
+    }
+
+    fn info(layer: &LayerData) -> &LayerInfo {
+        match layer {
+            LayerData::Tiles(info, _) | LayerData::Objects(info, _) => info,
+        }
+    }
+
+    #[test]
+    fn csv_tile_data() {
+        let text = xml_map(&format!(
+            r#"<layer name="a"><data encoding="csv">
+                {},{},
+                {},{}
+            </data></layer>"#,
+            TILES[0], TILES[1], TILES[2], TILES[3],
+        ));
+        let data = parse_xml_map(&text, Path::new("")).unwrap();
+        assert_eq!(tiles(&data), [&TILES.to_vec()]);
+    }
+
+    #[test]
+    fn base64_tile_data() {
+        let mut zlib = ZlibEncoder::new(Vec::new(), Compression::default());
+        zlib.write_all(&tile_bytes()).unwrap();
+        let mut gzip = GzEncoder::new(Vec::new(), Compression::default());
+        gzip.write_all(&tile_bytes()).unwrap();
+
+        assert_eq!(decode_tiles(&base64(&tile_bytes()), "").unwrap(), TILES);
+        assert_eq!(decode_tiles(&base64(&zlib.finish().unwrap()), "zlib").unwrap(), TILES);
+        assert_eq!(decode_tiles(&base64(&gzip.finish().unwrap()), "gzip").unwrap(), TILES);
+        assert!(decode_tiles(&base64(&tile_bytes()), "zstd").is_err());
+        assert!(decode_tiles("not base64!", "").is_err());
+    }
+
+    #[test]
+    fn base64_layers_in_both_formats() {
+        let encoded = base64(&tile_bytes());
+        let xml = xml_map(&format!(r#"<layer name="a"><data encoding="base64"> {} </data></layer>"#, encoded));
+        let json = format!(
+            r#"{{"width": 2, "height": 2, "tilewidth": 16, "tileheight": 16, "layers": [
+                {{"type": "tilelayer", "name": "a", "encoding": "base64", "data": "{}"}}
+            ]}}"#,
+            encoded,
+        );
+        assert_eq!(tiles(&parse_xml_map(&xml, Path::new("")).unwrap()), [&TILES.to_vec()]);
+        assert_eq!(tiles(&parse_json_map(&json, Path::new("")).unwrap()), [&TILES.to_vec()]);
+    }
+
+    #[test]
+    fn tile_count_is_checked() {
+        let text = xml_map(r#"<layer name="a"><data encoding="csv">1,2,3</data></layer>"#);
+        assert!(parse_xml_map(&text, Path::new("")).is_err());
+    }
+
+    #[test]
+    fn flip_bits() {
+        let tile = TILES[3];
+        assert_eq!(tile_id(tile), 3);
+        assert_ne!(tile & TILE_FLIP_HORIZONTAL, 0);
+        assert_eq!(tile & TILE_FLIP_VERTICAL, 0);
+        assert_ne!(tile & TILE_FLIP_DIAGONAL, 0);
+        // Tiled's fourth flag, for hexagonal rotation, isn't part of the id either
+        assert_eq!(tile_id(7 | 0x1000_0000), 7);
+    }
+
+    #[test]
+    fn colors() {
+        assert_eq!(parse_color("#ff8000").unwrap(), Color::rgba(255, 128, 0, 255));
+        assert_eq!(parse_color("#80ff8000").unwrap(), Color::rgba(255, 128, 0, 128));
+        assert_eq!(parse_color("102030").unwrap(), Color::rgba(16, 32, 48, 255));
+        assert!(parse_color("#fff").is_err());
+        assert!(parse_color("#gggggg").is_err());
+    }
+
+    #[test]
+    fn xml_properties() {
+        let text = xml_map(
+            r##"<properties>
+                <property name="title" value="Level 1"/>
+                <property name="lives" type="int" value="3"/>
+                <property name="gravity" type="float" value="9.5"/>
+                <property name="dark" type="bool" value="true"/>
+                <property name="fog" type="color" value="#80102030"/>
+                <property name="empty" type="color" value=""/>
+                <property name="target" type="object" value="12"/>
+                <property name="notes">line one
+line two</property>
+                <property name="spawn" type="class">
+                    <properties><property name="x" type="int" value="4"/></properties>
+                </property>
+            </properties>"##,
+        );
+        let properties = parse_xml_map(&text, Path::new("")).unwrap().properties;
+        assert_eq!(properties.get_str("title"), Some("Level 1"));
+        assert_eq!(properties.get_int("lives"), Some(3));
+        assert_eq!(properties.get_float("lives"), Some(3.0));
+        assert_eq!(properties.get_float("gravity"), Some(9.5));
+        assert_eq!(properties.get_bool("dark"), Some(true));
+        assert_eq!(properties.get_color("fog"), Some(Color::rgba(16, 32, 48, 128)));
+        assert_eq!(properties.get_color("empty"), Some(Color::rgba(0, 0, 0, 0)));
+        assert_eq!(properties.get_int("target"), Some(12));
+        assert_eq!(properties.get_str("notes"), Some("line one\nline two"));
+        match properties.get("spawn") {
+            Some(PropertyValue::Class(members)) => assert_eq!(members.get_int("x"), Some(4)),
+            other => panic!("Expected a class property, got {:?}", other),
+        }
+        assert!(parse_property("int", "three").is_err());
+    }
+
+    #[test]
+    fn json_properties() {
+        let value: Value = serde_json::from_str(
+            r##"[
+                {"name": "lives", "type": "int", "value": 3},
+                {"name": "gravity", "type": "float", "value": 9.5},
+                {"name": "dark", "type": "bool", "value": false},
+                {"name": "fog", "type": "color", "value": "#102030"},
+                {"name": "file", "type": "file", "value": "a.png"},
+                {"name": "spawn", "type": "class", "value": {"x": 4, "speed": 1.5, "name": "orc"}}
+            ]"##,
+        )
+        .unwrap();
+        let properties = parse_json_properties(&value).unwrap();
+        assert_eq!(properties.get_int("lives"), Some(3));
+        assert_eq!(properties.get_float("gravity"), Some(9.5));
+        assert_eq!(properties.get_bool("dark"), Some(false));
+        assert_eq!(properties.get_color("fog"), Some(Color::rgba(16, 32, 48, 255)));
+        assert_eq!(properties.get("file"), Some(&PropertyValue::File("a.png".to_string())));
+        match properties.get("spawn") {
+            Some(PropertyValue::Class(members)) => {
+                assert_eq!(members.get_int("x"), Some(4));
+                assert_eq!(members.get_float("speed"), Some(1.5));
+                assert_eq!(members.get_str("name"), Some("orc"));
+            }
+            other => panic!("Expected a class property, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn external_tilesets() {
+        let dir = std::env::temp_dir().join(format!("pgfx-tiled-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("tiles")).unwrap();
+        std::fs::write(
+            dir.join("tiles/terrain.tsx"),
+            r#"<tileset name="terrain" tilewidth="16" tileheight="16" margin="1" spacing="2">
+                <image source="terrain.png"/>
+                <tile id="2"><properties><property name="solid" type="bool" value="true"/></properties></tile>
+            </tileset>"#,
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("tiles/items.tsj"),
+            r#"{"name": "items", "tilewidth": 8, "tileheight": 8, "image": "items.png",
+                "tiles": [{"id": 0, "animation": [{"tileid": 0, "duration": 100}, {"tileid": 1, "duration": 200}]}]}"#,
+        )
+        .unwrap();
+
+        let xml = xml_map(r#"<tileset firstgid="1" source="tiles/terrain.tsx"/><tileset firstgid="50" source="tiles/items.tsj"/>"#);
+        let json = r#"{"width": 2, "height": 2, "tilewidth": 16, "tileheight": 16,
+            "tilesets": [{"firstgid": 1, "source": "tiles/terrain.tsx"}, {"firstgid": 50, "source": "tiles/items.tsj"}]}"#;
+        for data in [parse_xml_map(&xml, &dir), parse_json_map(json, &dir)] {
+            let data = data.unwrap();
+            let [terrain, items] = &data.tilesets[..] else { panic!("Expected two tilesets") };
+            assert_eq!((terrain.first_gid, terrain.name.as_str()), (1, "terrain"));
+            // Images are relative to the tileset file, not the map
+            assert_eq!(terrain.image, dir.join("tiles/terrain.png"));
+            assert_eq!((terrain.margin, terrain.spacing), (1.0, 2.0));
+            assert_eq!(terrain.tile_properties[0].0, 2);
+            assert_eq!(terrain.tile_properties[0].1.get_bool("solid"), Some(true));
+            assert_eq!((items.first_gid, items.tile_width), (50, 8.0));
+            assert_eq!(items.image, dir.join("tiles/items.png"));
+            assert_eq!(items.animations, [(0, vec![(0, 0.1), (1, 0.2)])]);
+        }
+        assert!(parse_xml_map(&xml_map(r#"<tileset firstgid="1" source="missing.tsx"/>"#), &dir).is_err());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn groups_apply_to_their_layers() {
+        let text = xml_map(
+            r##"<group name="g" opacity="0.5" offsetx="10" tintcolor="#ff8080ff">
+                <layer name="a" opacity="0.5" offsety="4" tintcolor="#808080"><data encoding="csv">0,0,0,0</data></layer>
+                <group name="inner" visible="0" offsetx="1">
+                    <objectgroup name="b"/>
+                </group>
+            </group>"##,
+        );
+        let data = parse_xml_map(&text, Path::new("")).unwrap();
+        let (a, b) = (info(&data.layers[0]), info(&data.layers[1]));
+        assert_eq!((a.opacity, a.offset, a.visible), (0.25, Point::new(10.0, 4.0), true));
+        assert_eq!(a.tint, Color::rgba(64, 64, 128, 255));
+        assert_eq!((b.opacity, b.offset, b.visible), (0.5, Point::new(11.0, 0.0), false));
+        assert_eq!(b.tint, Color::rgba(128, 128, 255, 255));
+    }
+
+    #[test]
+    fn json_groups_apply_tint() {
+        let json = r##"{"width": 1, "height": 1, "tilewidth": 16, "tileheight": 16, "layers": [
+            {"type": "group", "name": "g", "tintcolor": "#800000ff", "layers": [
+                {"type": "tilelayer", "name": "a", "data": [0], "tintcolor": "#ffffff"}
+            ]}
+        ]}"##;
+        let data = parse_json_map(json, Path::new("")).unwrap();
+        assert_eq!(info(&data.layers[0]).tint, Color::rgba(0, 0, 255, 128));
+    }
+}
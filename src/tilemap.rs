@@ -0,0 +1,741 @@
+use std::collections::HashMap;
+use std::{ptr, mem};
+use gl::types::*;
+
+use super::engine::Texture;
+use super::types::{Color, Insets, Point, Rect};
+use super::resources::{self, ResourceKind};
+
+// Tile maps ============================================================
+//
+// Tiles are stored as ids in the style of Tiled: 0 is an empty cell, and
+// tileset tiles are numbered from the tileset's first id on. The top bits
+// hold the flip flags, so ids read from Tiled maps can be used as is.
+
+pub const TILE_FLIP_HORIZONTAL: u32 = 0x8000_0000;
+pub const TILE_FLIP_VERTICAL: u32 = 0x4000_0000;
+/// Swaps the x and y axes of the tile. Combined with the other flips this
+/// rotates tiles by 90 degree steps, e.g. `TILE_FLIP_DIAGONAL | TILE_FLIP_HORIZONTAL`
+/// rotates clockwise.
+pub const TILE_FLIP_DIAGONAL: u32 = 0x2000_0000;
+// Also masks Tiled's hexagonal rotation bit
+const TILE_ID_MASK: u32 = 0x0FFF_FFFF;
+
+// In tiles
+const CHUNK_SIZE: usize = 16;
+// x, y, u, v per vertex
+const FLOATS_PER_TILE: usize = 6 * 4;
+
+/// Returns `tile` without its flip flags.
+pub fn tile_id(tile: u32) -> u32 {
+    tile & TILE_ID_MASK
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Orientation {
+    Orthogonal,
+    /// Diamond shaped cells, with the x axis going down to the right and the
+    /// y axis down to the left, laid out like Tiled's isometric maps.
+    Isometric,
+}
+
+struct TileAnimation {
+    // Tile index and duration in seconds
+    frames: Vec<(u32, f32)>,
+    total: f32,
+}
+
+impl TileAnimation {
+    fn frame_at(&self, time: f32) -> usize {
+        if self.total <= 0.0 {
+            return 0;
+        }
+        let mut t = time % self.total;
+        for (i, (_, duration)) in self.frames.iter().enumerate() {
+            if t < *duration {
+                return i;
+            }
+            t -= duration;
+        }
+        self.frames.len() - 1
+    }
+}
+
+pub struct Tileset {
+    texture: Texture,
+    first_id: u32,
+    tile_width: f32,
+    tile_height: f32,
+    margin: f32,
+    spacing: f32,
+    columns: u32,
+    tile_count: u32,
+    offset: Point,
+    animations: HashMap<u32, TileAnimation>,
+}
+
+impl Tileset {
+
+    /// Slices the texture into `tile_width` x `tile_height` tiles, numbered
+    /// left to right and top to bottom. `margin` is the border around the
+    /// whole grid and `spacing` the gap between tiles, both in pixels.
+    pub fn new(texture: Texture, tile_width: f32, tile_height: f32, margin: f32, spacing: f32) -> Self {
+        let count = |size: f32, tile: f32| ((size - 2.0 * margin + spacing) / (tile + spacing)).floor().max(0.0) as u32;
+        let columns = count(texture.width, tile_width);
+        let rows = count(texture.height, tile_height);
+        Self {
+            texture,
+            first_id: 1,
+            tile_width,
+            tile_height,
+            margin,
+            spacing,
+            columns,
+            tile_count: columns * rows,
+            offset: Point::ZERO,
+            animations: HashMap::new(),
+        }
+    }
+
+    /// Shifts every tile of the tileset by `offset` pixels when drawn.
+    pub fn with_offset(mut self, offset: Point) -> Self {
+        self.offset = offset;
+        self
+    }
+
+    /// Animates the `tile`th tile of the tileset through the given tile
+    /// indices, each shown for the given number of seconds. Animations are
+    /// advanced by `TileMap::update`.
+    pub fn set_animation(&mut self, tile: u32, frames: &[(u32, f32)]) {
+        if frames.is_empty() {
+            self.animations.remove(&tile);
+            return;
+        }
+        let total = frames.iter().map(|(_, duration)| duration).sum();
+        self.animations.insert(tile, TileAnimation { frames: frames.to_vec(), total });
+    }
+
+    pub fn texture(&self) -> &Texture {
+        &self.texture
+    }
+
+    /// The id of the first tile, assigned when the tileset is added to a map.
+    pub fn first_id(&self) -> u32 {
+        self.first_id
+    }
+
+    pub fn tile_count(&self) -> u32 {
+        self.tile_count
+    }
+
+    pub fn tile_width(&self) -> f32 {
+        self.tile_width
+    }
+
+    pub fn tile_height(&self) -> f32 {
+        self.tile_height
+    }
+
+    /// Source rectangle of the `index`th tile of this tileset (not a map id).
+    pub fn tile_rect(&self, index: u32) -> Rect {
+        let columns = self.columns.max(1);
+        let (col, row) = ((index % columns) as f32, (index / columns) as f32);
+        Rect::new(
+            self.margin + col * (self.tile_width + self.spacing),
+            self.margin + row * (self.tile_height + self.spacing),
+            self.tile_width,
+            self.tile_height,
+        )
+    }
+
+    fn animated_tile(&self, tile: u32, time: f32) -> u32 {
+        match self.animations.get(&tile) {
+            Some(animation) => animation.frames[animation.frame_at(time)].0,
+            None => tile,
+        }
+    }
+}
+
+// One vertex buffer per tileset used by a chunk.
+struct ChunkMesh {
+    tileset: usize,
+    vao: u32,
+    vbo: u32,
+    vertex_count: usize,
+}
+
+impl ChunkMesh {
+    fn new(tileset: usize) -> Self {
+        let (mut vao, mut vbo) = (0, 0);
+        unsafe {
+            gl::GenVertexArrays(1, &mut vao);
+            gl::GenBuffers(1, &mut vbo);
+            gl::BindVertexArray(vao);
+            gl::BindBuffer(gl::ARRAY_BUFFER, vbo);
+            let stride = 4 * mem::size_of::<GLfloat>() as GLsizei;
+            gl::EnableVertexAttribArray(0);
+            gl::VertexAttribPointer(0, 2, gl::FLOAT, gl::FALSE, stride, ptr::null());
+            gl::EnableVertexAttribArray(1);
+            gl::VertexAttribPointer(1, 2, gl::FLOAT, gl::FALSE, stride, (2 * mem::size_of::<GLfloat>()) as *const _);
+            gl::BindVertexArray(0);
+            gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+        }
+        resources::track(ResourceKind::VertexArray, vao, 0, "tilemap chunk");
+        resources::track(ResourceKind::Buffer, vbo, 0, "tilemap chunk");
+        Self { tileset, vao, vbo, vertex_count: 0 }
+    }
+
+    fn upload(&mut self, vertices: &[f32]) {
+        let bytes = mem::size_of_val(vertices);
+        unsafe {
+            gl::BindBuffer(gl::ARRAY_BUFFER, self.vbo);
+            gl::BufferData(gl::ARRAY_BUFFER, bytes as GLsizeiptr, vertices.as_ptr() as *const _, gl::STATIC_DRAW);
+            gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+        }
+        resources::set_bytes(ResourceKind::Buffer, self.vbo, bytes);
+        self.vertex_count = vertices.len() / 4;
+    }
+}
+
+impl Drop for ChunkMesh {
+    fn drop(&mut self) {
+        resources::release(ResourceKind::VertexArray, self.vao);
+        resources::release(ResourceKind::Buffer, self.vbo);
+    }
+}
+
+struct Chunk {
+    dirty: bool,
+    // Whether the chunk shows animated tiles and must be rebuilt when they change frame
+    animated: bool,
+    meshes: Vec<ChunkMesh>,
+}
+
+pub struct TileLayer {
+    pub name: String,
+    pub visible: bool,
+    pub opacity: f32,
+    pub tint: Color,
+    /// Drawing offset of the whole layer, in pixels.
+    pub offset: Point,
+    width: usize,
+    height: usize,
+    tiles: Vec<u32>,
+    chunks: Vec<Chunk>,
+    chunks_x: usize,
+}
+
+impl TileLayer {
+
+    fn new(name: &str, width: usize, height: usize) -> Self {
+        let chunks_x = width.div_ceil(CHUNK_SIZE);
+        let chunks_y = height.div_ceil(CHUNK_SIZE);
+        Self {
+            name: name.to_string(),
+            visible: true,
+            opacity: 1.0,
+            tint: Color::WHITE,
+            offset: Point::new(0.0, 0.0),
+            width,
+            height,
+            tiles: vec![0; width * height],
+            chunks: (0..chunks_x * chunks_y).map(|_| Chunk { dirty: true, animated: false, meshes: Vec::new() }).collect(),
+            chunks_x,
+        }
+    }
+
+    /// Returns the tile id (with flip flags) at the given cell, or 0 outside the layer.
+    pub fn tile(&self, x: usize, y: usize) -> u32 {
+        if x < self.width && y < self.height {
+            self.tiles[y * self.width + x]
+        } else {
+            0
+        }
+    }
+
+    pub fn set_tile(&mut self, x: usize, y: usize, tile: u32) {
+        if x >= self.width || y >= self.height {
+            return;
+        }
+        let cell = &mut self.tiles[y * self.width + x];
+        if *cell != tile {
+            *cell = tile;
+            self.chunks[(y / CHUNK_SIZE) * self.chunks_x + x / CHUNK_SIZE].dirty = true;
+        }
+    }
+
+    pub fn fill(&mut self, tile: u32) {
+        self.tiles.fill(tile);
+        self.mark_dirty();
+    }
+
+    /// Replaces all tiles, given row by row from the top.
+    pub fn set_tiles(&mut self, tiles: &[u32]) -> Result<(), String> {
+        if tiles.len() != self.tiles.len() {
+            return Err(format!("Expected {} tiles for a {}x{} layer, got {}", self.tiles.len(), self.width, self.height, tiles.len()));
+        }
+        self.tiles.copy_from_slice(tiles);
+        self.mark_dirty();
+        Ok(())
+    }
+
+    pub fn tiles(&self) -> &[u32] {
+        &self.tiles
+    }
+
+    fn mark_dirty(&mut self) {
+        for chunk in &mut self.chunks {
+            chunk.dirty = true;
+        }
+    }
+}
+
+#[derive(Copy, Clone)]
+struct Grid {
+    orientation: Orientation,
+    width: usize,
+    height: usize,
+    tile_width: f32,
+    tile_height: f32,
+}
+
+impl Grid {
+    // Top left corner of the cell's bounding box, in map pixels.
+    fn cell_origin(&self, x: usize, y: usize) -> Point {
+        match self.orientation {
+            Orientation::Orthogonal => Point::new(x as f32 * self.tile_width, y as f32 * self.tile_height),
+            Orientation::Isometric => Point::new(
+                (x as f32 - y as f32 + self.height as f32 - 1.0) * self.tile_width / 2.0,
+                (x + y) as f32 * self.tile_height / 2.0,
+            ),
+        }
+    }
+
+    fn cell_at(&self, point: Point) -> Option<(usize, usize)> {
+        let (x, y) = match self.orientation {
+            Orientation::Orthogonal => (point.x / self.tile_width, point.y / self.tile_height),
+            Orientation::Isometric => {
+                let px = (point.x - self.height as f32 * self.tile_width / 2.0) / self.tile_width;
+                let py = point.y / self.tile_height;
+                (py + px, py - px)
+            }
+        };
+        let (x, y) = (x.floor(), y.floor());
+        if x < 0.0 || y < 0.0 || x as usize >= self.width || y as usize >= self.height {
+            return None;
+        }
+        Some((x as usize, y as usize))
+    }
+
+    // Bounding box of the cells in the given ranges, not counting tiles that
+    // stick out of their cell.
+    fn cells_bounds(&self, xs: (usize, usize), ys: (usize, usize)) -> Rect {
+        let corners = [
+            self.cell_origin(xs.0, ys.0),
+            self.cell_origin(xs.1, ys.0),
+            self.cell_origin(xs.0, ys.1),
+            self.cell_origin(xs.1, ys.1),
+        ];
+        let min_x = corners.iter().map(|p| p.x).fold(f32::MAX, f32::min);
+        let min_y = corners.iter().map(|p| p.y).fold(f32::MAX, f32::min);
+        let max_x = corners.iter().map(|p| p.x).fold(f32::MIN, f32::max) + self.tile_width;
+        let max_y = corners.iter().map(|p| p.y).fold(f32::MIN, f32::max) + self.tile_height;
+        Rect::new(min_x, min_y, max_x - min_x, max_y - min_y)
+    }
+}
+
+/// A grid of tiles drawn from one or more tilesets, in any number of layers.
+///
+/// The geometry of every layer is split in chunks of 16x16 tiles that are
+/// kept on the GPU, so drawing a map only re-uploads the chunks whose tiles
+/// changed and skips the chunks outside the view.
+pub struct TileMap {
+    grid: Grid,
+    tilesets: Vec<Tileset>,
+    layers: Vec<TileLayer>,
+    time: f32,
+}
+
+impl TileMap {
+
+    /// Creates an empty map of `width` x `height` tiles of the given size in pixels.
+    pub fn new(width: usize, height: usize, tile_width: f32, tile_height: f32) -> Self {
+        Self {
+            grid: Grid {
+                orientation: Orientation::Orthogonal,
+                width,
+                height,
+                tile_width,
+                tile_height,
+            },
+            tilesets: Vec::new(),
+            layers: Vec::new(),
+            time: 0.0,
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.grid.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.grid.height
+    }
+
+    pub fn tile_width(&self) -> f32 {
+        self.grid.tile_width
+    }
+
+    pub fn tile_height(&self) -> f32 {
+        self.grid.tile_height
+    }
+
+    pub fn orientation(&self) -> Orientation {
+        self.grid.orientation
+    }
+
+    pub fn set_orientation(&mut self, orientation: Orientation) {
+        if orientation != self.grid.orientation {
+            self.grid.orientation = orientation;
+            for layer in &mut self.layers {
+                layer.mark_dirty();
+            }
+        }
+    }
+
+    /// Size of the whole map in pixels.
+    pub fn pixel_size(&self) -> (f32, f32) {
+        let Grid { width, height, tile_width, tile_height, .. } = self.grid;
+        match self.grid.orientation {
+            Orientation::Orthogonal => (width as f32 * tile_width, height as f32 * tile_height),
+            Orientation::Isometric => ((width + height) as f32 * tile_width / 2.0, (width + height) as f32 * tile_height / 2.0),
+        }
+    }
+
+    /// Adds a tileset and returns the id of its first tile. Ids continue
+    /// from the previous tileset, starting at 1.
+    pub fn add_tileset(&mut self, mut tileset: Tileset) -> u32 {
+        tileset.first_id = self.tilesets.last().map_or(1, |last| last.first_id + last.tile_count);
+        let first_id = tileset.first_id;
+        self.tilesets.push(tileset);
+        for layer in &mut self.layers {
+            layer.mark_dirty();
+        }
+        first_id
+    }
+
+    pub fn tilesets(&self) -> &[Tileset] {
+        &self.tilesets
+    }
+
+    /// Adds an empty layer on top of the existing ones and returns its index.
+    pub fn add_layer(&mut self, name: &str) -> usize {
+        self.layers.push(TileLayer::new(name, self.grid.width, self.grid.height));
+        self.layers.len() - 1
+    }
+
+    pub fn layers(&self) -> &[TileLayer] {
+        &self.layers
+    }
+
+    pub fn layer(&self, index: usize) -> Option<&TileLayer> {
+        self.layers.get(index)
+    }
+
+    pub fn layer_mut(&mut self, index: usize) -> Option<&mut TileLayer> {
+        self.layers.get_mut(index)
+    }
+
+    pub fn layer_by_name(&mut self, name: &str) -> Option<&mut TileLayer> {
+        self.layers.iter_mut().find(|layer| layer.name == name)
+    }
+
+    /// Returns the cell containing `point`, given in map pixels.
+    pub fn cell_at(&self, point: Point) -> Option<(usize, usize)> {
+        self.grid.cell_at(point)
+    }
+
+    /// Returns the top left corner of the cell's bounding box, in map pixels.
+    pub fn cell_position(&self, x: usize, y: usize) -> Point {
+        self.grid.cell_origin(x, y)
+    }
+
+    /// Returns the tileset a tile id belongs to, and the tile's index in it.
+    pub fn tileset_for(&self, tile: u32) -> Option<(usize, u32)> {
+        find_tileset(&self.tilesets, tile)
+    }
+
+    /// Advances tile animations by `dt` seconds.
+    pub fn update(&mut self, dt: f32) {
+        let previous = self.time;
+        self.time += dt;
+        let changed = self.tilesets.iter().any(|tileset| {
+            tileset.animations.values().any(|a| a.frame_at(previous) != a.frame_at(self.time))
+        });
+        if !changed {
+            return;
+        }
+        for chunk in self.layers.iter_mut().flat_map(|layer| layer.chunks.iter_mut()) {
+            if chunk.animated {
+                chunk.dirty = true;
+            }
+        }
+    }
+
+    // Expects the tilemap program to be in use with its view uniforms set,
+    // except for the camera which includes the layer offset. `view` is the
+    // visible area in map pixels.
+    pub(crate) fn draw_layer(&mut self, index: usize, view: Rect, program: u32) {
+        let grid = self.grid;
+        let time = self.time;
+        let tilesets = &self.tilesets;
+        let Some(layer) = self.layers.get_mut(index) else {
+            return;
+        };
+        if !layer.visible || layer.opacity <= 0.0 {
+            return;
+        }
+
+        // Tiles larger than the grid are anchored at the bottom left of
+        // their cell, so they can reach into the neighbouring chunks.
+        let overflow = tilesets.iter().fold(Insets::uniform(0.0), |o, t| Insets::new(
+            o.left.max(-t.offset.x),
+            o.top.max(t.tile_height - grid.tile_height - t.offset.y),
+            o.right.max(t.tile_width - grid.tile_width + t.offset.x),
+            o.bottom.max(t.offset.y),
+        ));
+        let view = Rect::new(view.x - layer.offset.x, view.y - layer.offset.y, view.width, view.height);
+
+        unsafe {
+            let location = gl::GetUniformLocation(program, b"camera\0".as_ptr() as *const _);
+            gl::Uniform2f(location, view.x, view.y);
+            let tint = layer.tint;
+            gl::VertexAttrib4f(
+                2,
+                tint.r as f32 / 255.0,
+                tint.g as f32 / 255.0,
+                tint.b as f32 / 255.0,
+                tint.a as f32 / 255.0 * layer.opacity,
+            );
+        }
+
+        // Isometric chunks are drawn back to front so tall tiles overlap correctly.
+        let mut order: Vec<usize> = (0..layer.chunks.len()).collect();
+        if grid.orientation == Orientation::Isometric {
+            order.sort_by_key(|&i| (i % layer.chunks_x + i / layer.chunks_x, i % layer.chunks_x));
+        }
+
+        for i in order {
+            let (cx, cy) = (i % layer.chunks_x, i / layer.chunks_x);
+            let xs = (cx * CHUNK_SIZE, ((cx + 1) * CHUNK_SIZE).min(grid.width) - 1);
+            let ys = (cy * CHUNK_SIZE, ((cy + 1) * CHUNK_SIZE).min(grid.height) - 1);
+            let cells = grid.cells_bounds(xs, ys);
+            let bounds = Rect::new(
+                cells.x - overflow.left,
+                cells.y - overflow.top,
+                cells.width + overflow.left + overflow.right,
+                cells.height + overflow.top + overflow.bottom,
+            );
+            if !intersects(bounds, view) {
+                continue;
+            }
+            if layer.chunks[i].dirty {
+                rebuild_chunk(layer, cx, cy, &grid, tilesets, time);
+            }
+            for mesh in &layer.chunks[i].meshes {
+                unsafe {
+                    gl::BindTexture(gl::TEXTURE_2D, tilesets[mesh.tileset].texture.texture_id);
+                    gl::BindVertexArray(mesh.vao);
+                    gl::DrawArrays(gl::TRIANGLES, 0, mesh.vertex_count as GLsizei);
+                }
+            }
+        }
+        unsafe {
+            gl::BindVertexArray(0);
+        }
+    }
+}
+
+fn intersects(a: Rect, b: Rect) -> bool {
+    a.x < b.x + b.width && b.x < a.x + a.width && a.y < b.y + b.height && b.y < a.y + a.height
+}
+
+fn find_tileset(tilesets: &[Tileset], tile: u32) -> Option<(usize, u32)> {
+    let id = tile_id(tile);
+    if id == 0 {
+        return None;
+    }
+    let index = tilesets.iter().rposition(|t| t.first_id <= id)?;
+    let local = id - tilesets[index].first_id;
+    (local < tilesets[index].tile_count).then_some((index, local))
+}
+
+fn rebuild_chunk(layer: &mut TileLayer, cx: usize, cy: usize, grid: &Grid, tilesets: &[Tileset], time: f32) {
+    let mut vertices: Vec<Vec<f32>> = vec![Vec::new(); tilesets.len()];
+    let mut animated = false;
+    for y in cy * CHUNK_SIZE..((cy + 1) * CHUNK_SIZE).min(layer.height) {
+        for x in cx * CHUNK_SIZE..((cx + 1) * CHUNK_SIZE).min(layer.width) {
+            let tile = layer.tiles[y * layer.width + x];
+            let Some((index, local)) = find_tileset(tilesets, tile) else {
+                continue;
+            };
+            let tileset = &tilesets[index];
+            animated |= tileset.animations.contains_key(&local);
+            let origin = grid.cell_origin(x, y);
+            let dest = Rect::new(
+                origin.x + tileset.offset.x,
+                origin.y + grid.tile_height - tileset.tile_height + tileset.offset.y,
+                tileset.tile_width,
+                tileset.tile_height,
+            );
+            push_tile(&mut vertices[index], tileset, tileset.animated_tile(local, time), tile, dest);
+        }
+    }
+
+    let chunk = &mut layer.chunks[cy * layer.chunks_x + cx];
+    chunk.meshes.retain(|mesh| !vertices[mesh.tileset].is_empty());
+    for (tileset, vertices) in vertices.iter().enumerate() {
+        if vertices.is_empty() {
+            continue;
+        }
+        let mesh = match chunk.meshes.iter().position(|mesh| mesh.tileset == tileset) {
+            Some(i) => &mut chunk.meshes[i],
+            None => {
+                chunk.meshes.push(ChunkMesh::new(tileset));
+                chunk.meshes.last_mut().unwrap()
+            }
+        };
+        mesh.upload(vertices);
+    }
+    chunk.dirty = false;
+    chunk.animated = animated;
+}
+
+fn push_tile(vertices: &mut Vec<f32>, tileset: &Tileset, local: u32, tile: u32, dest: Rect) {
+    let src = tileset.tile_rect(local);
+    let (width, height) = (tileset.texture.width, tileset.texture.height);
+    let (u0, u1) = (src.x / width, (src.x + src.width) / width);
+    let (v0, v1) = (src.y / height, (src.y + src.height) / height);
+
+    // Texture coordinates of the top left, top right, bottom left and bottom
+    // right corners. Tiled applies the diagonal flip first.
+    let mut uv = [[u0, v0], [u1, v0], [u0, v1], [u1, v1]];
+    if tile & TILE_FLIP_DIAGONAL != 0 {
+        uv.swap(1, 2);
+    }
+    if tile & TILE_FLIP_HORIZONTAL != 0 {
+        uv.swap(0, 1);
+        uv.swap(2, 3);
+    }
+    if tile & TILE_FLIP_VERTICAL != 0 {
+        uv.swap(0, 2);
+        uv.swap(1, 3);
+    }
+
+    let (x0, x1) = (dest.x, dest.x + dest.width);
+    let (y0, y1) = (dest.y, dest.y + dest.height);
+    let corners = [[x0, y0], [x1, y0], [x0, y1], [x1, y1]];
+    vertices.reserve(FLOATS_PER_TILE);
+    for corner in [0, 1, 3, 0, 3, 2] {
+        vertices.extend_from_slice(&corners[corner]);
+        vertices.extend_from_slice(&uv[corner]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn grid(orientation: Orientation) -> Grid {
+        Grid { orientation, width: 5, height: 4, tile_width: 32.0, tile_height: 16.0 }
+    }
+
+    // Tilesets of 16x16 tiles, numbered as a map would number them
+    fn tilesets(columns: &[u32]) -> Vec<Tileset> {
+        let mut map = TileMap::new(1, 1, 16.0, 16.0);
+        for &columns in columns {
+            map.add_tileset(Tileset::new(Texture::headless(columns as f32 * 16.0, 32.0), 16.0, 16.0, 0.0, 0.0));
+        }
+        map.tilesets
+    }
+
+    // Texture coordinates of the top left, top right, bottom left and bottom
+    // right corners of a tile from a 32x16 texture
+    fn corner_uvs(tile: u32) -> [[f32; 2]; 4] {
+        let tileset = Tileset::new(Texture::headless(32.0, 16.0), 16.0, 16.0, 0.0, 0.0);
+        let mut vertices = Vec::new();
+        push_tile(&mut vertices, &tileset, 0, tile, Rect::new(0.0, 0.0, 16.0, 16.0));
+        let uv = |vertex: usize| [vertices[vertex * 4 + 2], vertices[vertex * 4 + 3]];
+        [uv(0), uv(1), uv(5), uv(2)]
+    }
+
+    #[test]
+    fn cells_round_trip_through_their_centers() {
+        for orientation in [Orientation::Orthogonal, Orientation::Isometric] {
+            let grid = grid(orientation);
+            for y in 0..grid.height {
+                for x in 0..grid.width {
+                    let origin = grid.cell_origin(x, y);
+                    let center = Point::new(origin.x + grid.tile_width / 2.0, origin.y + grid.tile_height / 2.0);
+                    assert_eq!(grid.cell_at(center), Some((x, y)), "{:?}", orientation);
+                }
+            }
+            assert_eq!(grid.cell_at(Point::new(-1.0, -1.0)), None);
+        }
+        assert_eq!(grid(Orientation::Orthogonal).cell_at(Point::new(160.0, 0.0)), None);
+    }
+
+    #[test]
+    fn tile_ids_map_to_their_tileset() {
+        // Ids 1 to 4, then 5 to 10
+        let tilesets = tilesets(&[2, 3]);
+        assert_eq!(find_tileset(&tilesets, 0), None);
+        assert_eq!(find_tileset(&tilesets, 1), Some((0, 0)));
+        assert_eq!(find_tileset(&tilesets, 4), Some((0, 3)));
+        assert_eq!(find_tileset(&tilesets, 5), Some((1, 0)));
+        assert_eq!(find_tileset(&tilesets, 10), Some((1, 5)));
+        assert_eq!(find_tileset(&tilesets, 11), None);
+        assert_eq!(find_tileset(&tilesets, 6 | TILE_FLIP_HORIZONTAL | TILE_FLIP_DIAGONAL), Some((1, 1)));
+        assert_eq!(find_tileset(&tilesets, TILE_FLIP_VERTICAL), None);
+    }
+
+    #[test]
+    fn flips_swap_texture_corners() {
+        let (tl, tr, bl, br) = ([0.0, 0.0], [0.5, 0.0], [0.0, 1.0], [0.5, 1.0]);
+        assert_eq!(corner_uvs(1), [tl, tr, bl, br]);
+        assert_eq!(corner_uvs(1 | TILE_FLIP_HORIZONTAL), [tr, tl, br, bl]);
+        assert_eq!(corner_uvs(1 | TILE_FLIP_VERTICAL), [bl, br, tl, tr]);
+        assert_eq!(corner_uvs(1 | TILE_FLIP_DIAGONAL), [tl, bl, tr, br]);
+        // Rotated clockwise
+        assert_eq!(corner_uvs(1 | TILE_FLIP_DIAGONAL | TILE_FLIP_HORIZONTAL), [bl, tl, br, tr]);
+    }
+
+    #[test]
+    fn animations_wrap_around() {
+        let animation = TileAnimation { frames: vec![(3, 0.5), (4, 0.25)], total: 0.75 };
+        let frames: Vec<usize> = [0.0, 0.6, 0.75, 1.0, 1.3].iter().map(|&t| animation.frame_at(t)).collect();
+        assert_eq!(frames, [0, 1, 0, 0, 1]);
+        assert_eq!(TileAnimation { frames: vec![(3, 0.0)], total: 0.0 }.frame_at(1.0), 0);
+    }
+
+    #[test]
+    fn setting_a_tile_dirties_only_its_chunk() {
+        // 3x2 chunks
+        let mut layer = TileLayer::new("ground", 40, 20);
+        let dirty = |layer: &TileLayer| layer.chunks.iter().map(|chunk| chunk.dirty).collect::<Vec<_>>();
+        let clean = |layer: &mut TileLayer| layer.chunks.iter_mut().for_each(|chunk| chunk.dirty = false);
+
+        clean(&mut layer);
+        layer.set_tile(17, 3, 5);
+        assert_eq!(dirty(&layer), [false, true, false, false, false, false]);
+        assert_eq!(layer.tile(17, 3), 5);
+
+        clean(&mut layer);
+        layer.set_tile(17, 3, 5);
+        layer.set_tile(40, 0, 5);
+        assert_eq!(dirty(&layer), [false; 6]);
+
+        layer.set_tile(39, 19, 1);
+        assert_eq!(dirty(&layer), [false, false, false, false, false, true]);
+    }
+}
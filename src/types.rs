@@ -1,6 +1,6 @@
 use std::ops;
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub struct Point {
     pub x: f32,
     pub y: f32,
@@ -96,7 +96,30 @@ impl Rect {
     }
 }
 
-#[derive(Copy, Clone, Hash, PartialEq)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Insets {
+    pub left: f32,
+    pub top: f32,
+    pub right: f32,
+    pub bottom: f32,
+}
+
+impl Insets {
+    pub fn new(left: f32, top: f32, right: f32, bottom: f32) -> Self {
+        Self {
+            left,
+            top,
+            right,
+            bottom,
+        }
+    }
+
+    pub fn uniform(inset: f32) -> Self {
+        Self::new(inset, inset, inset, inset)
+    }
+}
+
+#[derive(Debug, Copy, Clone, Hash, PartialEq)]
 pub struct Color {
     pub r: u8,
     pub g: u8,